@@ -0,0 +1,134 @@
+use crate::errors::SyncError;
+use crate::locations::{cache_dir, HashAlgorithm};
+use crate::sync_logic::conflicts::{ConflictPolicies, ConflictPolicy};
+use crate::sync_logic::debounce::DEFAULT_QUIET_PERIOD;
+use crate::sync_logic::DEFAULT_MTIME_TOLERANCE;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub struct Config {
+    pub locations: Vec<String>,
+    pub state_path: PathBuf,
+    // `--conflict <politica>` si `--conflict <i>,<j>=<politica>` pentru o pereche
+    pub conflict_policies: ConflictPolicies,
+    pub list_conflicts: bool,
+    // o singura trecere de sincronizare, fara watcher
+    pub once: bool,
+    // `--resolve <id>[:<locatie>]`
+    pub resolve: Option<(i64, Option<usize>)>,
+    // `--debounce <ms>`: cata liniste asteptam dupa ultimul eveniment al unui fisier
+    pub debounce: Duration,
+    // `--mtime-tolerance <s>`: timpi mai apropiati de atat inseamna acelasi fisier
+    pub mtime_tolerance: Duration,
+    // `--hash <algoritm>`: cum calculam hash-ul continutului in locatiile fara `?hash=`
+    pub hash: HashAlgorithm,
+}
+
+pub fn parse_args(args: &[String]) -> Result<Config, SyncError> {
+    let mut locations = Vec::new();
+    let mut state_path = None;
+    let mut conflict_policies = ConflictPolicies::default();
+    let mut list_conflicts = false;
+    let mut once = false;
+    let mut resolve = None;
+    let mut debounce = DEFAULT_QUIET_PERIOD;
+    let mut mtime_tolerance = DEFAULT_MTIME_TOLERANCE;
+    let mut hash = HashAlgorithm::default();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        // optiuni fara valoare
+        if arg == "--list-conflicts" {
+            list_conflicts = true;
+            continue;
+        }
+        if arg == "--once" {
+            once = true;
+            continue;
+        }
+
+        if let Some(flag) = arg.strip_prefix("--") {
+            // acceptam atat `--flag=valoare` cat si `--flag valoare`
+            let (name, value) = match flag.split_once('=') {
+                Some((name, value)) => (name, value.to_string()),
+                None => {
+                    let value = iter
+                        .next()
+                        .ok_or_else(|| SyncError::Parse(format!("Missing value for --{}", flag)))?;
+                    (flag, value.clone())
+                }
+            };
+
+            match name {
+                "state" => state_path = Some(PathBuf::from(value)),
+                "conflict" if value.contains('=') => conflict_policies.add_pair(&value)?,
+                "conflict" => conflict_policies.default = ConflictPolicy::parse(&value)?,
+                "hash" => hash = HashAlgorithm::parse(&value)?,
+                "resolve" => resolve = Some(parse_resolve(&value)?),
+                "debounce" => {
+                    let ms = value.parse().map_err(|_| {
+                        SyncError::Parse(format!("Invalid --debounce value: {}", value))
+                    })?;
+                    debounce = Duration::from_millis(ms);
+                }
+                "mtime-tolerance" => {
+                    let secs = value.parse().map_err(|_| {
+                        SyncError::Parse(format!("Invalid --mtime-tolerance value: {}", value))
+                    })?;
+                    mtime_tolerance = Duration::from_secs(secs);
+                }
+                _ => return Err(SyncError::Parse(format!("Unknown option: --{}", name))),
+            }
+        } else {
+            locations.push(arg.clone());
+        }
+    }
+
+    if let Some(n) = conflict_policies.max_location() {
+        if n >= locations.len() {
+            return Err(SyncError::Parse(format!(
+                "--conflict names location #{}, but only {} locations were given",
+                n,
+                locations.len()
+            )));
+        }
+    }
+    let state_path = state_path.unwrap_or_else(|| default_state_path(&locations));
+
+    Ok(Config {
+        locations,
+        state_path,
+        conflict_policies,
+        list_conflicts,
+        once,
+        resolve,
+        debounce,
+        mtime_tolerance,
+        hash,
+    })
+}
+
+fn parse_resolve(value: &str) -> Result<(i64, Option<usize>), SyncError> {
+    let invalid = || SyncError::Parse(format!("Invalid --resolve value: {}", value));
+    match value.split_once(':') {
+        Some((id, loc)) => Ok((
+            id.parse().map_err(|_| invalid())?,
+            Some(loc.parse().map_err(|_| invalid())?),
+        )),
+        None => Ok((value.parse().map_err(|_| invalid())?, None)),
+    }
+}
+
+// Fiecare set de locatii are propria baza de date, ca sa nu se amestece starile. Sta
+// langa cache-ul de hash-uri; o baza mai veche din directorul curent ramane in uz.
+fn default_state_path(locations: &[String]) -> PathBuf {
+    let mut hasher = Sha256::new();
+    hasher.update(locations.join("\n"));
+    let id = &hex::encode(hasher.finalize())[..12];
+    let legacy = PathBuf::from(format!(".advanced_rsync-{}.db", id));
+    match cache_dir() {
+        Some(dir) if !legacy.exists() => dir.join(format!("state-{}.db", id)),
+        _ => legacy,
+    }
+}
@@ -0,0 +1,439 @@
+// Transfer delta in stilul rsync: destinatia trimite semnaturile blocurilor din copia ei,
+// sursa cauta acele blocuri (checksum rulant + hash puternic) si trimite doar ce difera.
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const MIN_BLOCK_SIZE: usize = 700;
+const MAX_BLOCK_SIZE: usize = 128 * 1024;
+const READ_CHUNK: usize = 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: Vec<u8>,
+    pub len: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Signature {
+    pub block_size: usize,
+    pub blocks: Vec<BlockSignature>,
+}
+
+#[derive(Debug, Clone)]
+pub enum DeltaOp {
+    // blocul cu indexul dat din copia veche
+    Copy(usize),
+    Literal(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Delta {
+    pub block_size: usize,
+    pub ops: Vec<DeltaOp>,
+    // hash-ul fisierului complet, verificat dupa reconstructie
+    pub checksum: Vec<u8>,
+}
+
+impl Delta {
+    pub fn literal_bytes(&self) -> usize {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                DeltaOp::Literal(data) => data.len(),
+                DeltaOp::Copy(_) => 0,
+            })
+            .sum()
+    }
+
+    pub fn copied_blocks(&self) -> usize {
+        self.ops
+            .iter()
+            .filter(|op| matches!(op, DeltaOp::Copy(_)))
+            .count()
+    }
+}
+
+// Ca rsync: aproximativ radical din dimensiune, intre niste limite
+pub fn block_size_for(len: u64) -> usize {
+    ((len as f64).sqrt() as usize).clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE)
+}
+
+// Checksum-ul rulant din rsync: a = suma octetilor, b = suma ponderata, ambele mod 2^16
+#[derive(Debug, Clone, Copy)]
+struct RollingChecksum {
+    a: u32,
+    b: u32,
+    len: usize,
+}
+
+impl RollingChecksum {
+    fn new(block: &[u8]) -> Self {
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        let len = block.len();
+        for (i, &byte) in block.iter().enumerate() {
+            a = a.wrapping_add(byte as u32);
+            b = b.wrapping_add(((len - i) as u32).wrapping_mul(byte as u32));
+        }
+        RollingChecksum { a, b, len }
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | ((self.b & 0xffff) << 16)
+    }
+
+    // fereastra avanseaza un octet: iese `old`, intra `new`
+    fn roll(&mut self, old: u8, new: u8) {
+        self.a = self.a.wrapping_sub(old as u32).wrapping_add(new as u32);
+        self.b = self
+            .b
+            .wrapping_sub((self.len as u32).wrapping_mul(old as u32))
+            .wrapping_add(self.a);
+    }
+}
+
+fn weak_checksum(block: &[u8]) -> u32 {
+    RollingChecksum::new(block).digest()
+}
+
+fn strong_checksum(data: &[u8]) -> Vec<u8> {
+    Sha256::digest(data).to_vec()
+}
+
+pub fn signature<R: Read>(mut reader: R, block_size: usize) -> std::io::Result<Signature> {
+    let mut blocks = Vec::new();
+    let mut buffer = vec![0u8; block_size];
+    loop {
+        let len = read_full(&mut reader, &mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        let block = &buffer[..len];
+        blocks.push(BlockSignature {
+            weak: weak_checksum(block),
+            strong: strong_checksum(block),
+            len,
+        });
+        if len < block_size {
+            break;
+        }
+    }
+    Ok(Signature { block_size, blocks })
+}
+
+// Parcurgem noua versiune in flux, cu o fereastra de un bloc. Intoarce None daca
+// diferentele depasesc `max_literal` octeti (mai bine trimitem fisierul intreg).
+pub fn compute_delta<R: Read>(
+    signature: &Signature,
+    mut reader: R,
+    max_literal: usize,
+) -> std::io::Result<Option<Delta>> {
+    let block_size = signature.block_size;
+    let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in signature.blocks.iter().enumerate() {
+        by_weak.entry(block.weak).or_default().push(i);
+    }
+
+    let find_block = |window: &[u8], weak: u32| -> Option<usize> {
+        let candidates = by_weak.get(&weak)?;
+        let strong = strong_checksum(window);
+        candidates.iter().copied().find(|&i| {
+            let block = &signature.blocks[i];
+            block.len == window.len() && block.strong == strong
+        })
+    };
+
+    // doar ultimul bloc din copia veche poate fi mai scurt
+    let short_len = signature
+        .blocks
+        .last()
+        .map(|b| b.len)
+        .filter(|&len| len < block_size);
+
+    let mut hasher = Sha256::new();
+    let mut ops = Vec::new();
+    let mut literal = Vec::new();
+    let mut literal_total = 0;
+    let mut rolling: Option<RollingChecksum> = None;
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut chunk = vec![0u8; READ_CHUNK];
+    let mut pos = 0;
+    let mut eof = false;
+
+    loop {
+        // Tinem in buffer cel putin o fereastra completa (plus octetul urmator)
+        while !eof && buf.len() - pos <= block_size {
+            if pos >= READ_CHUNK {
+                buf.drain(..pos);
+                pos = 0;
+            }
+            let n = reader.read(&mut chunk)?;
+            if n == 0 {
+                eof = true;
+            } else {
+                hasher.update(&chunk[..n]);
+                buf.extend_from_slice(&chunk[..n]);
+            }
+        }
+        if pos >= buf.len() {
+            break;
+        }
+
+        let end = (pos + block_size).min(buf.len());
+        let window = &buf[pos..end];
+
+        // La coada fisierului fereastra e incompleta: se poate potrivi cel mult cu ultimul bloc
+        let can_match = window.len() == block_size || short_len == Some(window.len());
+        if can_match {
+            // dupa o potrivire (sau la final) recalculam checksum-ul de la zero
+            let weak = match rolling {
+                Some(r) if r.len == window.len() => r,
+                _ => RollingChecksum::new(window),
+            };
+
+            if let Some(index) = find_block(window, weak.digest()) {
+                if !literal.is_empty() {
+                    ops.push(DeltaOp::Literal(std::mem::take(&mut literal)));
+                }
+                ops.push(DeltaOp::Copy(index));
+                pos = end;
+                rolling = None;
+                continue;
+            }
+
+            rolling = if end < buf.len() {
+                let mut next = weak;
+                next.roll(buf[pos], buf[end]);
+                Some(next)
+            } else {
+                None
+            };
+        } else {
+            rolling = None;
+        }
+
+        literal.push(buf[pos]);
+        literal_total += 1;
+        if literal_total > max_literal {
+            return Ok(None);
+        }
+        pos += 1;
+    }
+
+    if !literal.is_empty() {
+        ops.push(DeltaOp::Literal(literal));
+    }
+
+    Ok(Some(Delta {
+        block_size,
+        ops,
+        checksum: hasher.finalize().to_vec(),
+    }))
+}
+
+// Reconstruim fisierul nou din copia veche plus delta. Intoarce false daca rezultatul
+// nu are hash-ul asteptat (copia veche s-a schimbat intre timp).
+pub fn apply_delta<R: Read + Seek, W: Write>(
+    mut old: R,
+    delta: &Delta,
+    mut out: W,
+) -> std::io::Result<bool> {
+    let mut hasher = Sha256::new();
+    let mut block = vec![0u8; delta.block_size];
+
+    for op in &delta.ops {
+        match op {
+            DeltaOp::Copy(index) => {
+                old.seek(SeekFrom::Start((*index * delta.block_size) as u64))?;
+                let len = read_full(&mut old, &mut block)?;
+                hasher.update(&block[..len]);
+                out.write_all(&block[..len])?;
+            }
+            DeltaOp::Literal(data) => {
+                hasher.update(data);
+                out.write_all(data)?;
+            }
+        }
+    }
+    out.flush()?;
+
+    Ok(hasher.finalize().as_slice() == delta.checksum.as_slice())
+}
+
+fn read_full<R: Read>(reader: &mut R, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Date pseudo-aleatoare, ca blocurile sa nu semene intre ele
+    fn data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    // Delta de la `old` la `new`, aplicata peste `old`; intoarce delta si rezultatul
+    fn round_trip(old: &[u8], new: &[u8], block_size: usize) -> (Delta, Vec<u8>) {
+        let signature = signature(Cursor::new(old), block_size).unwrap();
+        let delta = compute_delta(&signature, Cursor::new(new), usize::MAX)
+            .unwrap()
+            .unwrap();
+        let mut out = Vec::new();
+        assert!(apply_delta(Cursor::new(old), &delta, &mut out).unwrap());
+        assert_eq!(out, new);
+        (delta, out)
+    }
+
+    #[test]
+    fn rolling_checksum_matches_a_fresh_one() {
+        let bytes = data(4096, 1);
+        let window = 700;
+        let mut rolling = RollingChecksum::new(&bytes[..window]);
+        for start in 1..=bytes.len() - window {
+            rolling.roll(bytes[start - 1], bytes[start + window - 1]);
+            assert_eq!(
+                rolling.digest(),
+                weak_checksum(&bytes[start..start + window]),
+                "window starting at {}",
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn block_size_stays_within_limits() {
+        assert_eq!(block_size_for(0), MIN_BLOCK_SIZE);
+        assert_eq!(block_size_for(1_000_000), 1000);
+        assert_eq!(block_size_for(u64::MAX), MAX_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn identical_file_is_all_copies() {
+        let old = data(10 * 700, 2);
+        let (delta, _) = round_trip(&old, &old, 700);
+        assert_eq!(delta.copied_blocks(), 10);
+        assert_eq!(delta.literal_bytes(), 0);
+    }
+
+    #[test]
+    fn insert_delete_and_append() {
+        let block = 700;
+        let old = data(20 * block, 3);
+
+        // inserat la mijloc, nealiniat la bloc
+        let mut inserted = old.clone();
+        inserted.splice(
+            5 * block + 123..5 * block + 123,
+            b"inserted text".iter().copied(),
+        );
+        let (delta, _) = round_trip(&old, &inserted, block);
+        assert_eq!(delta.copied_blocks(), 19);
+        assert_eq!(delta.literal_bytes(), block + 13);
+
+        // sters din mijloc
+        let mut deleted = old.clone();
+        deleted.drain(8 * block + 10..8 * block + 60);
+        let (delta, _) = round_trip(&old, &deleted, block);
+        assert_eq!(delta.copied_blocks(), 19);
+        assert_eq!(delta.literal_bytes(), block - 50);
+
+        // adaugat la final
+        let mut appended = old.clone();
+        appended.extend_from_slice(&data(300, 4));
+        let (delta, _) = round_trip(&old, &appended, block);
+        assert_eq!(delta.copied_blocks(), 20);
+        assert_eq!(delta.literal_bytes(), 300);
+    }
+
+    #[test]
+    fn empty_old_or_new_file() {
+        let bytes = data(2000, 5);
+
+        let (delta, _) = round_trip(&[], &bytes, 700);
+        assert_eq!(delta.copied_blocks(), 0);
+        assert_eq!(delta.literal_bytes(), 2000);
+
+        let (delta, _) = round_trip(&bytes, &[], 700);
+        assert!(delta.ops.is_empty());
+
+        let (delta, _) = round_trip(&[], &[], 700);
+        assert!(delta.ops.is_empty());
+    }
+
+    #[test]
+    fn short_final_block_is_reused() {
+        let block = 700;
+        // ultimul bloc are doar 250 de octeti
+        let old = data(3 * block + 250, 6);
+        let mut new = b"prefix".to_vec();
+        new.extend_from_slice(&old);
+        let (delta, _) = round_trip(&old, &new, block);
+        assert_eq!(delta.copied_blocks(), 4);
+        assert_eq!(delta.literal_bytes(), 6);
+        assert!(matches!(delta.ops.last(), Some(DeltaOp::Copy(3))));
+    }
+
+    // Mai mult decat un READ_CHUNK, ca bufferul sa fie golit din mers
+    #[test]
+    fn large_file_across_read_chunks() {
+        let block = 2048;
+        let old = data(3 * READ_CHUNK, 7);
+        let mut new = old.clone();
+        new[READ_CHUNK + 5] ^= 0xff;
+        // inserat la granita unui bloc: doar octetii noi sunt literali
+        new.splice(2 * READ_CHUNK..2 * READ_CHUNK, data(100, 8));
+        let (delta, _) = round_trip(&old, &new, block);
+        assert_eq!(delta.literal_bytes(), block + 100);
+    }
+
+    #[test]
+    fn too_many_literal_bytes_gives_up() {
+        let old = data(10 * 700, 9);
+        let new = data(10 * 700, 10);
+        let signature = signature(Cursor::new(&old), 700).unwrap();
+        assert!(compute_delta(&signature, Cursor::new(&new), 1000)
+            .unwrap()
+            .is_none());
+        // exact la limita inca merge
+        let delta = compute_delta(&signature, Cursor::new(&new), new.len())
+            .unwrap()
+            .unwrap();
+        assert_eq!(delta.literal_bytes(), new.len());
+    }
+
+    #[test]
+    fn changed_old_copy_fails_verification() {
+        let old = data(5 * 700, 11);
+        let mut new = old.clone();
+        new.extend_from_slice(b"tail");
+        let signature = signature(Cursor::new(&old), 700).unwrap();
+        let delta = compute_delta(&signature, Cursor::new(&new), usize::MAX)
+            .unwrap()
+            .unwrap();
+
+        let mut changed = old.clone();
+        changed[10] ^= 0xff;
+        let mut out = Vec::new();
+        assert!(!apply_delta(Cursor::new(&changed), &delta, &mut out).unwrap());
+    }
+}
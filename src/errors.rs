@@ -1,13 +1,234 @@
+use std::error::Error;
+use std::fmt;
+use std::io::ErrorKind;
+
+type Source = Box<dyn Error + Send + Sync>;
+
+// Unde a aparut o eroare: locatia, calea relativa si operatia, plus cauza originala
+#[derive(Debug, Default)]
+pub struct ErrorContext {
+    pub location: Option<String>,
+    pub path: Option<String>,
+    pub operation: Option<String>,
+    pub message: String,
+    pub source: Option<Source>,
+}
+
+impl ErrorContext {
+    pub fn new(message: impl Into<String>) -> Self {
+        ErrorContext {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_source(mut self, source: impl Into<Source>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
 #[derive(Debug)]
-#[allow(dead_code)]
 pub enum SyncError {
-    Io(std::io::Error),
-    Ftp(String),
+    NotFound(Box<ErrorContext>),
+    PermissionDenied(Box<ErrorContext>),
+    // destinatia exista deja sau s-a schimbat intre timp
+    Conflict(Box<ErrorContext>),
+    ReadOnlyLocation(Box<ErrorContext>),
+    // raspuns neasteptat de la server sau date invalide (ex. o arhiva stricata);
+    // `transient` pentru raspunsurile care pot trece singure (FTP 4xx)
+    ProtocolError {
+        context: Box<ErrorContext>,
+        transient: bool,
+    },
+    Timeout(Box<ErrorContext>),
+    // conexiune refuzata, intrerupta sau inchisa de server
+    Connection(Box<ErrorContext>),
+    // locatia a esuat de prea multe ori la rand si e lasata in pace o vreme
+    Unavailable(Box<ErrorContext>),
+    Io(Box<ErrorContext>),
+    // argumente sau sintaxa unei locatii
     Parse(String),
+    State(Box<ErrorContext>),
+}
+
+impl SyncError {
+    pub fn not_found(message: impl Into<String>) -> Self {
+        SyncError::NotFound(ErrorContext::new(message).into())
+    }
+
+    pub fn permission_denied(message: impl Into<String>) -> Self {
+        SyncError::PermissionDenied(ErrorContext::new(message).into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        SyncError::Conflict(ErrorContext::new(message).into())
+    }
+
+    pub fn read_only(message: impl Into<String>) -> Self {
+        SyncError::ReadOnlyLocation(ErrorContext::new(message).into())
+    }
+
+    pub fn protocol(message: impl Into<String>) -> Self {
+        SyncError::ProtocolError {
+            context: ErrorContext::new(message).into(),
+            transient: false,
+        }
+    }
+
+    pub fn unavailable(message: impl Into<String>) -> Self {
+        SyncError::Unavailable(ErrorContext::new(message).into())
+    }
+
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            SyncError::NotFound(c)
+            | SyncError::PermissionDenied(c)
+            | SyncError::Conflict(c)
+            | SyncError::ReadOnlyLocation(c)
+            | SyncError::ProtocolError { context: c, .. }
+            | SyncError::Timeout(c)
+            | SyncError::Connection(c)
+            | SyncError::Unavailable(c)
+            | SyncError::Io(c)
+            | SyncError::State(c) => Some(c),
+            SyncError::Parse(_) => None,
+        }
+    }
+
+    fn context_mut(&mut self) -> Option<&mut ErrorContext> {
+        match self {
+            SyncError::NotFound(c)
+            | SyncError::PermissionDenied(c)
+            | SyncError::Conflict(c)
+            | SyncError::ReadOnlyLocation(c)
+            | SyncError::ProtocolError { context: c, .. }
+            | SyncError::Timeout(c)
+            | SyncError::Connection(c)
+            | SyncError::Unavailable(c)
+            | SyncError::Io(c)
+            | SyncError::State(c) => Some(c),
+            SyncError::Parse(_) => None,
+        }
+    }
+
+    // Contextul se completeaza din interior spre exterior: ce a pus deja
+    // nivelul de mai jos (mai precis) nu se suprascrie
+    pub fn at(mut self, path: &str) -> Self {
+        if let Some(c) = self.context_mut() {
+            c.path.get_or_insert_with(|| path.to_string());
+        }
+        self
+    }
+
+    pub fn during(mut self, operation: &str) -> Self {
+        if let Some(c) = self.context_mut() {
+            c.operation.get_or_insert_with(|| operation.to_string());
+        }
+        self
+    }
+
+    pub fn in_location(mut self, location: &str) -> Self {
+        if let Some(c) = self.context_mut() {
+            c.location.get_or_insert_with(|| location.to_string());
+        }
+        self
+    }
+
+    // Erori care pot trece singure (conexiune pierduta, timeout, server ocupat),
+    // spre deosebire de cele permanente (fisier lipsa, permisiuni, configuratie gresita)
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SyncError::Timeout(_) | SyncError::Connection(_) | SyncError::Unavailable(_) => true,
+            SyncError::ProtocolError { transient, .. } => *transient,
+            SyncError::NotFound(_)
+            | SyncError::PermissionDenied(_)
+            | SyncError::Conflict(_)
+            | SyncError::ReadOnlyLocation(_)
+            | SyncError::Io(_)
+            | SyncError::Parse(_)
+            | SyncError::State(_) => false,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            SyncError::NotFound(_) => "not found",
+            SyncError::PermissionDenied(_) => "permission denied",
+            SyncError::Conflict(_) => "conflict",
+            SyncError::ReadOnlyLocation(_) => "read-only location",
+            SyncError::ProtocolError { .. } => "protocol error",
+            SyncError::Timeout(_) => "timed out",
+            SyncError::Connection(_) => "connection error",
+            SyncError::Unavailable(_) => "location unavailable",
+            SyncError::Io(_) => "I/O error",
+            SyncError::Parse(_) => "invalid argument",
+            SyncError::State(_) => "sync state error",
+        }
+    }
+}
+
+// `not found (read `docs/a.txt` in ftp:host/docs): 550 No such file`
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = match self {
+            SyncError::Parse(message) => return write!(f, "{}: {}", self.kind(), message),
+            _ => self.context().expect("every other variant has a context"),
+        };
+
+        write!(f, "{}", self.kind())?;
+        let mut details = Vec::new();
+        match (&context.operation, &context.path) {
+            (Some(op), Some(path)) => details.push(format!("{} `{}`", op, path)),
+            (Some(op), None) => details.push(op.clone()),
+            (None, Some(path)) => details.push(format!("`{}`", path)),
+            (None, None) => {}
+        }
+        if let Some(location) = &context.location {
+            details.push(format!("in {}", location));
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(" "))?;
+        }
+        write!(f, ": {}", context.message)
+    }
+}
+
+impl Error for SyncError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.context()?
+            .source
+            .as_deref()
+            .map(|s| s as &(dyn Error + 'static))
+    }
 }
 
 impl From<std::io::Error> for SyncError {
     fn from(err: std::io::Error) -> Self {
-        SyncError::Io(err)
+        let context = ErrorContext::new(err.to_string());
+        match err.kind() {
+            ErrorKind::NotFound => SyncError::NotFound(context.with_source(err).into()),
+            ErrorKind::PermissionDenied | ErrorKind::ReadOnlyFilesystem => {
+                SyncError::PermissionDenied(context.with_source(err).into())
+            }
+            ErrorKind::AlreadyExists => SyncError::Conflict(context.with_source(err).into()),
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => {
+                SyncError::Timeout(context.with_source(err).into())
+            }
+            ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NotConnected
+            | ErrorKind::BrokenPipe
+            | ErrorKind::UnexpectedEof
+            | ErrorKind::Interrupted => SyncError::Connection(context.with_source(err).into()),
+            _ => SyncError::Io(context.with_source(err).into()),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for SyncError {
+    fn from(err: rusqlite::Error) -> Self {
+        SyncError::State(ErrorContext::new(err.to_string()).with_source(err).into())
     }
 }
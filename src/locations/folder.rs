@@ -1,68 +1,173 @@
-use super::{DirMetadata, FileMetadata, Location};
+use super::hash_cache::{FileKey, HashCache};
+use super::{
+    is_transfer_temp, Capabilities, DirMetadata, FileMetadata, HashAlgorithm, Location, WriteStream,
+};
+use crate::delta::{self, Delta, Signature};
 use crate::errors::SyncError;
-use sha2::{Digest, Sha256};
-use std::any::Any;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime};
+use std::collections::HashSet;
+use std::fs::{File, Metadata};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use walkdir::WalkDir;
 
 pub struct FolderLocation {
     pub path: PathBuf,
+    pub hash: HashAlgorithm,
+    // hash-urile fisierelor neschimbate de la rularea trecuta
+    hashes: Option<HashCache>,
 }
 
 impl FolderLocation {
     pub fn new(path: &str) -> Self {
         FolderLocation {
             path: PathBuf::from(path),
+            hash: HashAlgorithm::default(),
+            hashes: None,
         }
     }
+
+    // Fara cache (baza nu se poate deschide) doar recitim fisierele la fiecare listing
+    pub fn with_hash_cache(mut self, db: &Path) -> Self {
+        match HashCache::open(db, &self.path) {
+            Ok(cache) => self.hashes = Some(cache),
+            Err(e) => println!(
+                "Hash cache {} unavailable, every listing will rehash {}: {}",
+                db.display(),
+                self.path.display(),
+                e
+            ),
+        }
+        self
+    }
+
+    // Metadatele unui fisier; hash-ul vine din cache daca fisierul nu s-a schimbat,
+    // altfel il calculam si il adaugam in `hashed`
+    fn describe(
+        &self,
+        relative_path: String,
+        full_path: &Path,
+        metadata: &Metadata,
+        hashed: &mut Vec<(String, FileKey, String)>,
+    ) -> FileMetadata {
+        let key = FileKey::of(metadata);
+        // un hash din cache calculat cu alt algoritm nu ne foloseste
+        let cached = self
+            .hashes
+            .as_ref()
+            .and_then(|cache| cache.get(&relative_path, key))
+            .filter(|hash| HashAlgorithm::of(hash) == Some(self.hash));
+        let hash = cached.or_else(|| {
+            let hash = self.hash.hash_file(full_path)?;
+            hashed.push((relative_path.clone(), key, hash.clone()));
+            Some(hash)
+        });
+        FileMetadata {
+            path: relative_path,
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+            size: metadata.len(),
+            hash,
+        }
+    }
+
+    fn remember(&self, hashed: &[(String, FileKey, String)], listed: Option<&HashSet<String>>) {
+        if let Some(cache) = &self.hashes {
+            if let Err(e) = cache.update(hashed, listed) {
+                println!("Could not update the hash cache: {}", e);
+            }
+        }
+    }
+}
+
+struct FolderWriteStream {
+    writer: BufWriter<File>,
+    tmp_path: PathBuf,
+    final_path: PathBuf,
+    // mutat peste cel final; altfel `Drop` sterge fisierul temporar
+    finished: bool,
+}
+
+impl Write for FolderWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
 }
 
-fn calculate_file_hash(path: &std::path::Path) -> Option<String> {
-    let mut file = File::open(path).ok()?;
-    let mut hasher = Sha256::new();
-    let mut buffer = Vec::new();
-    file.read_to_end(&mut buffer).ok()?;
-    hasher.update(buffer);
-    Some(hex::encode(hasher.finalize()))
+impl WriteStream for FolderWriteStream {
+    fn finish(mut self: Box<Self>) -> Result<(), SyncError> {
+        self.writer.flush()?;
+        std::fs::rename(&self.tmp_path, &self.final_path)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+// O copiere abandonata (sursa n-a mai putut fi citita) nu lasa `.nume.part` in urma
+impl Drop for FolderWriteStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = std::fs::remove_file(&self.tmp_path);
+        }
+    }
 }
 
 impl Location for FolderLocation {
-    fn as_any(&self) -> &dyn Any {
-        self
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            writable: true,
+            set_mtime: true,
+            hash: self.hash,
+            native_hashes: vec![],
+            watch_root: Some(self.path.clone()),
+            rename: true,
+            // sistemele de fisiere implicite de pe Windows si macOS nu tin cont de majuscule
+            case_sensitive: cfg!(not(any(windows, target_os = "macos"))),
+            permissions: cfg!(unix),
+        }
     }
+
     fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
         let mut results = Vec::new();
+        let mut hashed = Vec::new();
 
         for entry in std::fs::read_dir(&self.path)? {
             let entry = entry?;
             let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
             let relative_path = entry
                 .path()
                 .strip_prefix(&self.path)
                 .map(|p| p.to_string_lossy().to_string())
                 .unwrap_or_else(|_| entry.path().to_string_lossy().to_string());
 
-            let hash = calculate_file_hash(&entry.path());
-
-            let modified_time = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-            let adjusted_time = modified_time
-                .checked_add(Duration::from_secs(2 * 3600))
-                .unwrap_or(modified_time);
-
-            results.push(FileMetadata {
-                path: relative_path,
-                modified: adjusted_time,
-                //size: metadata.len(),
-                hash,
-            });
+            results.push(self.describe(relative_path, &entry.path(), &metadata, &mut hashed));
         }
 
+        self.remember(&hashed, None);
         Ok(results)
     }
 
+    // Doar calea ceruta, fara sa parcurgem tot folderul
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        let full_path = self.path.join(path);
+        let metadata = match std::fs::metadata(&full_path) {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut hashed = Vec::new();
+        let file = self.describe(path.to_string(), &full_path, &metadata, &mut hashed);
+        self.remember(&hashed, None);
+        Ok(Some(file))
+    }
+
     fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
         let full_path = self.path.join(path);
         let data = std::fs::read(&full_path)?;
@@ -81,6 +186,33 @@ impl Location for FolderLocation {
         Ok(())
     }
 
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        let file = File::open(self.path.join(path))?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
+        let full_path = self.path.join(path);
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Scriem intr-un fisier temporar si il mutam peste cel final doar la sfarsit
+        let file_name = full_path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = full_path.with_file_name(format!(".{}.part", file_name));
+        let file = File::create(&tmp_path)?;
+
+        Ok(Box::new(FolderWriteStream {
+            writer: BufWriter::new(file),
+            tmp_path,
+            final_path: full_path,
+            finished: false,
+        }))
+    }
+
     fn delete_file(&self, path: &str) -> Result<(), SyncError> {
         let full_path = self.path.join(path);
         if full_path.exists() {
@@ -90,9 +222,10 @@ impl Location for FolderLocation {
     }
     fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
         let mut results = Vec::new();
+        let mut hashed = Vec::new();
 
         for entry in WalkDir::new(&self.path).into_iter().filter_map(|e| e.ok()) {
-            if entry.file_type().is_file() {
+            if entry.file_type().is_file() && !is_transfer_temp(entry.path()) {
                 let full_path = entry.path();
                 let metadata = std::fs::metadata(full_path)?;
                 let relative_path = full_path
@@ -100,21 +233,15 @@ impl Location for FolderLocation {
                     .map(|p| p.to_string_lossy().to_string())
                     .unwrap_or_else(|_| full_path.to_string_lossy().to_string());
 
-                let hash = calculate_file_hash(full_path);
-
-                let modified_time = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
-                let adjusted_time = modified_time
-                    .checked_add(Duration::from_secs(2 * 3600))
-                    .unwrap_or(modified_time);
-
-                results.push(FileMetadata {
-                    path: relative_path,
-                    modified: adjusted_time,
-                    hash,
-                });
+                results.push(self.describe(relative_path, full_path, &metadata, &mut hashed));
             }
         }
 
+        if !hashed.is_empty() {
+            println!("Hashed {} new or changed files", hashed.len());
+        }
+        let listed: HashSet<String> = results.iter().map(|f| f.path.clone()).collect();
+        self.remember(&hashed, Some(&listed));
         Ok(results)
     }
 
@@ -146,6 +273,59 @@ impl Location for FolderLocation {
         Ok(())
     }
 
+    fn block_signature(&self, path: &str) -> Result<Option<Signature>, SyncError> {
+        let full_path = self.path.join(path);
+        if !full_path.is_file() {
+            return Ok(None);
+        }
+        let file = File::open(full_path)?;
+        let block_size = delta::block_size_for(file.metadata()?.len());
+        Ok(Some(delta::signature(BufReader::new(file), block_size)?))
+    }
+
+    fn apply_delta(&self, path: &str, delta: &Delta) -> Result<bool, SyncError> {
+        let full_path = self.path.join(path);
+        let old = File::open(&full_path)?;
+
+        // Construim noua versiune alaturi si o mutam peste cea veche doar daca e corecta
+        let file_name = full_path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = full_path.with_file_name(format!(".{}.delta-tmp", file_name));
+        let out = BufWriter::new(File::create(&tmp_path)?);
+
+        match delta::apply_delta(old, delta, out) {
+            Ok(true) => {
+                std::fs::rename(&tmp_path, &full_path)?;
+                Ok(true)
+            }
+            Ok(false) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                Ok(false)
+            }
+            Err(e) => {
+                let _ = std::fs::remove_file(&tmp_path);
+                Err(e.into())
+            }
+        }
+    }
+
+    fn set_modified(&self, path: &str, modified: SystemTime) -> Result<(), SyncError> {
+        let file = File::options().write(true).open(self.path.join(path))?;
+        file.set_modified(modified)?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        let to_path = self.path.join(to);
+        if let Some(parent) = to_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(self.path.join(from), to_path)?;
+        Ok(())
+    }
+
     fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
         let dir_path = self.path.join(path);
         if dir_path.is_dir() {
@@ -154,3 +334,31 @@ impl Location for FolderLocation {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_root;
+
+    #[test]
+    fn abandoned_write_leaves_nothing_behind() {
+        let root = temp_root("folder_abandoned");
+        let location = FolderLocation::new(root.to_str().unwrap());
+
+        let mut writer = location.open_write("docs/a.txt").unwrap();
+        writer.write_all(b"half").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_dir(root.join("docs")).unwrap().count(), 0);
+
+        let mut writer = location.open_write("docs/a.txt").unwrap();
+        writer.write_all(b"whole").unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(root.join("docs/a.txt")).unwrap(),
+            "whole"
+        );
+        assert_eq!(std::fs::read_dir(root.join("docs")).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
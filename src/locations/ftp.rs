@@ -1,86 +1,313 @@
-use crate::errors::SyncError;
-use crate::locations::{DirMetadata, FileMetadata, Location};
-use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
-use ftp::FtpStream;
-use sha2::{Digest, Sha256};
-use std::any::Any;
-use std::io::Cursor;
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::hash_cache::{FileKey, HashCache};
+use crate::errors::{ErrorContext, SyncError};
+use crate::locations::{
+    is_transfer_temp, Capabilities, DirMetadata, FileMetadata, HashAlgorithm, Location, WriteStream,
+};
+use chrono::{DateTime, FixedOffset, NaiveDateTime, Utc};
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use suppaftp::list::ListParser;
+use suppaftp::native_tls::{Certificate, TlsConnector};
+use suppaftp::types::FileType;
+use suppaftp::{
+    FtpError, FtpResult, NativeTlsConnector, NativeTlsFtpStream, Status, TlsStream, TransferStream,
+};
+
+// Toate conexiunile folosesc acelasi tip de stream; in modul Plain TLS nu e pornit
+type FtpStream = NativeTlsFtpStream;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FtpSecurity {
+    Plain,
+    // `ftps://`: TLS de la inceputul conexiunii (de obicei portul 990)
+    Implicit,
+    // `ftpes://`: conexiune normala, apoi AUTH TLS
+    Explicit,
+}
 
 pub struct FtpLocation {
     pub user: String,
     pub pass: String,
     pub host: String,
     pub path: String, // folder remote de unde facem sync
+    pub security: FtpSecurity,
+    // certificate CA suplimentare (PEM), pentru servere cu CA propriu
+    pub ca_file: Option<PathBuf>,
+    // accepta orice certificat; doar pentru servere de test self-signed
+    pub insecure: bool,
+    // fusul orar in care listeaza serverul timpii din LIST (`?tz=`); fara el masuram decalajul
+    pub tz: Option<FixedOffset>,
+    pub hash: HashAlgorithm,
+    // cu cate secunde sunt inainte timpii raportati de server fata de UTC, aflat o singura data
+    clock_offset: Mutex<Option<i64>>,
+    // raspunsul la FEAT, cerut o singura data
+    features: Mutex<Option<FtpFeatures>>,
+    // hash-urile deja calculate, ca sa nu descarcam fisierele nemodificate la fiecare poll
+    known: Mutex<HashMap<String, KnownFile>>,
+    // aceleasi hash-uri pastrate intre rulari, ca la foldere
+    hashes: Option<HashCache>,
+    // conexiuni logate care asteapta sa fie refolosite
+    pool: Mutex<Vec<FtpSession>>,
+    // directoarele gasite de ultimul `list_files_recursive`, pentru `list_dirs_recursive`
+    // care il urmeaza, ca sa nu parcurgem arborele de doua ori intr-o trecere
+    walked_dirs: Mutex<Option<Vec<DirMetadata>>>,
 }
 
-fn list_dirs_recursive_ftp(
-    ftp_stream: &mut FtpStream,
-    relative_path: &str, // Calea relativa pentru cwd
-    full_path: &str,     // Calea completa pentru inregistrare
-    results: &mut Vec<DirMetadata>,
-) -> Result<(), SyncError> {
-    println!("Listing directories in: '{}'", relative_path);
+// Cate conexiuni libere pastram si dupa cat timp de pauza le verificam cu NOOP
+const MAX_IDLE_SESSIONS: usize = 4;
+const KEEPALIVE_AFTER: Duration = Duration::from_secs(15);
 
-    if relative_path != "." && !relative_path.is_empty() {
-        println!("Changing directory to: '{}'", relative_path);
-        ftp_stream
-            .cwd(relative_path)
-            .map_err(|e| SyncError::Ftp(format!("Failed cwd({}): {}", relative_path, e)))?;
+// Fisierul scris la prima conexiune ca sa vedem ce ora raporteaza serverul pentru el;
+// numele de fisier temporar il tine departe de sincronizare daca ramane in urma
+const CLOCK_PROBE: &str = ".advanced-rsync-clock.part";
 
-        let current_dir = ftp_stream
-            .pwd()
-            .map_err(|e| SyncError::Ftp(e.to_string()))?;
-        println!("Current directory after cwd: '{}'", current_dir);
-    }
+// O conexiune logata; `cwd` e directorul curent relativ la root (None daca nu il stim)
+struct FtpSession {
+    stream: FtpStream,
+    root: String,
+    cwd: Option<String>,
+    last_used: Instant,
+}
 
-    let entries = ftp_stream
-        .list(None)
-        .map_err(|e| SyncError::Ftp(format!("Failed list in {}: {}", relative_path, e)))?;
+impl FtpSession {
+    // CWD doar daca nu suntem deja acolo; mereu pe calea absoluta, fara perechi cwd/cdup
+    fn cd(&mut self, dir: &str) -> Result<(), SyncError> {
+        if self.cwd.as_deref() == Some(dir) {
+            return Ok(());
+        }
+        let target = if dir.is_empty() {
+            self.root.clone()
+        } else {
+            format!("{}/{}", self.root.trim_end_matches('/'), dir)
+        };
+        self.cwd = None;
+        self.stream
+            .cwd(&target)
+            .map_err(|e| ftp_error(e).during("cwd").at(dir))?;
+        self.cwd = Some(dir.to_string());
+        Ok(())
+    }
 
-    for entry in entries {
-        println!("Entry: '{}'", entry);
-        if let Some((entry_name, is_dir, maybe_time)) = parse_list_entry_dir(&entry) {
-            println!(
-                "Parsed entry - Name: '{}', Is Dir: {}, Modified: {:?}",
-                entry_name, is_dir, maybe_time
-            );
-            if is_dir {
-                // Construim calea completa pentru inregistrare
-                let child_full_path = if full_path == "." || full_path.is_empty() {
-                    entry_name.clone()
-                } else {
-                    format!("{}/{}", full_path, entry_name)
-                };
-
-                // !!!Evitam adaugarea path-urilor goale sau "." sau ".."
-                if child_full_path.is_empty() || child_full_path == "." || child_full_path == ".." {
-                    println!("Skipping invalid directory path: '{}'", child_full_path);
-                    continue;
+    // Creeaza tot lantul de directoare si ramane in ultimul
+    fn make_dirs(&mut self, dir: &str) -> Result<(), SyncError> {
+        self.cd("")?;
+        let mut current = String::new();
+        for part in dir.split('/').filter(|p| !p.is_empty()) {
+            current = join_path(&current, part);
+            // ignoram daca deja exista
+            match self.stream.mkdir(part) {
+                Ok(_) => println!("Created directory '{}'", current),
+                Err(e) if is_unavailable(&e) => {}
+                Err(e) => {
+                    return Err(ftp_error(e).during("create dir").at(&current));
                 }
+            }
+            self.cd(&current)?;
+        }
+        Ok(())
+    }
+}
 
-                let dir_modified = maybe_time.unwrap_or(UNIX_EPOCH);
+// Sesiune imprumutata din pool; se intoarce in pool cand e eliberata
+struct PooledSession<'a> {
+    session: Option<FtpSession>,
+    pool: &'a Mutex<Vec<FtpSession>>,
+}
 
-                results.push(DirMetadata {
-                    path: child_full_path.clone(),
-                    modified: dir_modified,
-                });
+impl<'a> PooledSession<'a> {
+    fn new(session: FtpSession, pool: &'a Mutex<Vec<FtpSession>>) -> Self {
+        PooledSession {
+            session: Some(session),
+            pool,
+        }
+    }
 
-                // calea relativă entry_name si calea completă child_full_path
-                list_dirs_recursive_ftp(ftp_stream, &entry_name, &child_full_path, results)?;
-            }
+    // conexiunea nu mai e buna de nimic
+    fn discard(&mut self) {
+        self.session = None;
+    }
+
+    fn is_discarded(&self) -> bool {
+        self.session.is_none()
+    }
+}
+
+impl Deref for PooledSession<'_> {
+    type Target = FtpSession;
+
+    fn deref(&self) -> &FtpSession {
+        self.session.as_ref().expect("session already discarded")
+    }
+}
+
+impl DerefMut for PooledSession<'_> {
+    fn deref_mut(&mut self) -> &mut FtpSession {
+        self.session.as_mut().expect("session already discarded")
+    }
+}
+
+impl Drop for PooledSession<'_> {
+    fn drop(&mut self) {
+        let Some(mut session) = self.session.take() else {
+            return;
+        };
+        session.last_used = Instant::now();
+        let mut pool = self.pool.lock().unwrap();
+        if pool.len() < MAX_IDLE_SESSIONS {
+            pool.push(session);
+        } else {
+            let _ = session.stream.quit();
         }
     }
+}
 
-    // ne intaorcem la directrul parinte
-    if relative_path != "." && !relative_path.is_empty() {
-        println!("Changing directory up from: '{}'", relative_path);
-        ftp_stream
-            .cdup()
-            .map_err(|e| SyncError::Ftp(format!("Failed cdup from {}: {}", relative_path, e)))?;
+struct KnownFile {
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+    unique: Option<String>,
+    // hash-ul dat de server, daca nu e SHA-256 (MD5, CRC...)
+    server_hash: Option<String>,
+    hash: String,
+}
+
+impl KnownFile {
+    // size+mtime (si `unique`, daca il avem) neschimbate -> acelasi continut
+    fn matches(&self, entry: &FtpEntry) -> bool {
+        (entry.size.is_some() || entry.modified.is_some())
+            && self.size == entry.size
+            && self.modified == entry.modified
+            && self.unique == entry.unique
+    }
+}
+
+// Hash calculat de server, fara sa descarcam fisierul
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ServerHash {
+    // HASH (draft-bryan-ftpext-hash), cu algoritmul ales prin OPTS HASH
+    Hash(&'static str),
+    XSha256,
+    XMd5,
+    XCrc,
+}
+
+impl ServerHash {
+    // doar hash-urile facute cu algoritmul nostru se pot compara cu ale celorlalte locatii
+    fn algorithm(self) -> Option<HashAlgorithm> {
+        match self {
+            ServerHash::Hash("SHA-256") | ServerHash::XSha256 => Some(HashAlgorithm::Sha256),
+            ServerHash::Hash("CRC32") | ServerHash::XCrc => Some(HashAlgorithm::Crc32),
+            _ => None,
+        }
+    }
+
+    fn command(self, path: &str) -> String {
+        match self {
+            ServerHash::Hash(_) => format!("HASH {}", path),
+            ServerHash::XSha256 => format!("XSHA256 {}", path),
+            ServerHash::XMd5 => format!("XMD5 {}", path),
+            ServerHash::XCrc => format!("XCRC {}", path),
+        }
     }
 
-    Ok(())
+    fn hex_len(self) -> usize {
+        match self {
+            ServerHash::Hash("SHA-256") | ServerHash::XSha256 => 64,
+            ServerHash::Hash("SHA-1") => 40,
+            ServerHash::Hash("MD5") | ServerHash::XMd5 => 32,
+            _ => 8, // CRC32
+        }
+    }
+}
+
+// Comenzile optionale pe care le folosim, anuntate de server prin FEAT
+#[derive(Debug, Clone, Copy, Default)]
+struct FtpFeatures {
+    mlst: bool, // MLST + MLSD
+    mdtm: bool,
+    size: bool,
+    hash: Option<ServerHash>,
+    // setarea mtime-ului: MFMT e anuntat in FEAT, SITE UTIME doar il incercam
+    mfmt: bool,
+    site_utime: bool,
+}
+
+impl FtpFeatures {
+    fn negotiate(ftp_stream: &mut FtpStream, wanted: HashAlgorithm) -> Self {
+        // serverele vechi nu stiu FEAT: ramanem la LIST
+        let features = match ftp_stream.feat() {
+            Ok(features) => features,
+            Err(e) => {
+                println!("FEAT not supported ({}), falling back to LIST", e);
+                return FtpFeatures::default();
+            }
+        };
+        let has = |name: &str| features.keys().any(|k| k.eq_ignore_ascii_case(name));
+        // `HASH SHA-1;SHA-256*;MD5`: algoritmii pe care ii poate calcula serverul
+        let hash_algos: Vec<String> = features
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("HASH"))
+            .and_then(|(_, v)| v.as_deref())
+            .map(|v| {
+                v.split(';')
+                    .map(|a| a.trim_end_matches('*').to_ascii_uppercase())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let hash_algo = |name: &'static str| {
+            hash_algos
+                .iter()
+                .any(|a| a == name)
+                .then_some(ServerHash::Hash(name))
+        };
+
+        // de preferat algoritmul ales pentru sincronizare; celelalte doar confirma ca un
+        // fisier atins nu s-a schimbat
+        let native = match wanted {
+            HashAlgorithm::Sha256 => {
+                hash_algo("SHA-256").or_else(|| has("XSHA256").then_some(ServerHash::XSha256))
+            }
+            HashAlgorithm::Crc32 => {
+                hash_algo("CRC32").or_else(|| has("XCRC").then_some(ServerHash::XCrc))
+            }
+            HashAlgorithm::Blake3 | HashAlgorithm::Xxh3 => None,
+        };
+        let hash = native
+            .or_else(|| hash_algo("SHA-256"))
+            .or_else(|| has("XSHA256").then_some(ServerHash::XSha256))
+            .or_else(|| hash_algo("SHA-1"))
+            .or_else(|| hash_algo("MD5"))
+            .or_else(|| has("XMD5").then_some(ServerHash::XMd5))
+            .or_else(|| hash_algo("CRC32"))
+            .or_else(|| has("XCRC").then_some(ServerHash::XCrc));
+
+        let negotiated = FtpFeatures {
+            mlst: has("MLST"),
+            mdtm: has("MDTM"),
+            size: has("SIZE"),
+            hash,
+            mfmt: has("MFMT"),
+            site_utime: true,
+        };
+        println!("FTP server features: {:?}", negotiated);
+        negotiated
+    }
+}
+
+// O intrare dintr-un listing, indiferent de unde vine (MLSD, LIST, MDTM/SIZE)
+#[derive(Debug, Clone, Default)]
+struct FtpEntry {
+    name: String,
+    is_dir: bool,
+    size: Option<u64>,
+    modified: Option<SystemTime>,
+    // fapta `unique` din MLSx (de obicei device+inode)
+    unique: Option<String>,
+    // timpul vine din LIST, in ora locala a serverului; MLSx si MDTM dau mereu UTC
+    local_time: bool,
 }
 
 impl FtpLocation {
@@ -90,166 +317,616 @@ impl FtpLocation {
             pass: pass.to_string(),
             host: host.to_string(),
             path: path.to_string(),
+            security: FtpSecurity::Plain,
+            ca_file: None,
+            insecure: false,
+            tz: None,
+            hash: HashAlgorithm::default(),
+            clock_offset: Mutex::new(None),
+            features: Mutex::new(None),
+            known: Mutex::new(HashMap::new()),
+            hashes: None,
+            pool: Mutex::new(Vec::new()),
+            walked_dirs: Mutex::new(None),
+        }
+    }
+
+    // Fara cache doar descarcam din nou fisierele la prima trecere dupa pornire
+    pub fn with_hash_cache(mut self, db: &Path) -> Self {
+        let root = format!("ftp://{}@{}/{}", self.user, self.host, self.path);
+        match HashCache::open_remote(db, &root) {
+            Ok(cache) => self.hashes = Some(cache),
+            Err(e) => println!(
+                "Hash cache {} unavailable, {} will be hashed again after every restart: {}",
+                db.display(),
+                root,
+                e
+            ),
         }
+        self
     }
 
-    fn connect(&self) -> Result<FtpStream, SyncError> {
-        let mut ftp_stream =
-            FtpStream::connect(&self.host).map_err(|e| SyncError::Ftp(e.to_string()))?;
+    fn remember(&self, hashed: &[(String, FileKey, String)], listed: Option<&HashSet<String>>) {
+        if let Some(cache) = &self.hashes {
+            if let Err(e) = cache.update(hashed, listed) {
+                println!("Could not update the hash cache: {}", e);
+            }
+        }
+    }
+
+    fn tls_connector(&self) -> Result<NativeTlsConnector, SyncError> {
+        let mut builder = TlsConnector::builder();
+        if let Some(ca_file) = &self.ca_file {
+            let pem = std::fs::read(ca_file)?;
+            let cert = Certificate::from_pem(&pem).map_err(|e| {
+                SyncError::Parse(format!("Invalid CA bundle {}: {}", ca_file.display(), e))
+            })?;
+            builder.add_root_certificate(cert);
+        }
+        if self.insecure {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        let connector = builder
+            .build()
+            .map_err(|e| SyncError::protocol(format!("TLS setup failed: {}", e)))?;
+        Ok(NativeTlsConnector::from(connector))
+    }
+
+    fn connect(&self) -> Result<FtpSession, SyncError> {
+        println!("Connecting to FTP server {}", self.host);
+        let domain = self.host();
+        let ftp_stream = match self.security {
+            FtpSecurity::Plain => FtpStream::connect(&self.host),
+            FtpSecurity::Explicit => {
+                let connector = self.tls_connector()?;
+                FtpStream::connect(&self.host).and_then(|s| s.into_secure(connector, &domain))
+            }
+            FtpSecurity::Implicit => {
+                FtpStream::connect_secure_implicit(&self.host, self.tls_connector()?, &domain)
+            }
+        };
+        let mut ftp_stream = ftp_stream.map_err(|e| ftp_error(e).during("connect"))?;
 
         ftp_stream
             .login(&self.user, &self.pass)
-            .map_err(|e| SyncError::Ftp(e.to_string()))?;
+            .map_err(|e| ftp_error(e).during("login"))?;
+        // fara conversii de capat de linie
+        ftp_stream
+            .transfer_type(FileType::Binary)
+            .map_err(|e| ftp_error(e).during("set binary mode"))?;
+
+        let features = *self
+            .features
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| FtpFeatures::negotiate(&mut ftp_stream, self.hash));
+        // algoritmul pentru HASH se alege pe fiecare sesiune
+        if let Some(ServerHash::Hash(algo)) = features.hash {
+            if let Err(e) = ftp_stream.opts("HASH", Some(algo)) {
+                println!("Failed to select {} for HASH: {}", algo, e);
+            }
+        }
 
-        // Intram in root
+        // Intram in root si tinem minte calea absoluta, ca sa ne putem intoarce oricand
         if !self.path.is_empty() && self.path != "." {
             ftp_stream
                 .cwd(&self.path)
-                .map_err(|e| SyncError::Ftp(e.to_string()))?;
+                .map_err(|e| ftp_error(e).during("cwd").at(&self.path))?;
         }
-        Ok(ftp_stream)
+        let root = ftp_stream.pwd().map_err(|e| ftp_error(e).during("pwd"))?;
+        self.clock_offset
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| self.measure_clock_offset(&mut ftp_stream));
+        Ok(FtpSession {
+            stream: ftp_stream,
+            root,
+            cwd: Some(String::new()),
+            last_used: Instant::now(),
+        })
     }
 
-    fn list_files_recursive_ftp(
-        &self,
-        ftp_stream: &mut FtpStream,
-        relative_path: &str, // Calea relativa pentru cwd
-        full_path: &str,     // Calea completa pentru inregistrare
-        results: &mut Vec<FileMetadata>,
-    ) -> Result<(), SyncError> {
-        println!("Listing files in: '{}'", relative_path);
-
-        if relative_path != "." && !relative_path.is_empty() {
-            println!("Changing directory to: '{}'", relative_path);
-            ftp_stream
-                .cwd(relative_path)
-                .map_err(|e| SyncError::Ftp(format!("Failed cwd({}): {}", relative_path, e)))?;
+    // O sesiune din pool, verificata cu NOOP daca a stat prea mult nefolosita
+    fn session(&self) -> Result<PooledSession<'_>, SyncError> {
+        loop {
+            let idle = self.pool.lock().unwrap().pop();
+            let Some(mut session) = idle else {
+                break;
+            };
+            if session.last_used.elapsed() < KEEPALIVE_AFTER {
+                return Ok(PooledSession::new(session, &self.pool));
+            }
+            match session.stream.noop() {
+                Ok(_) => return Ok(PooledSession::new(session, &self.pool)),
+                Err(e) => println!("Dropping dead FTP connection: {}", e),
+            }
+        }
+        Ok(PooledSession::new(self.connect()?, &self.pool))
+    }
 
-            let current_dir = ftp_stream
-                .pwd()
-                .map_err(|e| SyncError::Ftp(e.to_string()))?;
-            println!("Current directory after cwd: '{}'", current_dir);
+    // Ruleaza `op` pe o sesiune din pool; daca a esuat pentru ca s-a pierdut conexiunea
+    // (NOOP nu mai raspunde), reincearca o data pe o conexiune noua.
+    // Sesiunea e intoarsa impreuna cu rezultatul, pentru transferurile in flux.
+    fn checkout<T>(
+        &self,
+        mut op: impl FnMut(&mut FtpSession) -> Result<T, SyncError>,
+    ) -> Result<(PooledSession<'_>, T), SyncError> {
+        let mut session = self.session()?;
+        match op(&mut session) {
+            Ok(value) => Ok((session, value)),
+            Err(e) if session.stream.noop().is_ok() => Err(e),
+            Err(e) => {
+                println!("FTP connection lost ({:?}), reconnecting", e);
+                session.discard();
+                let mut session = PooledSession::new(self.connect()?, &self.pool);
+                let value = op(&mut session)?;
+                Ok((session, value))
+            }
         }
+    }
 
-        let entries = ftp_stream
-            .list(None)
-            .map_err(|e| SyncError::Ftp(format!("Failed list in {}: {}", relative_path, e)))?;
+    fn with_session<T>(
+        &self,
+        op: impl FnMut(&mut FtpSession) -> Result<T, SyncError>,
+    ) -> Result<T, SyncError> {
+        self.checkout(op).map(|(_, value)| value)
+    }
 
-        for entry_line in entries {
-            println!("Entry: '{}'", entry_line);
-            if let Some((name, is_dir, maybe_time)) = parse_list_entry(&entry_line) {
+    fn features(&self) -> FtpFeatures {
+        self.features.lock().unwrap().unwrap_or_default()
+    }
+
+    fn measure_clock_offset(&self, ftp_stream: &mut FtpStream) -> i64 {
+        if let Some(tz) = self.tz {
+            return tz.local_minus_utc() as i64;
+        }
+        // cu MLSD nu folosim deloc timpii din LIST
+        if self.features().mlst {
+            return 0;
+        }
+        match self.probe_clock(ftp_stream) {
+            Ok(Some(offset)) => {
                 println!(
-                    "Parsed entry - Name: '{}', Is Dir: {}, Modified: {:?}",
-                    name, is_dir, maybe_time
+                    "FTP server {} reports times {}s off from our clock",
+                    self.host, offset
                 );
-                if is_dir {
-                    // Este subdirector -> recursie
-                    let sub_dir = if full_path == "." || full_path.is_empty() {
-                        name.clone()
-                    } else {
-                        format!("{}/{}", full_path, name)
-                    };
-
-                    if sub_dir.is_empty() || sub_dir == "." || sub_dir == ".." {
-                        println!("Skipping invalid subdirectory path: '{}'", sub_dir);
-                        continue;
-                    }
+                offset
+            }
+            Ok(None) => {
+                println!(
+                    "FTP server {} reports no modification times, assuming UTC",
+                    self.host
+                );
+                0
+            }
+            Err(e) => {
+                println!(
+                    "Could not measure the clock offset of {}, assuming UTC: {}",
+                    self.host, e
+                );
+                0
+            }
+        }
+    }
 
-                    // calea relativă name și calea completă sub_dir
-                    self.list_files_recursive_ftp(ftp_stream, &name, &sub_dir, results)?;
-                } else {
-                    let full_file_path = if full_path == "." || full_path.is_empty() {
-                        name.clone()
-                    } else {
-                        format!("{}/{}", full_path, name)
-                    };
-
-                    if full_file_path.is_empty() || full_file_path == "." || full_file_path == ".."
-                    {
-                        println!("Skipping invalid file path: '{}'", full_file_path);
-                        continue;
-                    }
+    // Urcam un fisier mic si citim ce ora ii da LIST: diferenta fata de ceasul nostru
+    // e fusul orar in care listeaza serverul
+    fn probe_clock(&self, ftp_stream: &mut FtpStream) -> Result<Option<i64>, SyncError> {
+        let before = SystemTime::now();
+        ftp_stream
+            .put_file(CLOCK_PROBE, &mut Cursor::new(b"clock probe"))
+            .map_err(|e| ftp_error(e).during("STOR").at(CLOCK_PROBE))?;
+        let after = SystemTime::now();
 
-                    let file_modified = maybe_time.unwrap_or(UNIX_EPOCH);
-                    let mut file_hash = None;
+        let reported = ftp_stream
+            .list(None)
+            .map_err(|e| ftp_error(e).during("LIST"))
+            .map(|lines| {
+                lines
+                    .iter()
+                    .filter_map(|l| parse_list_line(l))
+                    .find(|e| e.name == CLOCK_PROBE)
+                    .and_then(|e| e.modified)
+            });
+        if let Err(e) = ftp_stream.rm(CLOCK_PROBE) {
+            println!("Failed to remove {}: {}", CLOCK_PROBE, e);
+        }
 
-                    if let Ok(data) = self.read_file(&full_file_path) {
-                        file_hash = Some(calculate_hash(&data));
-                    }
+        // LIST da timpul trunchiat la minut, deci si decalajul il masuram in minute
+        let minute = Duration::from_secs(60);
+        let midpoint = before + after.duration_since(before).unwrap_or_default() / 2;
+        Ok(reported?.map(|reported| {
+            if reported + minute >= before && reported <= after + Duration::from_secs(1) {
+                0
+            } else {
+                let offset = seconds_between(midpoint, reported + minute / 2);
+                (offset + 30).div_euclid(60) * 60
+            }
+        }))
+    }
 
-                    results.push(FileMetadata {
-                        path: full_file_path.clone(),
-                        modified: file_modified,
-                        hash: file_hash,
-                    });
+    // Timpul raportat de server, adus in UTC; doar cel din LIST e in ora locala
+    fn to_utc(&self, entry: &FtpEntry) -> SystemTime {
+        let Some(reported) = entry.modified else {
+            return UNIX_EPOCH;
+        };
+        if !entry.local_time {
+            return reported;
+        }
+        let offset = self.clock_offset.lock().unwrap().unwrap_or(0);
+        shift_time(reported, -offset)
+    }
+
+    // Continutul unui director (relativ la root): MLSD daca se poate, altfel LIST. Cu
+    // `exact`, timpii si dimensiunile fisierelor din LIST se cer din nou, unul cate unul.
+    fn list_dir(
+        &self,
+        ftp_stream: &mut FtpStream,
+        dir: &str,
+        exact: bool,
+    ) -> Result<Vec<FtpEntry>, SyncError> {
+        let pathname = (!dir.is_empty()).then_some(dir);
+
+        if self.features().mlst {
+            let lines = ftp_stream
+                .mlsd(pathname)
+                .map_err(|e| ftp_error(e).during("MLSD").at(dir))?;
+            return Ok(lines
+                .iter()
+                .filter_map(|l| parse_mlsx_line(l))
+                .filter(|e| !is_transfer_temp(Path::new(&e.name)))
+                .collect());
+        }
 
-                    println!("Added file: '{}'", full_file_path);
+        let lines = ftp_stream
+            .list(pathname)
+            .map_err(|e| ftp_error(e).during("LIST").at(dir))?;
+        let mut entries = Vec::new();
+        for line in lines {
+            let Some(mut entry) = parse_list_line(&line) else {
+                continue;
+            };
+            // upload-uri neterminate si sonda altei instante care isi masoara decalajul
+            if is_transfer_temp(Path::new(&entry.name)) {
+                continue;
+            }
+            // LIST are doar minute (sau doar ziua) si ora locala a serverului
+            if exact && !entry.is_dir {
+                if let Some(exact) = self.stat(ftp_stream, &join_path(dir, &entry.name))? {
+                    entry.size = exact.size.or(entry.size);
+                    if exact.modified.is_some() {
+                        entry.modified = exact.modified;
+                        entry.local_time = false;
+                    }
                 }
             }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+
+    // Metadatele unui singur fisier: MLST, altfel MDTM/SIZE
+    fn stat(&self, ftp_stream: &mut FtpStream, path: &str) -> Result<Option<FtpEntry>, SyncError> {
+        let features = self.features();
+        if features.mlst {
+            return match ftp_stream.mlst(Some(path)) {
+                Ok(line) => Ok(parse_mlsx_line(&line)),
+                Err(e) if is_unavailable(&e) => Ok(None),
+                Err(e) => Err(ftp_error(e).during("MLST").at(path)),
+            };
+        }
+        if !features.mdtm && !features.size {
+            return Ok(None);
         }
 
-        // Revenim la directorul parinte
-        if relative_path != "." && !relative_path.is_empty() {
-            println!("Changing directory up from: '{}'", relative_path);
-            ftp_stream.cdup().map_err(|e| {
-                SyncError::Ftp(format!("Failed cdup from {}: {}", relative_path, e))
-            })?;
+        let (_, name) = split_path_dir_file(path);
+        let mut entry = FtpEntry {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        if features.mdtm {
+            // MDTM e mereu in UTC
+            match ftp_stream.mdtm(path) {
+                Ok(time) => entry.modified = Some(time.and_utc().into()),
+                Err(e) if is_unavailable(&e) => return Ok(None),
+                Err(e) => return Err(ftp_error(e).during("MDTM").at(path)),
+            }
         }
+        if features.size {
+            match ftp_stream.size(path) {
+                Ok(size) => entry.size = Some(size as u64),
+                Err(e) if is_unavailable(&e) => return Ok(None),
+                Err(e) => return Err(ftp_error(e).during("SIZE").at(path)),
+            }
+        }
+        Ok(Some(entry))
+    }
 
+    // Tot arborele de sub `dir`, cu cai relative la root
+    fn walk(
+        &self,
+        ftp_stream: &mut FtpStream,
+        dir: &str,
+        exact: bool,
+        results: &mut Vec<(String, FtpEntry)>,
+    ) -> Result<(), SyncError> {
+        println!("Listing: '{}'", dir);
+        for entry in self.list_dir(ftp_stream, dir, exact)? {
+            println!(
+                "Entry - Name: '{}', Is Dir: {}, Size: {:?}, Modified: {:?}, Unique: {:?}",
+                entry.name, entry.is_dir, entry.size, entry.modified, entry.unique
+            );
+            let path = join_path(dir, &entry.name);
+            let is_dir = entry.is_dir;
+            results.push((path.clone(), entry));
+            if is_dir {
+                self.walk(ftp_stream, &path, exact, results)?;
+            }
+        }
         Ok(())
     }
+
+    fn dir_metadata(&self, entries: Vec<(String, FtpEntry)>) -> Vec<DirMetadata> {
+        entries
+            .into_iter()
+            .map(|(path, e)| DirMetadata {
+                path,
+                modified: self.to_utc(&e),
+            })
+            .collect()
+    }
+
+    // Hash-ul calculat de server pentru `path`, daca il stie
+    fn server_hash(&self, ftp_stream: &mut FtpStream, path: &str) -> Option<String> {
+        let algo = self.features().hash?;
+        let response = match ftp_stream.custom_command(
+            algo.command(path),
+            &[Status::File, Status::RequestedFileActionOk],
+        ) {
+            Ok(response) => response,
+            Err(e) => {
+                println!("Server hash failed for {}: {}", path, e);
+                return None;
+            }
+        };
+        // `213 SHA-256 0-1023 <hex> nume` sau `250 <hex>`
+        let body = String::from_utf8_lossy(&response.body);
+        body.split_whitespace()
+            .find(|t| t.len() == algo.hex_len() && t.chars().all(|c| c.is_ascii_hexdigit()))
+            .map(|t| t.to_ascii_lowercase())
+    }
+
+    // Hash-ul unui fisier, de la cea mai ieftina metoda la cea mai scumpa:
+    // cache (size+mtime) -> hash de pe server cu algoritmul nostru -> alt hash de pe server
+    // (doar confirma ca nu s-a schimbat) -> descarcare. Ce calculam ajunge in `hashed`.
+    fn file_metadata(
+        &self,
+        ftp_stream: &mut FtpStream,
+        path: String,
+        entry: &FtpEntry,
+        hashed: &mut Vec<(String, FileKey, String)>,
+    ) -> FileMetadata {
+        let weak = self
+            .features()
+            .hash
+            .is_some_and(|h| h.algorithm() != Some(self.hash));
+        let key = entry
+            .size
+            .zip(entry.modified)
+            .map(|(size, modified)| FileKey::remote(size, modified));
+        let mut known = self.known.lock().unwrap();
+        // dupa o repornire, hash-ul din cache-ul de pe disc
+        if !known.contains_key(&path) {
+            let cached = self
+                .hashes
+                .as_ref()
+                .zip(key)
+                .and_then(|(cache, key)| cache.get(&path, key))
+                .filter(|hash| HashAlgorithm::of(hash) == Some(self.hash));
+            if let Some(hash) = cached {
+                known.insert(
+                    path.clone(),
+                    KnownFile {
+                        size: entry.size,
+                        modified: entry.modified,
+                        unique: entry.unique.clone(),
+                        server_hash: None,
+                        hash,
+                    },
+                );
+            }
+        }
+        let previous = known.get(&path);
+
+        let mut server_hash = None;
+        let hash = match previous {
+            Some(prev) if prev.matches(entry) => Some(prev.hash.clone()),
+            _ => {
+                server_hash = self.server_hash(ftp_stream, &path);
+                match (&server_hash, previous) {
+                    (Some(native), _) if !weak => Some(self.hash.tag(native)),
+                    // doar mtime s-a schimbat, continutul e acelasi
+                    (Some(h), Some(prev)) if prev.server_hash.as_ref() == Some(h) => {
+                        Some(prev.hash.clone())
+                    }
+                    _ => {
+                        println!("Downloading {} to hash it", path);
+                        self.open_read(&path)
+                            .ok()
+                            .and_then(|reader| self.hash.hash_reader(reader).ok())
+                    }
+                }
+            }
+        };
+
+        match &hash {
+            Some(hash) => {
+                if let Some(key) = key.filter(|_| previous.is_none_or(|p| p.hash != *hash)) {
+                    hashed.push((path.clone(), key, hash.clone()));
+                }
+                let server_hash = server_hash.or_else(|| {
+                    previous
+                        .filter(|p| p.hash == *hash)
+                        .and_then(|p| p.server_hash.clone())
+                });
+                known.insert(
+                    path.clone(),
+                    KnownFile {
+                        size: entry.size,
+                        modified: entry.modified,
+                        unique: entry.unique.clone(),
+                        server_hash: server_hash.filter(|_| weak),
+                        hash: hash.clone(),
+                    },
+                );
+            }
+            None => {
+                known.remove(&path);
+            }
+        }
+
+        FileMetadata {
+            path,
+            modified: self.to_utc(entry),
+            size: entry.size.unwrap_or(0),
+            hash,
+        }
+    }
 }
 
 //   //////////////////////////////////////IMPL Location pentru FtpLocation//////////////////////////////////////////
 impl Location for FtpLocation {
-    fn as_any(&self) -> &dyn Any {
-        self
+    // HASH se afla abia dupa prima conexiune; pana atunci nu ne bazam pe el
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            writable: true,
+            set_mtime: self.features().mfmt || self.features().site_utime,
+            hash: self.hash,
+            native_hashes: self
+                .features()
+                .hash
+                .and_then(|h| h.algorithm())
+                .into_iter()
+                .collect(),
+            watch_root: None,
+            rename: true,
+            case_sensitive: true,
+            permissions: false,
+        }
+    }
+
+    fn host(&self) -> String {
+        match self.host.rsplit_once(':') {
+            Some((host, _port)) => host.to_string(),
+            None => self.host.clone(),
+        }
     }
 
     // nu il mai folosim
     fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
-        let mut ftp_stream = self.connect()?;
-        let entries = ftp_stream
-            .list(None)
-            .map_err(|e| SyncError::Ftp(format!("Failed list in root: {}", e)))?;
-
-        let mut results = Vec::new();
-        for entry_line in entries {
-            if let Some((name, is_dir, maybe_time)) = parse_list_entry(&entry_line) {
-                if !is_dir {
-                    let file_modified = maybe_time.unwrap_or(UNIX_EPOCH);
-                    let mut file_hash = None;
-                    if let Ok(data) = self.read_file(&name) {
-                        file_hash = Some(calculate_hash(&data));
-                    }
-                    results.push(FileMetadata {
-                        path: name,
-                        modified: file_modified,
-                        hash: file_hash,
-                    });
-                }
-            }
-        }
-        Ok(results)
+        let mut hashed = Vec::new();
+        let files = self.with_session(|session| {
+            session.cd("")?;
+            let entries = self.list_dir(&mut session.stream, "", true)?;
+            Ok(entries
+                .iter()
+                .filter(|e| !e.is_dir)
+                .map(|e| self.file_metadata(&mut session.stream, e.name.clone(), e, &mut hashed))
+                .collect())
+        })?;
+        self.remember(&hashed, None);
+        Ok(files)
     }
 
     // folosim metoda recursiva
     fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
         println!("Starting recursive file listing");
-        let mut ftp_stream = self.connect()?;
-        let mut results = Vec::new();
-
-        self.list_files_recursive_ftp(&mut ftp_stream, ".", ".", &mut results)?;
+        // in afara sesiunii: dupa o reconectare nu pierdem ce am calculat deja
+        let mut hashed = Vec::new();
+        let mut dirs = Vec::new();
+        let results = self.with_session(|session| {
+            session.cd("")?;
+            let mut entries = Vec::new();
+            self.walk(&mut session.stream, "", true, &mut entries)?;
+
+            let (dir_entries, file_entries): (Vec<_>, Vec<_>) =
+                entries.into_iter().partition(|(_, e)| e.is_dir);
+            dirs = self.dir_metadata(dir_entries);
+            Ok(file_entries
+                .into_iter()
+                .map(|(path, e)| self.file_metadata(&mut session.stream, path, &e, &mut hashed))
+                .collect::<Vec<_>>())
+        })?;
+        *self.walked_dirs.lock().unwrap() = Some(dirs);
+        let listed: HashSet<String> = results.iter().map(|f| f.path.clone()).collect();
+        self.known
+            .lock()
+            .unwrap()
+            .retain(|path, _| listed.contains(path));
+        self.remember(&hashed, Some(&listed));
         println!("Completed recursive file listing");
         Ok(results)
     }
 
+    // MLST (sau MDTM/SIZE) pe calea ceruta; fara ele, tot listing-ul
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        let features = self.features();
+        if !features.mlst && !features.mdtm && !features.size {
+            return Ok(self
+                .list_files_recursive()?
+                .into_iter()
+                .find(|f| f.path == path));
+        }
+        let mut hashed = Vec::new();
+        let file = self.with_session(|session| {
+            session.cd("")?;
+            Ok(match self.stat(&mut session.stream, path)? {
+                Some(entry) if !entry.is_dir => Some(self.file_metadata(
+                    &mut session.stream,
+                    path.to_string(),
+                    &entry,
+                    &mut hashed,
+                )),
+                _ => None,
+            })
+        })?;
+        self.remember(&hashed, None);
+        Ok(file)
+    }
+
+    // Cu hash-ul serverului, daca e facut cu algoritmul cerut; altfel descarcam fisierul
+    fn content_hash(&self, path: &str, algorithm: HashAlgorithm) -> Result<String, SyncError> {
+        let server = self.features().hash.and_then(|h| h.algorithm());
+        if server == Some(algorithm) {
+            let native = self.with_session(|session| {
+                session.cd("")?;
+                Ok(self.server_hash(&mut session.stream, path))
+            })?;
+            if let Some(hex) = native {
+                return Ok(algorithm.tag(&hex));
+            }
+        }
+        println!("Downloading {} to hash it with {}", path, algorithm.name());
+        Ok(algorithm.hash_reader(self.open_read(path)?)?)
+    }
+
+    // Directoarele din parcurgerea facuta chiar inainte de `list_files_recursive`; altfel
+    // parcurgem arborele fara sa mai cerem detaliile fisierelor
     fn list_dirs_recursive(&self) -> Result<Vec<DirMetadata>, SyncError> {
+        if let Some(dirs) = self.walked_dirs.lock().unwrap().take() {
+            return Ok(dirs);
+        }
         println!("Starting recursive directory listing");
-        let mut ftp_stream = self.connect()?;
-        let mut results = Vec::new();
-
-        list_dirs_recursive_ftp(&mut ftp_stream, ".", ".", &mut results)?;
+        let mut entries = Vec::new();
+        self.with_session(|session| {
+            session.cd("")?;
+            entries.clear();
+            self.walk(&mut session.stream, "", false, &mut entries)
+        })?;
+
+        let results = self.dir_metadata(entries.into_iter().filter(|(_, e)| e.is_dir).collect());
         println!("Completed recursive directory listing");
         Ok(results)
     }
@@ -257,224 +934,382 @@ impl Location for FtpLocation {
     ////////////////////////////////////////// READ FILE //////////////////////////////////////////
 
     fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
-        let mut ftp_stream = self.connect()?;
-        let (dir, filename) = split_path_dir_file(path);
-
-        if !dir.is_empty() && dir != "." {
-            ftp_stream
-                .cwd(&dir)
-                .map_err(|e| SyncError::Ftp(format!("Failed cwd({}): {}", dir, e)))?;
-        }
+        let mut buffer = Vec::new();
+        self.open_read(path)?.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
 
-        let data = ftp_stream
-            .retr(filename, |reader| {
-                let mut buffer = Vec::new();
-                std::io::copy(reader, &mut buffer).map_err(ftp::FtpError::ConnectionError)?;
-                Ok(buffer)
-            })
-            .map_err(|e| SyncError::Ftp(format!("Failed retr {}: {}", filename, e)))?;
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        let (dir, filename) = split_path_dir_file(path);
 
-        if !dir.is_empty() && dir != "." {
-            let _ = ftp_stream.cdup();
-        }
-        Ok(data)
+        // RETR in flux; transferul se incheie cand cititorul e eliberat
+        let (session, stream) = self.checkout(|session| {
+            session.cd(&dir)?;
+            session
+                .stream
+                .retr_as_stream(filename)
+                .map_err(|e| ftp_error(e).during("RETR").at(path))
+        })?;
+        Ok(Box::new(FtpReadStream {
+            stream: Some(stream),
+            session,
+        }))
     }
 
     ////////////////////////////////////////// WRITE FILE (recursiv) //////////////////////////////////////////
     fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError> {
-        let mut ftp_stream = self.connect()?;
+        let mut writer = self.open_write(path)?;
+        writer.write_all(data)?;
+        writer.finish()
+    }
 
-        // Desfacem path-ul recursiv
+    // Ca la foldere: urcam in `.nume.part` si il mutam peste cel final doar la sfarsit,
+    // ca un upload intrerupt sa nu lase un fisier trunchiat sub numele adevarat
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
         let (dir, filename) = split_path_dir_file(path);
+        let tmp_name = format!(".{}.part", filename);
+
+        // facem tot lantul de subdirectoare, apoi upload in flux (STOR)
+        let (session, stream) = self.checkout(|session| {
+            session.make_dirs(&dir)?;
+            session
+                .stream
+                .put_with_stream(&tmp_name)
+                .map_err(|e| ftp_error(e).during("STOR").at(path))
+        })?;
+        Ok(Box::new(FtpWriteStream {
+            stream: Some(stream),
+            session,
+            location: self,
+            path: path.to_string(),
+            tmp_path: join_path(&dir, &tmp_name),
+        }))
+    }
 
-        // facem tot lantul de subdirectoare
-        if !dir.is_empty() && dir != "." {
-            let parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
-            for part in &parts {
-                // ignoram daca deja exista
-                match ftp_stream.mkdir(part) {
-                    Ok(_) => println!("Created subdir: {}", part),
-                    Err(ftp::FtpError::InvalidResponse(msg)) if msg.contains("550") => {}
-                    Err(e) => {
-                        return Err(SyncError::Ftp(format!(
-                            "Failed to make_dir({}): {}",
-                            part, e
-                        )));
-                    }
+    // `MFMT 20240131120000 cale` sau, pe serverele fara MFMT (ProFTPD), `SITE UTIME`;
+    // amandoua primesc timpul in UTC
+    fn set_modified(&self, path: &str, modified: SystemTime) -> Result<(), SyncError> {
+        let features = self.features();
+        let time = DateTime::<Utc>::from(modified).format("%Y%m%d%H%M%S");
+        let (verb, command) = if features.mfmt {
+            ("MFMT", format!("MFMT {} {}", time, path))
+        } else if features.site_utime {
+            ("SITE UTIME", format!("SITE UTIME {} {}", time, path))
+        } else {
+            return Ok(());
+        };
+
+        let result = self.with_session(|session| {
+            session.cd("")?;
+            session
+                .stream
+                .custom_command(
+                    &command,
+                    &[
+                        Status::CommandOk,
+                        Status::File,
+                        Status::RequestedFileActionOk,
+                    ],
+                )
+                .map(|_| ())
+                .map_err(|e| ftp_error(e).during(verb).at(path))
+        });
+        match result {
+            // comanda necunoscuta (500/502/504): nu o mai incercam
+            Err(SyncError::ProtocolError { ref context, .. })
+                if !features.mfmt && is_not_implemented(context) =>
+            {
+                println!(
+                    "{} does not support SITE UTIME, modification times will not be kept",
+                    self.host
+                );
+                if let Some(features) = self.features.lock().unwrap().as_mut() {
+                    features.site_utime = false;
                 }
-                //trecem mai departe
-                ftp_stream
-                    .cwd(part)
-                    .map_err(|e| SyncError::Ftp(format!("Failed cwd({}): {}", part, e)))?;
-            }
-        }
-
-        // Upload
-        let mut cursor = Cursor::new(data.to_vec());
-        ftp_stream
-            .put(filename, &mut cursor)
-            .map_err(|e| SyncError::Ftp(format!("Failed put {}: {}", filename, e)))?;
-
-        // revenim la root
-        if !dir.is_empty() && dir != "." {
-            let parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
-            for _ in 0..parts.len() {
-                let _ = ftp_stream.cdup();
+                Ok(())
             }
+            result => result,
         }
-
-        Ok(())
     }
 
     ////////////////////////////////////////// DELETE FILE //////////////////////////////////////////
     fn delete_file(&self, path: &str) -> Result<(), SyncError> {
-        let mut ftp_stream = self.connect()?;
-        let (dir, filename) = split_path_dir_file(path);
-
-        if !dir.is_empty() && dir != "." {
-            let parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
-            // coboram
-            for part in &parts {
-                ftp_stream
-                    .cwd(part)
-                    .map_err(|e| SyncError::Ftp(format!("Failed cwd({}): {}", part, e)))?;
-            }
-        }
-
-        match ftp_stream.rm(filename) {
-            Ok(_) => println!("File {} deleted successfully.", path),
-            Err(ftp::FtpError::InvalidResponse(msg)) if msg.contains("550") => {
-                println!("File {} does not exist or already deleted: {}", path, msg);
-            }
-            Err(e) => {
-                return Err(SyncError::Ftp(format!(
-                    "Failed to delete file {}, error: {}",
-                    path, e
-                )));
+        self.with_session(|session| {
+            session.cd("")?;
+            match session.stream.rm(path) {
+                Ok(_) => println!("File {} deleted successfully.", path),
+                Err(e) if is_unavailable(&e) => {
+                    println!("File {} does not exist or already deleted: {}", path, e);
+                }
+                Err(e) => {
+                    return Err(ftp_error(e).during("delete").at(path));
+                }
             }
-        }
+            Ok(())
+        })
+    }
 
-        // revenim la root
-        if !dir.is_empty() && dir != "." {
-            let parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
-            for _ in 0..parts.len() {
-                let _ = ftp_stream.cdup();
-            }
-        }
+    ////////////////////////////////////////// RENAME (RNFR/RNTO) //////////////////////////////////////////
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        let (to_dir, _) = split_path_dir_file(to);
+        self.with_session(|session| {
+            // directorul destinatie trebuie sa existe
+            session.make_dirs(&to_dir)?;
+            session.cd("")?;
+            session.stream.rename(from, to).map_err(|e| {
+                ftp_error(e)
+                    .during("rename")
+                    .at(&format!("{} -> {}", from, to))
+            })
+        })?;
+        println!("Renamed '{}' to '{}'", from, to);
         Ok(())
     }
 
     ///////////////////////////////////// CREATE DIR (recursiv) //////////////////////////////////////////
     fn create_dir(&self, path: &str) -> Result<(), SyncError> {
-        let mut ftp_stream = self.connect()?;
-        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
-
         println!("Creating directory: '{}'", path);
-        let mut current_path = String::new();
+        self.with_session(|session| session.make_dirs(path))
+    }
 
-        for part in &parts {
-            if !current_path.is_empty() {
-                current_path.push('/');
-            }
-            current_path.push_str(part);
+    //////////////////////////////////// REMOVE DIR (recursiv) ////////////////////////////////////////
+    fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            println!("Invalid target directory to remove: '{}'", path);
+            return Ok(());
+        }
 
-            println!("Ensuring directory exists: '{}'", current_path);
-            match ftp_stream.mkdir(part) {
-                Ok(_) => {
-                    println!("Created directory '{}'", current_path);
+        println!("Removing directory: '{}'", path);
+        self.with_session(|session| {
+            session.cd("")?;
+            let ftp_stream = &mut session.stream;
+            let mut entries = Vec::new();
+            self.walk(ftp_stream, path, false, &mut entries)?;
+
+            // intai fisierele, apoi directoarele de la cele mai adanci in sus
+            for (entry_path, _) in entries.iter().filter(|(_, e)| !e.is_dir) {
+                match ftp_stream.rm(entry_path) {
+                    Ok(_) => println!("Removed file '{}'", entry_path),
+                    Err(e) => println!("Failed to remove file '{}': {:?}", entry_path, e),
                 }
-                Err(ftp::FtpError::InvalidResponse(msg)) if msg.contains("550") => {
-                    println!("Directory '{}' may already exist: {}", current_path, msg);
+            }
+            for (entry_path, _) in entries.iter().filter(|(_, e)| e.is_dir).rev() {
+                match ftp_stream.rmdir(entry_path) {
+                    Ok(_) => println!("Removed directory '{}'", entry_path),
+                    Err(e) => println!("Cannot remove directory '{}': {:?}", entry_path, e),
                 }
+            }
+
+            match ftp_stream.rmdir(path) {
+                Ok(_) => println!("Removed directory '{}'", path),
                 Err(e) => {
-                    return Err(SyncError::Ftp(format!(
-                        "Failed to create directory '{}': {}",
-                        current_path, e
-                    )));
+                    println!("Cannot remove directory '{}': {:?}", path, e);
+
+                    return Err(ftp_error(e).during("remove dir").at(path));
                 }
             }
+            Ok(())
+        })
+    }
+}
 
-            println!("Changing directory to '{}'", current_path);
-            ftp_stream.cwd(part).map_err(|e| {
-                SyncError::Ftp(format!(
-                    "Failed to change directory to '{}': {}",
-                    current_path, e
-                ))
-            })?;
+// Fluxurile de transfer tin sesiunea imprumutata cat timp sunt folosite;
+// dupa ce transferul se incheie (226), sesiunea se intoarce in pool
+struct FtpReadStream<'a, S: FinishTransfer> {
+    stream: Option<S>,
+    session: PooledSession<'a>,
+}
+
+impl<S: Read + FinishTransfer> Read for FtpReadStream<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.read(buf),
+            None => Ok(0),
         }
+    }
+}
 
-        for _ in 0..parts.len() {
-            println!("Changing directory up");
-            ftp_stream
-                .cdup()
-                .map_err(|e| SyncError::Ftp(format!("Failed to cdup: {}", e)))?;
+impl<S: FinishTransfer> Drop for FtpReadStream<'_, S> {
+    fn drop(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            // raspunsul de final nu a venit: nu mai stim in ce stare e conexiunea
+            if let Err(e) = stream.finish_transfer() {
+                println!("Discarding FTP connection after failed transfer: {}", e);
+                self.session.discard();
+            }
+        }
+    }
+}
+
+struct FtpWriteStream<'a, S: FinishTransfer> {
+    stream: Option<S>,
+    session: PooledSession<'a>,
+    // pentru curatenie cand propria conexiune s-a pierdut
+    location: &'a FtpLocation,
+    path: String,
+    tmp_path: String,
+}
+
+impl<S: FinishTransfer> FtpWriteStream<'_, S> {
+    // `.nume.part` -> `nume`; unele servere (IIS) nu mut peste un fisier existent
+    fn publish(&mut self) -> Result<(), SyncError> {
+        let session = &mut *self.session;
+        session.cd("")?;
+        let renamed = session.stream.rename(&self.tmp_path, &self.path);
+        if renamed.is_err() && session.stream.rm(&self.path).is_ok() {
+            return session
+                .stream
+                .rename(&self.tmp_path, &self.path)
+                .map_err(|e| ftp_error(e).during("rename").at(&self.path));
         }
+        renamed.map_err(|e| ftp_error(e).during("rename").at(&self.path))
+    }
 
-        Ok(())
+    fn remove_partial(&mut self) {
+        let tmp_path = self.tmp_path.clone();
+        let remove = |session: &mut FtpSession| {
+            session.cd("")?;
+            session
+                .stream
+                .rm(&tmp_path)
+                .map_err(|e| ftp_error(e).during("DELE").at(&tmp_path))
+        };
+        let result = if self.session.is_discarded() {
+            self.location.with_session(remove)
+        } else {
+            remove(&mut self.session)
+        };
+        if let Err(e) = result {
+            println!(
+                "Failed to remove the partial upload {}: {}",
+                self.tmp_path, e
+            );
+        }
     }
+}
 
-    //////////////////////////////////// REMOVE DIR (recursiv) ////////////////////////////////////////
-    fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
-        let mut ftp_stream = self.connect()?;
-        let parts: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+trait FinishTransfer {
+    fn finish_transfer(self) -> FtpResult<()>;
+}
 
-        println!("Removing directory: '{}'", path);
+impl<T: TlsStream> FinishTransfer for TransferStream<T> {
+    fn finish_transfer(self) -> FtpResult<()> {
+        self.finish()
+    }
+}
 
-        // Coboram
-        for part in &parts[..parts.len().saturating_sub(1)] {
-            println!("Changing directory to '{}'", part);
-            ftp_stream
-                .cwd(part)
-                .map_err(|e| SyncError::Ftp(format!("Failed to cwd to '{}': {}", part, e)))?;
+impl<S: Write + FinishTransfer> Write for FtpWriteStream<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.write(buf),
+            None => Err(std::io::Error::other("transfer already finished")),
         }
+    }
 
-        // Directorul care trebuie sters
-        let target_dir = parts.last().unwrap_or(&"");
-        if target_dir.is_empty() {
-            println!("Invalid target directory to remove: '{}'", path);
-            return Ok(());
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.stream.as_mut() {
+            Some(stream) => stream.flush(),
+            None => Ok(()),
         }
+    }
+}
 
-        println!("Listing contents of directory '{}'", target_dir);
-        let entries = ftp_stream
-            .list(Some(target_dir))
-            .map_err(|e| SyncError::Ftp(format!("Failed to list '{}': {}", target_dir, e)))?;
-
-        for entry_line in entries {
-            println!("Entry in '{}': '{}'", target_dir, entry_line);
-            if let Some((name, is_dir, _maybe_time)) = parse_list_entry_dir(&entry_line) {
-                if is_dir {
-                    // Recursiv: remove_dir a/b
-                    let sub_path = format!("{}/{}", path, name);
-                    println!("Recursively removing subdirectory: '{}'", sub_path);
-                    self.remove_dir(&sub_path)?;
-                } else {
-                    println!("Removing file '{}'", name);
-                    match ftp_stream.rm(&format!("{}/{}", target_dir, name)) {
-                        Ok(_) => println!("Removed file '{}'", name),
-                        Err(e) => println!("Failed to remove file '{}': {:?}", name, e),
-                    }
-                }
+impl<S: Write + FinishTransfer> WriteStream for FtpWriteStream<'_, S> {
+    fn finish(mut self: Box<Self>) -> Result<(), SyncError> {
+        let Some(stream) = self.stream.take() else {
+            return Ok(());
+        };
+        // serverul confirma (226) ca a primit tot fisierul
+        let result = match stream.finish_transfer() {
+            Ok(()) => self.publish(),
+            Err(e) => {
+                self.session.discard();
+                Err(ftp_error(e).during("STOR").at(&self.path))
             }
+        };
+        if result.is_err() {
+            self.remove_partial();
         }
+        result
+    }
+}
 
-        println!("Removing directory '{}'", path);
-        match ftp_stream.rmdir(target_dir) {
-            Ok(_) => println!("Removed directory '{}'", path),
-            Err(e) => {
-                println!("Cannot remove directory '{}': {:?}", path, e);
+// Abandonat fara `finish` (sursa n-a mai putut fi citita): citim raspunsul transferului,
+// ca urmatoarea comanda pe conexiune sa nu il primeasca pe al lui, si stergem ce s-a urcat
+impl<S: FinishTransfer> Drop for FtpWriteStream<'_, S> {
+    fn drop(&mut self) {
+        let Some(stream) = self.stream.take() else {
+            return;
+        };
+        if let Err(e) = stream.finish_transfer() {
+            println!("Discarding FTP connection after failed transfer: {}", e);
+            self.session.discard();
+        }
+        self.remove_partial();
+    }
+}
 
-                return Err(SyncError::Ftp(format!(
-                    "Failed to remove directory '{}': {}",
-                    path, e
-                )));
+// Erorile FTP dupa codul de raspuns: 4xx trec de obicei singure (421 inseamna ca
+// serverul inchide conexiunea), 530 e autentificarea, iar 550 e folosit si pentru
+// fisiere lipsa si pentru acces refuzat, asa ca ne uitam si la text
+fn ftp_error(e: FtpError) -> SyncError {
+    let message = e.to_string();
+    match e {
+        FtpError::ConnectionError(err) => {
+            let timed_out = matches!(err.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock);
+            let context = ErrorContext::new(message).with_source(err).into();
+            if timed_out {
+                SyncError::Timeout(context)
+            } else {
+                SyncError::Connection(context)
             }
         }
-
-        Ok(())
+        FtpError::UnexpectedResponse(ref response) => {
+            let code = response.status.code();
+            let body = String::from_utf8_lossy(&response.body).to_lowercase();
+            let context = ErrorContext::new(message).with_source(e);
+            match code {
+                421 => SyncError::Connection(context.into()),
+                530 | 532 => SyncError::PermissionDenied(context.into()),
+                550 if body.contains("denied") || body.contains("permission") => {
+                    SyncError::PermissionDenied(context.into())
+                }
+                550 => SyncError::NotFound(context.into()),
+                400..=499 => SyncError::ProtocolError {
+                    context: context.into(),
+                    transient: true,
+                },
+                _ => SyncError::ProtocolError {
+                    context: context.into(),
+                    transient: false,
+                },
+            }
+        }
+        _ => SyncError::ProtocolError {
+            context: ErrorContext::new(message).with_source(e).into(),
+            transient: false,
+        },
     }
 }
 
+// 500/501/502/504: serverul nu stie comanda (sau forma ei)
+fn is_not_implemented(context: &ErrorContext) -> bool {
+    context
+        .source
+        .as_ref()
+        .and_then(|s| s.downcast_ref::<FtpError>())
+        .is_some_and(|e| {
+            matches!(e, FtpError::UnexpectedResponse(resp)
+                if matches!(resp.status.code(), 500 | 501 | 502 | 504))
+        })
+}
+
+// 550: fisierul/directorul nu exista sau exista deja
+fn is_unavailable(err: &FtpError) -> bool {
+    matches!(err, FtpError::UnexpectedResponse(resp) if resp.status == Status::FileUnavailable)
+}
+
 fn split_path_dir_file(path: &str) -> (String, &str) {
     match path.rsplit_once('/') {
         Some((dir, file)) => (dir.to_string(), file),
@@ -482,105 +1317,197 @@ fn split_path_dir_file(path: &str) -> (String, &str) {
     }
 }
 
-fn parse_list_entry_dir(entry_line: &str) -> Option<(String, bool, Option<SystemTime>)> {
-    let parts: Vec<&str> = entry_line.split_whitespace().collect();
-    if parts.len() < 9 {
-        return None;
+fn join_path(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
     }
-    let file_type = parts[0].chars().next()?;
-    let is_dir = file_type == 'd';
-    let name = parts[8..].join(" ");
-    let maybe_time = None;
-
-    Some((name, is_dir, maybe_time))
 }
 
-fn parse_list_entry(line: &str) -> Option<(String, bool, Option<SystemTime>)> {
-    println!("Parsing entry: {}", line);
-
-    let parts: Vec<&str> = line.split_whitespace().collect();
-    println!("Split parts: {:?}", parts);
-
-    if parts.len() < 9 {
-        println!("Entry has insufficient parts: {:?}", parts);
-        return None;
-    }
-
-    let file_type = parts[0].chars().next()?;
-    let is_dir = file_type == 'd';
-    let name = parts[8..].join(" ");
-    println!("File name: {}", name);
-
-    let modified = match parse_ftp_date(&parts[5..8]) {
-        Some(m) => m,
-        None => SystemTime::UNIX_EPOCH,
+// `type=file;size=1024;modify=20240131120000;unique=802U1A; nume fisier`
+fn parse_mlsx_line(line: &str) -> Option<FtpEntry> {
+    let (facts, name) = line.split_once(' ')?;
+    let mut entry = FtpEntry {
+        name: name.to_string(),
+        ..Default::default()
     };
-
-    Some((name, is_dir, Some(modified)))
-}
-
-fn parse_ftp_date(date_parts: &[&str]) -> Option<SystemTime> {
-    if date_parts.len() != 3 {
-        println!("Unexpected number of date parts: {:?}", date_parts);
-        return None;
+    let mut kind = None;
+
+    for fact in facts.split(';').filter(|f| !f.is_empty()) {
+        let Some((key, value)) = fact.split_once('=') else {
+            continue;
+        };
+        match key.to_ascii_lowercase().as_str() {
+            "type" => kind = Some(value.to_ascii_lowercase()),
+            "size" => entry.size = value.parse().ok(),
+            // YYYYMMDDHHMMSS[.sss], mereu UTC
+            "modify" => {
+                let whole = value.split('.').next().unwrap_or(value);
+                entry.modified = NaiveDateTime::parse_from_str(whole, "%Y%m%d%H%M%S")
+                    .ok()
+                    .map(|t| t.and_utc().into());
+            }
+            "unique" => entry.unique = Some(value.to_string()),
+            _ => {}
+        }
     }
 
-    // parsam luna
-    let month = match date_parts[0] {
-        "Jan" => 1,
-        "Feb" => 2,
-        "Mar" => 3,
-        "Apr" => 4,
-        "May" => 5,
-        "Jun" => 6,
-        "Jul" => 7,
-        "Aug" => 8,
-        "Sep" => 9,
-        "Oct" => 10,
-        "Nov" => 11,
-        "Dec" => 12,
-        other => {
-            println!("Invalid month: {}", other);
+    match kind.as_deref() {
+        Some("file") => {}
+        Some("dir") => entry.is_dir = true,
+        // directorul curent/parinte si tipurile speciale (`OS.unix=slink:...`)
+        Some(other) => {
+            println!("Skipping '{}' of type {}", entry.name, other);
             return None;
         }
-    };
+        None => return None,
+    }
+    if entry.name.is_empty() || entry.name == "." || entry.name == ".." {
+        return None;
+    }
+    Some(entry)
+}
 
-    // Parsam ziua
-    let day = match date_parts[1].parse::<u32>() {
-        Ok(d) => d,
+// Ultima varianta: LIST in format Unix (`ls -l`, cu ora sau cu anul) sau DOS/IIS
+fn parse_list_line(line: &str) -> Option<FtpEntry> {
+    let file = match ListParser::parse_posix(line).or_else(|_| ListParser::parse_dos(line)) {
+        Ok(file) => file,
         Err(e) => {
-            println!("Failed to parse day: {}, error: {}", date_parts[1], e);
+            // de ex. `total 12`
+            println!("Skipping LIST line '{}': {}", line, e);
             return None;
         }
     };
+    if file.name().is_empty() || file.name() == "." || file.name() == ".." {
+        return None;
+    }
+    // ca tipurile speciale din MLSx: nu urmam legaturile
+    if file.is_symlink() {
+        println!("Skipping symlink '{}'", file.name());
+        return None;
+    }
+    Some(FtpEntry {
+        name: file.name().to_string(),
+        is_dir: file.is_directory(),
+        size: (!file.is_directory()).then_some(file.size() as u64),
+        modified: Some(file.modified()),
+        unique: None,
+        local_time: true,
+    })
+}
 
-    // Parsam ora (HH:MM)
-    let naive_time = match NaiveTime::parse_from_str(date_parts[2], "%H:%M") {
-        Ok(t) => t,
-        Err(e) => {
-            println!(
-                "Failed to parse time (HH:MM): {}, error: {}",
-                date_parts[2], e
-            );
-            return None;
-        }
+fn shift_time(time: SystemTime, secs: i64) -> SystemTime {
+    let shift = Duration::from_secs(secs.unsigned_abs());
+    let shifted = if secs >= 0 {
+        time.checked_add(shift)
+    } else {
+        time.checked_sub(shift)
     };
+    shifted.unwrap_or(time)
+}
 
-    // luam anul curent din Utc
-    let current_date = Utc::now();
-    let current_year = current_date.year();
+// `to - from` in secunde intregi, rotunjit
+fn seconds_between(from: SystemTime, to: SystemTime) -> i64 {
+    match to.duration_since(from) {
+        Ok(ahead) => (ahead.as_millis() as i64 + 500) / 1000,
+        Err(behind) => -((behind.duration().as_millis() as i64 + 500) / 1000),
+    }
+}
 
-    // NaiveDateTime cu anul curent
-    let naive_date = NaiveDate::from_ymd_opt(current_year, month, day)?;
-    let naive_datetime = NaiveDateTime::new(naive_date, naive_time);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let datetime = Utc.from_utc_datetime(&naive_datetime);
+    fn time(entry: &FtpEntry) -> String {
+        DateTime::<Utc>::from(entry.modified.unwrap())
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
 
-    Some(datetime.into())
-}
+    #[test]
+    fn mlsx_lines() {
+        let entry =
+            parse_mlsx_line("type=file;size=1024;modify=20240131120000;unique=802U1A; report.txt")
+                .unwrap();
+        assert_eq!(entry.name, "report.txt");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, Some(1024));
+        assert_eq!(time(&entry), "2024-01-31 12:00:00");
+        assert_eq!(entry.unique.as_deref(), Some("802U1A"));
+        // MLSx da mereu UTC
+        assert!(!entry.local_time);
+
+        // fractiuni de secunda, fapte cu majuscule si spatii in nume
+        let entry = parse_mlsx_line("Type=dir;Modify=20240131120000.123; my docs").unwrap();
+        assert_eq!(entry.name, "my docs");
+        assert!(entry.is_dir);
+        assert_eq!(time(&entry), "2024-01-31 12:00:00");
+
+        for line in [
+            "type=cdir;modify=20240131120000; .",
+            "type=pdir;modify=20240131120000; ..",
+            "type=OS.unix=slink:/etc/passwd;modify=20240131120000; link",
+            "no facts here",
+        ] {
+            assert!(parse_mlsx_line(line).is_none(), "{}", line);
+        }
+    }
+
+    #[test]
+    fn unix_list_lines() {
+        // fisierele din ultimele 6 luni au ora, cele mai vechi anul
+        let entry = parse_list_line("-rw-r--r--   1 user  group      1024 Jan 31 12:00 report.txt")
+            .unwrap();
+        assert_eq!(entry.name, "report.txt");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, Some(1024));
+        assert!(time(&entry).ends_with("-01-31 12:00:00"));
+        // ora locala a serverului
+        assert!(entry.local_time);
+
+        let entry =
+            parse_list_line("-rw-r--r--   1 user  group        10 Mar  5  2019 old file.txt")
+                .unwrap();
+        assert_eq!(entry.name, "old file.txt");
+        assert_eq!(time(&entry), "2019-03-05 00:00:00");
+
+        let entry =
+            parse_list_line("drwxr-xr-x   2 user  group      4096 Jan 31 12:00 docs").unwrap();
+        assert_eq!(entry.name, "docs");
+        assert!(entry.is_dir);
+        assert_eq!(entry.size, None);
+
+        for line in [
+            "total 12",
+            "drwxr-xr-x   2 user  group      4096 Jan 31 12:00 .",
+            "drwxr-xr-x   2 user  group      4096 Jan 31 12:00 ..",
+        ] {
+            assert!(parse_list_line(line).is_none(), "{}", line);
+        }
+    }
 
-fn calculate_hash(data: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    hex::encode(hasher.finalize())
+    #[test]
+    fn dos_list_lines() {
+        let entry = parse_list_line("01-31-24  12:00PM                 1024 report.txt").unwrap();
+        assert_eq!(entry.name, "report.txt");
+        assert!(!entry.is_dir);
+        assert_eq!(entry.size, Some(1024));
+        assert_eq!(time(&entry), "2024-01-31 12:00:00");
+        assert!(entry.local_time);
+
+        let entry = parse_list_line("01-31-24  09:30AM       <DIR>          my docs").unwrap();
+        assert_eq!(entry.name, "my docs");
+        assert!(entry.is_dir);
+        assert_eq!(time(&entry), "2024-01-31 09:30:00");
+    }
+
+    // Legaturile simbolice nu se sincronizeaza, la fel ca `OS.unix=slink` din MLSD
+    #[test]
+    fn symlinks_are_skipped() {
+        assert!(parse_list_line(
+            "lrwxrwxrwx   1 user  group         7 Jan 31 12:00 current -> v1.2.3"
+        )
+        .is_none());
+    }
 }
@@ -0,0 +1,288 @@
+use crate::errors::SyncError;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Un fisier modificat chiar acum poate fi scris din nou in aceeasi cuanta de timp a
+// sistemului de fisiere, fara sa i se schimbe mtime-ul; hash-ul lui nu il tinem minte
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+// Hash-urile unei locatii nedeschise de atata timp (folder sters, disc scos de mult,
+// server renuntat) se sterg; un disc nemontat doar pentru o vreme si le pastreaza
+const UNUSED_ROOT_AGE: Duration = Duration::from_secs(90 * 24 * 3600);
+
+// Ce stim despre un fisier fara sa il citim. Cat timp raman toate la fel, continutul
+// (deci si hash-ul) e acelasi; inode-ul prinde fisierele inlocuite prin rename.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FileKey {
+    size: u64,
+    modified: SystemTime,
+    inode: u64,
+}
+
+impl FileKey {
+    pub fn of(metadata: &Metadata) -> Self {
+        #[cfg(unix)]
+        let inode = std::os::unix::fs::MetadataExt::ino(metadata);
+        #[cfg(not(unix))]
+        let inode = 0;
+        FileKey {
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            inode,
+        }
+    }
+
+    // Pentru fisierele de pe un server: doar ce raporteaza listing-ul
+    pub fn remote(size: u64, modified: SystemTime) -> Self {
+        FileKey {
+            size,
+            modified,
+            inode: 0,
+        }
+    }
+
+    fn modified_nanos(&self) -> i64 {
+        self.modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0)
+    }
+}
+
+// `$XDG_CACHE_HOME/advanced_rsync`, altfel `~/.cache/...` (`%LOCALAPPDATA%` pe Windows)
+pub fn cache_dir() -> Option<PathBuf> {
+    let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))?;
+    Some(cache_dir.join("advanced_rsync"))
+}
+
+// Hash-urile fisierelor dintr-un folder (sau de pe un server FTP), pastrate intre rulari
+// intr-o baza SQLite comuna tuturor locatiilor (cheia include radacina locatiei)
+pub struct HashCache {
+    conn: Mutex<Connection>,
+    root: String,
+}
+
+impl HashCache {
+    pub fn open(db: &Path, root: &Path) -> Result<Self, SyncError> {
+        let root = root.canonicalize().unwrap_or_else(|_| root.to_path_buf());
+        Self::open_root(db, root.to_string_lossy().to_string())
+    }
+
+    // Radacina unei locatii de la distanta, ca URL (`ftp://user@host/cale`)
+    pub fn open_remote(db: &Path, root: &str) -> Result<Self, SyncError> {
+        Self::open_root(db, root.to_string())
+    }
+
+    fn open_root(db: &Path, root: String) -> Result<Self, SyncError> {
+        if let Some(parent) = db.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(db)?;
+        // aceeasi baza poate fi folosita de mai multe procese deodata
+        conn.busy_timeout(Duration::from_secs(5))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS hashes (
+                root TEXT NOT NULL,
+                path TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                modified INTEGER NOT NULL,
+                inode INTEGER NOT NULL,
+                hash TEXT NOT NULL,
+                PRIMARY KEY (root, path)
+            );
+            CREATE TABLE IF NOT EXISTS roots (
+                root TEXT PRIMARY KEY,
+                last_used INTEGER NOT NULL
+            );",
+        )?;
+        prune_unused_roots(&conn, &root)?;
+        Ok(HashCache {
+            conn: Mutex::new(conn),
+            root,
+        })
+    }
+
+    pub fn default_path() -> Option<PathBuf> {
+        Some(cache_dir()?.join("hashes.db"))
+    }
+
+    // Hash-ul tinut minte pentru `path`, daca fisierul nu s-a schimbat de atunci
+    pub fn get(&self, path: &str, key: FileKey) -> Option<String> {
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT hash FROM hashes
+             WHERE root = ?1 AND path = ?2 AND size = ?3 AND modified = ?4 AND inode = ?5",
+            params![
+                self.root,
+                path,
+                key.size as i64,
+                key.modified_nanos(),
+                key.inode as i64
+            ],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap_or_else(|e| {
+            println!("Hash cache lookup failed for {}: {}", path, e);
+            None
+        })
+    }
+
+    // Salveaza hash-urile proaspat calculate. Dupa un listing complet (`listed`) uitam si
+    // fisierele care nu mai exista.
+    pub fn update(
+        &self,
+        hashed: &[(String, FileKey, String)],
+        listed: Option<&HashSet<String>>,
+    ) -> Result<(), SyncError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let settled = SystemTime::now() - RACY_WINDOW;
+        for (path, key, hash) in hashed {
+            if key.modified > settled {
+                continue;
+            }
+            tx.execute(
+                "INSERT OR REPLACE INTO hashes (root, path, size, modified, inode, hash)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    self.root,
+                    path,
+                    key.size as i64,
+                    key.modified_nanos(),
+                    key.inode as i64,
+                    hash
+                ],
+            )?;
+        }
+        if let Some(listed) = listed {
+            let known: Vec<String> = {
+                let mut stmt = tx.prepare("SELECT path FROM hashes WHERE root = ?1")?;
+                let rows = stmt.query_map(params![self.root], |row| row.get(0))?;
+                rows.collect::<Result<_, _>>()?
+            };
+            for path in known.iter().filter(|p| !listed.contains(*p)) {
+                tx.execute(
+                    "DELETE FROM hashes WHERE root = ?1 AND path = ?2",
+                    params![self.root, path],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+// Tine minte ca `root` e folosita acum si uita locatiile nefolosite de UNUSED_ROOT_AGE.
+// Nu ne uitam daca folderul exista: un disc nemontat la pornire isi pastreaza hash-urile.
+fn prune_unused_roots(conn: &Connection, root: &str) -> Result<(), SyncError> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    // cache-urile de dinainte de tabela `roots` pornesc ca folosite acum
+    conn.execute(
+        "INSERT OR IGNORE INTO roots (root, last_used) SELECT DISTINCT root, ?1 FROM hashes",
+        params![now],
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO roots (root, last_used) VALUES (?1, ?2)",
+        params![root, now],
+    )?;
+    conn.execute(
+        "DELETE FROM roots WHERE last_used < ?1",
+        params![now - UNUSED_ROOT_AGE.as_secs() as i64],
+    )?;
+    conn.execute(
+        "DELETE FROM hashes WHERE root NOT IN (SELECT root FROM roots)",
+        [],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_root;
+    use std::fs;
+
+    // un fisier scris "demult", ca sa treaca de RACY_WINDOW
+    fn old_file(path: &Path) -> FileKey {
+        fs::write(path, "content").unwrap();
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        FileKey::of(&fs::metadata(path).unwrap())
+    }
+
+    fn roots(cache: &HashCache) -> i64 {
+        cache
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT COUNT(DISTINCT root) FROM hashes", [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn hashes_are_kept_until_the_file_goes_away_or_its_folder_is_unused() {
+        let base = temp_root("hash_cache_prune");
+        let db = base.join("hashes.db");
+        let (kept, removed) = (base.join("kept"), base.join("removed"));
+        fs::create_dir_all(&kept).unwrap();
+        fs::create_dir_all(&removed).unwrap();
+
+        let key = old_file(&kept.join("a.txt"));
+        let cache = HashCache::open(&db, &kept).unwrap();
+        cache
+            .update(&[("a.txt".to_string(), key, "sha256:aa".to_string())], None)
+            .unwrap();
+        assert_eq!(cache.get("a.txt", key).as_deref(), Some("sha256:aa"));
+
+        let other = HashCache::open(&db, &removed).unwrap();
+        let removed_key = old_file(&removed.join("b.txt"));
+        other
+            .update(
+                &[("b.txt".to_string(), removed_key, "sha256:bb".to_string())],
+                None,
+            )
+            .unwrap();
+        drop(other);
+
+        // un listing complet fara `a.txt` il scoate din cache
+        let listed = HashSet::new();
+        cache.update(&[], Some(&listed)).unwrap();
+        assert_eq!(cache.get("a.txt", key), None);
+
+        // un folder lipsa (disc nemontat) isi pastreaza hash-urile
+        fs::remove_dir_all(&removed).unwrap();
+        drop(cache);
+        let cache = HashCache::open(&db, &kept).unwrap();
+        assert_eq!(roots(&cache), 1);
+
+        // pana nu mai e folosit de UNUSED_ROOT_AGE
+        cache
+            .conn
+            .lock()
+            .unwrap()
+            .execute(
+                "UPDATE roots SET last_used = last_used - ?1 - 1 WHERE root != ?2",
+                params![UNUSED_ROOT_AGE.as_secs() as i64, cache.root],
+            )
+            .unwrap();
+        drop(cache);
+        let cache = HashCache::open(&db, &kept).unwrap();
+        assert_eq!(roots(&cache), 0);
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+use crate::errors::SyncError;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+// Algoritmul cu care calculam hash-ul continutului, ales per locatie (`?hash=`, altfel
+// `--hash`). Hash-urile se salveaza ca `algoritm:hex` (ex. `blake3:…`), asa ca doua
+// hash-uri se compara doar daca vin din acelasi algoritm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    Xxh3,
+    // il are deja fiecare intrare dintr-o arhiva zip
+    Crc32,
+}
+
+const ALGORITHMS: [HashAlgorithm; 4] = [
+    HashAlgorithm::Sha256,
+    HashAlgorithm::Blake3,
+    HashAlgorithm::Xxh3,
+    HashAlgorithm::Crc32,
+];
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Xxh3 => "xxh3",
+            HashAlgorithm::Crc32 => "crc32",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, SyncError> {
+        ALGORITHMS
+            .into_iter()
+            .find(|a| a.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                SyncError::Parse(format!(
+                    "Unknown hash algorithm: {} (use sha256, blake3, xxh3 or crc32)",
+                    name
+                ))
+            })
+    }
+
+    // Algoritmul unui hash salvat (`blake3:…`)
+    pub fn of(hash: &str) -> Option<Self> {
+        let (name, _) = hash.split_once(':')?;
+        Self::parse(name).ok()
+    }
+
+    // `hex` calculat altundeva (server FTP, arhiva zip), cu prefixul algoritmului
+    pub fn tag(self, hex: &str) -> String {
+        format!("{}:{}", self.name(), hex.to_ascii_lowercase())
+    }
+
+    // Citim in bucati, fara sa tinem tot continutul in memorie
+    pub fn hash_reader(self, mut reader: impl Read) -> std::io::Result<String> {
+        let mut hasher = ContentHasher::new(self);
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let n = reader.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buffer[..n]);
+        }
+        Ok(hasher.finish())
+    }
+
+    pub fn hash_file(self, path: &Path) -> Option<String> {
+        self.hash_reader(File::open(path).ok()?).ok()
+    }
+}
+
+// Hash-urile salvate inainte sa le punem prefixul erau toate SHA-256
+pub fn normalize_hash(hash: String) -> String {
+    if HashAlgorithm::of(&hash).is_some() {
+        hash
+    } else {
+        HashAlgorithm::Sha256.tag(&hash)
+    }
+}
+
+// Some(egale) daca hash-urile vin din acelasi algoritm; altfel nu spun nimic despre continut
+pub fn same_hash(a: &str, b: &str) -> Option<bool> {
+    let (a, b) = (normalize_hash(a.to_string()), normalize_hash(b.to_string()));
+    (HashAlgorithm::of(&a)? == HashAlgorithm::of(&b)?).then(|| a == b)
+}
+
+enum ContentHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+    Crc32(crc32fast::Hasher),
+}
+
+impl ContentHasher {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => ContentHasher::Blake3(Box::default()),
+            HashAlgorithm::Xxh3 => ContentHasher::Xxh3(Box::default()),
+            HashAlgorithm::Crc32 => ContentHasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ContentHasher::Sha256(h) => h.update(data),
+            ContentHasher::Blake3(h) => {
+                h.update(data);
+            }
+            ContentHasher::Xxh3(h) => h.update(data),
+            ContentHasher::Crc32(h) => h.update(data),
+        }
+    }
+
+    fn finish(self) -> String {
+        let (algorithm, hex) = match self {
+            ContentHasher::Sha256(h) => (HashAlgorithm::Sha256, hex::encode(h.finalize())),
+            ContentHasher::Blake3(h) => (HashAlgorithm::Blake3, h.finalize().to_hex().to_string()),
+            ContentHasher::Xxh3(h) => (HashAlgorithm::Xxh3, format!("{:016x}", h.digest())),
+            // acelasi format ca XCRC pe FTP si CRC-ul din zip
+            ContentHasher::Crc32(h) => (HashAlgorithm::Crc32, format!("{:08x}", h.finalize())),
+        };
+        algorithm.tag(&hex)
+    }
+}
@@ -1,15 +1,23 @@
 mod folder;
 mod ftp;
+mod hash_cache;
+mod hashing;
+mod retry;
+mod sftp;
 mod zip;
 
+use crate::delta::{Delta, Signature};
 use crate::errors::SyncError;
+use chrono::FixedOffset;
+use std::io::{Cursor, Read, Write};
 use std::time::SystemTime;
 
 #[derive(Debug, Clone)]
 pub struct FileMetadata {
     pub path: String,
     pub modified: SystemTime,
-    //pub size: u64,
+    // 0 daca locatia nu stie dimensiunea (ex. FTP fara SIZE)
+    pub size: u64,
     pub hash: Option<String>,
 }
 
@@ -20,11 +28,115 @@ pub struct DirMetadata {
 }
 
 pub use folder::FolderLocation;
-pub use ftp::FtpLocation;
-use std::any::Any;
+pub use ftp::{FtpLocation, FtpSecurity};
+pub use hash_cache::cache_dir;
+use hash_cache::HashCache;
+pub use hashing::{normalize_hash, same_hash, HashAlgorithm};
+pub use retry::{RetryPolicy, RetryingLocation};
+pub use sftp::SftpLocation;
+use std::path::PathBuf;
 pub use zip::ZipLocation;
-pub trait Location: Any {
-    fn as_any(&self) -> &dyn Any;
+
+// Ce poate face o locatie. Motorul de sincronizare se uita doar aici, nu la tipul
+// concret, asa ca un backend nou nu cere schimbari in sync_logic.
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    pub writable: bool,
+    // poate pastra timpul modificarii unui fisier scris
+    pub set_mtime: bool,
+    // algoritmul hash-urilor din listing (`?hash=`, implicit `--hash`)
+    pub hash: HashAlgorithm,
+    // algoritmii cu care locatia afla hash-ul fara sa citeasca fisierul (CRC-ul din zip,
+    // HASH pe FTP)
+    pub native_hashes: Vec<HashAlgorithm>,
+    // folderul local care poate fi urmarit cu notify; caile din evenimente sunt relative la el
+    pub watch_root: Option<PathBuf>,
+    // mutare nativa; altfel rename inseamna copiere + stergere
+    pub rename: bool,
+    pub case_sensitive: bool,
+    // pastreaza drepturile fisierelor (rwx)
+    pub permissions: bool,
+}
+
+impl Default for Capabilities {
+    // ce garanteaza implementarile implicite din `Location`
+    fn default() -> Self {
+        Capabilities {
+            writable: true,
+            set_mtime: false,
+            hash: HashAlgorithm::default(),
+            native_hashes: vec![],
+            watch_root: None,
+            rename: false,
+            case_sensitive: true,
+            permissions: false,
+        }
+    }
+}
+
+impl Capabilities {
+    // `writable, rename, mtime, watched` pentru mesajul de la pornire
+    pub fn summary(&self) -> String {
+        let flags = [
+            (self.writable, "writable"),
+            (!self.writable, "read-only"),
+            (self.rename, "rename"),
+            (self.set_mtime, "mtime"),
+            (!self.native_hashes.is_empty(), "native hash"),
+            (self.permissions, "permissions"),
+            (!self.case_sensitive, "case-insensitive"),
+            (self.watch_root.is_some(), "watched"),
+        ];
+        flags
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+// Flux de scriere care trebuie inchis explicit, ca sa aflam daca datele au ajuns
+pub trait WriteStream: Write {
+    fn finish(self: Box<Self>) -> Result<(), SyncError>;
+}
+
+// Fisierele temporare ale unui transfer neterminat (`.nume.part`, `.nume.delta-tmp`)
+pub fn is_transfer_temp(path: &std::path::Path) -> bool {
+    path.file_name()
+        .and_then(|f| f.to_str())
+        .is_some_and(|f| f.starts_with('.') && (f.ends_with(".part") || f.ends_with(".delta-tmp")))
+}
+
+type FinishFn<'a> = Box<dyn FnOnce(&[u8]) -> Result<(), SyncError> + 'a>;
+
+// Pentru locatiile fara scriere in flux: adunam datele si le scriem la final
+struct BufferedWriteStream<'a> {
+    data: Vec<u8>,
+    on_finish: FinishFn<'a>,
+}
+
+impl Write for BufferedWriteStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WriteStream for BufferedWriteStream<'_> {
+    fn finish(self: Box<Self>) -> Result<(), SyncError> {
+        (self.on_finish)(&self.data)
+    }
+}
+
+pub trait Location {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError>;
     fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError>;
     fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError>;
@@ -33,6 +145,29 @@ pub trait Location: Any {
     fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
         self.list_files()
     }
+
+    // Un singur fisier (None daca nu exista). Implicit il cautam in listing-ul complet;
+    // locatiile care pot interoga direct o cale o fac
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        Ok(self
+            .list_files_recursive()?
+            .into_iter()
+            .find(|f| f.path == path))
+    }
+
+    // Citire/scriere in flux, fara sa tinem tot fisierul in memorie.
+    // Implicit se bazeaza pe read_file/write_file.
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        Ok(Box::new(Cursor::new(self.read_file(path)?)))
+    }
+
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
+        let path = path.to_string();
+        Ok(Box::new(BufferedWriteStream {
+            data: Vec::new(),
+            on_finish: Box::new(move |data| self.write_file(&path, data)),
+        }))
+    }
     fn create_dir(&self, _path: &str) -> Result<(), SyncError> {
         Ok(())
     }
@@ -44,9 +179,50 @@ pub trait Location: Any {
     fn list_dirs_recursive(&self) -> Result<Vec<DirMetadata>, SyncError> {
         Ok(vec![])
     }
+
+    // Mutare/redenumire (fisier sau director). Implicit: copiem si stergem.
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        let data = self.read_file(from)?;
+        self.write_file(to, &data)?;
+        self.delete_file(from)
+    }
+
+    // Hash-ul unui fisier cu alt algoritm decat cel din listing, ca sa il putem compara cu
+    // copia din alta locatie. Implicit citim tot fisierul.
+    fn content_hash(&self, path: &str, algorithm: HashAlgorithm) -> Result<String, SyncError> {
+        Ok(algorithm.hash_reader(self.open_read(path)?)?)
+    }
+
+    // Timpul modificarii unui fisier tocmai scris, ca sa ramana cel al sursei; doar pentru
+    // locatiile cu `capabilities().set_mtime`
+    fn set_modified(&self, _path: &str, _modified: SystemTime) -> Result<(), SyncError> {
+        Ok(())
+    }
+
+    // Transfer delta: semnatura blocurilor din copia existenta. None daca locatia nu poate
+    // citi local fisierul (sau acesta nu exista), caz in care se scrie fisierul intreg.
+    fn block_signature(&self, _path: &str) -> Result<Option<Signature>, SyncError> {
+        Ok(None)
+    }
+
+    // Reconstruieste fisierul din copia veche plus delta; false daca nu s-a putut
+    fn apply_delta(&self, _path: &str, _delta: &Delta) -> Result<bool, SyncError> {
+        Ok(false)
+    }
+
+    // Scrie modificarile adunate pana acum (o arhiva se rescrie o singura data pe lot)
+    fn flush(&self) -> Result<(), SyncError> {
+        Ok(())
+    }
+
+    // Masina pe care se afla datele (apare in numele copiilor de conflict)
+    fn host(&self) -> String {
+        gethostname::gethostname().to_string_lossy().to_string()
+    }
 }
 
-pub fn parse_location(loc_str: &str) -> Result<Box<dyn Location>, SyncError> {
+// `hash`: algoritmul locatiilor care nu si-l aleg singure cu `?hash=`
+pub fn parse_location(loc_str: &str, hash: HashAlgorithm) -> Result<Box<dyn Location>, SyncError> {
     let parts: Vec<&str> = loc_str.splitn(2, ':').collect();
     if parts.len() < 2 {
         return Err(SyncError::Parse("Format invalid".to_string()));
@@ -55,21 +231,177 @@ pub fn parse_location(loc_str: &str) -> Result<Box<dyn Location>, SyncError> {
     let loc_type = parts[0];
     let loc_path = parts[1];
 
-    match loc_type {
-        "folder" => Ok(Box::new(FolderLocation::new(loc_path))),
-        "zip" => Ok(Box::new(ZipLocation::new(loc_path))),
-        "ftp" => {
-            let ftp_str = loc_path;
-            if let Some((cred, host_path)) = ftp_str.split_once('@') {
-                if let Some((user, pass)) = cred.split_once(':') {
-                    if let Some((host, remote_path)) = host_path.split_once('/') {
-                        println!("{}", { remote_path });
-                        return Ok(Box::new(FtpLocation::new(user, pass, host, remote_path)));
-                    }
-                }
+    let location: Box<dyn Location> = match loc_type {
+        "folder" => parse_folder(loc_path, hash)?,
+        "zip" => parse_zip(loc_path, hash)?,
+        "ftp" => parse_ftp(loc_path, FtpSecurity::Plain, hash)?,
+        "ftps" => parse_ftp(loc_path, FtpSecurity::Implicit, hash)?,
+        "ftpes" => parse_ftp(loc_path, FtpSecurity::Explicit, hash)?,
+        "sftp" => parse_sftp(loc_path, hash)?,
+        _ => return Err(SyncError::Parse(format!("Tip necunoscut: {}", loc_type))),
+    };
+    Ok(Box::new(RetryingLocation::new(
+        location,
+        &display_name(loc_str),
+        RetryPolicy::default(),
+    )))
+}
+
+// Numele locatiei in mesaje, fara utilizator si parola
+fn display_name(loc_str: &str) -> String {
+    match (loc_str.split_once(':'), loc_str.rsplit_once('@')) {
+        (Some((loc_type, _)), Some((_, rest))) => format!("{}:{}", loc_type, rest),
+        _ => loc_str.to_string(),
+    }
+}
+
+// Optiunile locatiei vin dupa `?`, separate prin `&`: `cale?ca=ca.pem&insecure`
+fn split_options(loc_path: &str) -> (&str, Vec<(&str, Option<&str>)>) {
+    match loc_path.split_once('?') {
+        Some((loc_path, options)) => (
+            loc_path,
+            options
+                .split('&')
+                .filter(|o| !o.is_empty())
+                .map(|o| match o.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (o, None),
+                })
+                .collect(),
+        ),
+        None => (loc_path, Vec::new()),
+    }
+}
+
+// `cale[?hash-cache=<fisier>|no-hash-cache][&hash=<algoritm>]`; implicit cache-ul comun
+// din directorul de cache al utilizatorului
+fn parse_folder(loc_path: &str, hash: HashAlgorithm) -> Result<Box<dyn Location>, SyncError> {
+    let (loc_path, options) = split_options(loc_path);
+    let mut cache = HashCache::default_path();
+    let mut location = FolderLocation::new(loc_path);
+    location.hash = hash;
+    for (name, value) in options {
+        match (name, value) {
+            ("hash-cache", Some(db)) => cache = Some(PathBuf::from(db)),
+            ("no-hash-cache", None) => cache = None,
+            ("hash", Some(name)) => location.hash = HashAlgorithm::parse(name)?,
+            _ => return Err(unknown_option("folder", name)),
+        }
+    }
+    Ok(Box::new(match cache {
+        Some(db) => location.with_hash_cache(&db),
+        None => location,
+    }))
+}
+
+// `arhiva.zip[?writable][&tz=local|utc|+02:00]`
+fn parse_zip(loc_path: &str, hash: HashAlgorithm) -> Result<Box<dyn Location>, SyncError> {
+    let (loc_path, options) = split_options(loc_path);
+    let mut location = ZipLocation::new(loc_path);
+    location.hash = hash;
+    for (name, value) in options {
+        match (name, value) {
+            ("writable", None) => location.writable = true,
+            ("tz", Some("local")) => location.dos_timezone = None,
+            ("tz", Some(tz)) => location.dos_timezone = Some(parse_offset("zip", tz)?),
+            ("hash", Some(name)) => location.hash = HashAlgorithm::parse(name)?,
+            _ => return Err(unknown_option("zip", name)),
+        }
+    }
+    Ok(Box::new(location))
+}
+
+// `utc` sau `+02:00` / `-05:00`
+fn parse_offset(kind: &str, tz: &str) -> Result<FixedOffset, SyncError> {
+    if tz == "utc" {
+        return Ok(FixedOffset::east_opt(0).unwrap());
+    }
+    tz.parse()
+        .map_err(|_| SyncError::Parse(format!("Invalid {} timezone: {}", kind, tz)))
+}
+
+fn unknown_option(kind: &str, name: &str) -> SyncError {
+    SyncError::Parse(format!("Unknown {} option: {}", kind, name))
+}
+
+// `[//]user:parola@host[:port]/cale[?ca=<fisier PEM>&insecure&tz=auto|utc|+02:00&hash-cache=<fisier>]`
+fn parse_ftp(
+    loc_path: &str,
+    security: FtpSecurity,
+    hash: HashAlgorithm,
+) -> Result<Box<dyn Location>, SyncError> {
+    let invalid = || SyncError::Parse("Format ftp invalid".to_string());
+    let (loc_path, options) = split_options(loc_path);
+    let loc_path = loc_path.strip_prefix("//").unwrap_or(loc_path);
+
+    let (cred, host_path) = loc_path.rsplit_once('@').ok_or_else(invalid)?;
+    let (user, pass) = cred.split_once(':').ok_or_else(invalid)?;
+    let (host, remote_path) = host_path.split_once('/').ok_or_else(invalid)?;
+    let host = if host.contains(':') {
+        host.to_string()
+    } else {
+        let port = match security {
+            FtpSecurity::Implicit => 990,
+            _ => 21,
+        };
+        format!("{}:{}", host, port)
+    };
+
+    let mut cache = HashCache::default_path();
+    let mut location = FtpLocation::new(user, pass, &host, remote_path);
+    location.security = security;
+    location.hash = hash;
+    for (name, value) in options {
+        match (name, value) {
+            ("ca", Some(ca)) if security != FtpSecurity::Plain => {
+                location.ca_file = Some(ca.into())
+            }
+            ("insecure", None) if security != FtpSecurity::Plain => {
+                println!("WARNING: certificate verification is disabled for {}", host);
+                location.insecure = true
             }
-            Err(SyncError::Parse("Format ftp invalid".to_string()))
+            ("tz", Some("auto")) => location.tz = None,
+            ("tz", Some(tz)) => location.tz = Some(parse_offset("ftp", tz)?),
+            ("hash", Some(name)) => location.hash = HashAlgorithm::parse(name)?,
+            ("hash-cache", Some(db)) => cache = Some(PathBuf::from(db)),
+            ("no-hash-cache", None) => cache = None,
+            _ => return Err(unknown_option("ftp", name)),
+        }
+    }
+    Ok(Box::new(match cache {
+        Some(db) => location.with_hash_cache(&db),
+        None => location,
+    }))
+}
+
+// `user[:parola]@host[:port]/cale[?key=<fisier cheie privata>]`
+fn parse_sftp(loc_path: &str, hash: HashAlgorithm) -> Result<Box<dyn Location>, SyncError> {
+    let invalid = || SyncError::Parse("Format sftp invalid".to_string());
+    let (loc_path, options) = split_options(loc_path);
+
+    let (cred, host_path) = loc_path.rsplit_once('@').ok_or_else(invalid)?;
+    let (user, pass) = match cred.split_once(':') {
+        Some((user, pass)) => (user, Some(pass)),
+        None => (cred, None),
+    };
+    let (host_port, remote_path) = host_path.split_once('/').ok_or_else(invalid)?;
+    let (host, port) = match host_port.split_once(':') {
+        Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+        None => (host_port, 22),
+    };
+    if user.is_empty() || host.is_empty() {
+        return Err(invalid());
+    }
+
+    let mut location = SftpLocation::new(user, pass, host, port, remote_path);
+    location.hash = hash;
+    for (name, value) in options {
+        match (name, value) {
+            ("key", Some(key)) => location.key = Some(key.into()),
+            ("accept-new", None) => location.accept_new = true,
+            ("hash", Some(name)) => location.hash = HashAlgorithm::parse(name)?,
+            _ => return Err(unknown_option("sftp", name)),
         }
-        _ => Err(SyncError::Parse(format!("Tip necunoscut: {}", loc_type))),
     }
+    Ok(Box::new(location))
 }
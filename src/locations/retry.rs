@@ -0,0 +1,486 @@
+use crate::delta::{Delta, Signature};
+use crate::errors::SyncError;
+use crate::locations::{
+    Capabilities, DirMetadata, FileMetadata, HashAlgorithm, Location, WriteStream,
+};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Read;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Cat de insistent reincercam o operatie care a esuat temporar
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // cat stam departe de o locatie dupa ce s-au terminat incercarile (se dubleaza la fiecare
+    // pana la `max_cooldown`)
+    pub cooldown: Duration,
+    pub max_cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+            max_cooldown: Duration::from_secs(600),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Backoff exponential cu jitter: jumatate fixa, jumatate aleatoare, ca mai multe
+    // operatii esuate odata sa nu revina toate in acelasi moment
+    fn delay(&self, attempt: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let delay = exp.min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(random_fraction())
+    }
+
+    fn cooldown(&self, outages: u32) -> Duration {
+        self.cooldown
+            .saturating_mul(1 << outages.saturating_sub(1).min(16))
+            .min(self.max_cooldown)
+    }
+}
+
+// Numar aleator in [0, 1), fara sa aducem o dependinta doar pentru asta
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hasher.write_u128(now.as_nanos());
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[derive(Default)]
+struct Availability {
+    unavailable_until: Option<Instant>,
+    // de cate ori la rand am renuntat la locatie
+    outages: u32,
+}
+
+// Invelis peste orice locatie: reincearca erorile temporare si, cand nu mai are rost,
+// marcheaza locatia ca indisponibila pentru o vreme, ca restul sa se sincronizeze in continuare
+pub struct RetryingLocation {
+    inner: Box<dyn Location>,
+    name: String,
+    policy: RetryPolicy,
+    availability: Mutex<Availability>,
+}
+
+impl RetryingLocation {
+    pub fn new(inner: Box<dyn Location>, name: &str, policy: RetryPolicy) -> Self {
+        RetryingLocation {
+            inner,
+            name: name.to_string(),
+            policy,
+            availability: Mutex::new(Availability::default()),
+        }
+    }
+
+    // Erorile ies de aici cu locatia, operatia si calea completate
+    fn retry<T>(
+        &self,
+        operation: &str,
+        path: &str,
+        op: impl FnMut() -> Result<T, SyncError>,
+    ) -> Result<T, SyncError> {
+        self.attempt(operation, op).map_err(|e| {
+            let e = e.in_location(&self.name).during(operation);
+            if path.is_empty() {
+                e
+            } else {
+                e.at(path)
+            }
+        })
+    }
+
+    fn attempt<T>(
+        &self,
+        operation: &str,
+        mut op: impl FnMut() -> Result<T, SyncError>,
+    ) -> Result<T, SyncError> {
+        if let Some(until) = self.availability.lock().unwrap().unavailable_until {
+            if Instant::now() < until {
+                return Err(SyncError::unavailable(format!(
+                    "unavailable for another {}s",
+                    (until - Instant::now()).as_secs()
+                )));
+            }
+        }
+
+        let mut attempt = 1;
+        loop {
+            match op() {
+                Ok(value) => {
+                    let mut availability = self.availability.lock().unwrap();
+                    if availability.outages > 0 {
+                        println!("Location {} is available again", self.name);
+                    }
+                    *availability = Availability::default();
+                    return Ok(value);
+                }
+                Err(e) if !e.is_retryable() => return Err(e),
+                Err(e) if attempt >= self.policy.max_attempts => {
+                    let mut availability = self.availability.lock().unwrap();
+                    availability.outages += 1;
+                    let cooldown = self.policy.cooldown(availability.outages);
+                    availability.unavailable_until = Some(Instant::now() + cooldown);
+                    println!(
+                        "{} on {} failed {} times ({}), marking it unavailable for {}s",
+                        operation,
+                        self.name,
+                        attempt,
+                        e,
+                        cooldown.as_secs()
+                    );
+                    return Err(e);
+                }
+                Err(e) => {
+                    let delay = self.policy.delay(attempt);
+                    println!(
+                        "{} on {} failed ({}), retrying in {}ms (attempt {}/{})",
+                        operation,
+                        self.name,
+                        e,
+                        delay.as_millis(),
+                        attempt + 1,
+                        self.policy.max_attempts
+                    );
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn renamed(&self, from: &str, to: &str) -> bool {
+        matches!(
+            (self.inner.stat_file(from), self.inner.stat_file(to)),
+            (Ok(None), Ok(Some(_)))
+        )
+    }
+}
+
+impl Location for RetryingLocation {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
+        self.retry("list", "", || self.inner.list_files())
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        self.retry("read", path, || self.inner.read_file(path))
+    }
+
+    // scrierea o ia mereu de la capat si inlocuieste tot fisierul, asa ca o incercare
+    // repetata dupa una care a mers (dar i s-a pierdut raspunsul) doar scrie acelasi continut
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError> {
+        self.retry("write", path, || self.inner.write_file(path, data))
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), SyncError> {
+        self.retry("delete", path, || self.inner.delete_file(path))
+    }
+
+    fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
+        self.retry("list", "", || self.inner.list_files_recursive())
+    }
+
+    // pentru fluxuri reincercam doar deschiderea; un transfer intrerupt la jumatate
+    // se reia de la capat la urmatoarea trecere
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        self.retry("open for reading", path, || self.inner.open_read(path))
+    }
+
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
+        self.retry("open for writing", path, || self.inner.open_write(path))
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), SyncError> {
+        self.retry("create dir", path, || self.inner.create_dir(path))
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
+        self.retry("remove dir", path, || self.inner.remove_dir(path))
+    }
+
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        self.retry("stat", path, || self.inner.stat_file(path))
+    }
+
+    fn list_dirs_recursive(&self) -> Result<Vec<DirMetadata>, SyncError> {
+        self.retry("list dirs", "", || self.inner.list_dirs_recursive())
+    }
+
+    // Un rename nu se poate repeta: daca a mers pe server si s-a pierdut doar raspunsul,
+    // urmatoarea incercare nu mai gaseste sursa. Dupa orice esec ne uitam intai daca
+    // sursa a disparut si destinatia exista, adica rename-ul s-a facut deja.
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        self.retry("rename", from, || {
+            self.inner.rename(from, to).or_else(|e| {
+                if self.renamed(from, to) {
+                    println!(
+                        "Rename '{}' -> '{}' on {} went through, only its reply was lost",
+                        from, to, self.name
+                    );
+                    Ok(())
+                } else {
+                    Err(e)
+                }
+            })
+        })
+    }
+
+    fn set_modified(&self, path: &str, modified: SystemTime) -> Result<(), SyncError> {
+        self.retry("set mtime", path, || {
+            self.inner.set_modified(path, modified)
+        })
+    }
+
+    fn content_hash(&self, path: &str, algorithm: HashAlgorithm) -> Result<String, SyncError> {
+        self.retry("hash", path, || self.inner.content_hash(path, algorithm))
+    }
+
+    fn block_signature(&self, path: &str) -> Result<Option<Signature>, SyncError> {
+        self.retry("signature", path, || self.inner.block_signature(path))
+    }
+
+    fn apply_delta(&self, path: &str, delta: &Delta) -> Result<bool, SyncError> {
+        self.retry("apply delta", path, || self.inner.apply_delta(path, delta))
+    }
+
+    // ce e de scris la flush e deja local (arhiva zip); nu il blocam cat locatia e indisponibila
+    fn flush(&self) -> Result<(), SyncError> {
+        self.inner.flush()
+    }
+
+    fn host(&self) -> String {
+        self.inner.host()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ErrorContext;
+    use crate::locations::FolderLocation;
+    use crate::test_util::temp_root;
+    use std::fs;
+    use std::sync::Arc;
+
+    // Un folder la care raspunsul primelor `lost` rename-uri se pierde dupa ce s-au facut
+    struct LostReplies {
+        inner: FolderLocation,
+        lost: Mutex<u32>,
+    }
+
+    impl Location for LostReplies {
+        fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
+            self.inner.list_files()
+        }
+
+        fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+            self.inner.read_file(path)
+        }
+
+        fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError> {
+            self.inner.write_file(path, data)
+        }
+
+        fn delete_file(&self, path: &str) -> Result<(), SyncError> {
+            self.inner.delete_file(path)
+        }
+
+        fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+            self.inner.stat_file(path)
+        }
+
+        fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+            self.inner.rename(from, to)?;
+            let mut lost = self.lost.lock().unwrap();
+            if *lost > 0 {
+                *lost -= 1;
+                return Err(SyncError::Connection(
+                    ErrorContext::new("connection reset").into(),
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn rename_with_lost_reply_is_not_reported_as_failed() {
+        let root = temp_root("retry_rename");
+        fs::write(root.join("old.txt"), "content").unwrap();
+
+        let inner = LostReplies {
+            inner: FolderLocation::new(root.to_str().unwrap()),
+            lost: Mutex::new(1),
+        };
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        };
+        let location = RetryingLocation::new(Box::new(inner), "test", policy);
+
+        location.rename("old.txt", "new.txt").unwrap();
+        assert!(!root.join("old.txt").exists());
+        assert_eq!(fs::read_to_string(root.join("new.txt")).unwrap(), "content");
+
+        // un rename care chiar nu se poate face ramane o eroare
+        assert!(location.rename("missing.txt", "other.txt").is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // O locatie care esueaza cu erorile date, in ordine, apoi merge; numara apelurile
+    struct Scripted {
+        errors: Mutex<Vec<SyncError>>,
+        calls: Arc<Mutex<u32>>,
+    }
+
+    impl Location for Scripted {
+        fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
+            *self.calls.lock().unwrap() += 1;
+            let mut errors = self.errors.lock().unwrap();
+            if errors.is_empty() {
+                Ok(Vec::new())
+            } else {
+                Err(errors.remove(0))
+            }
+        }
+
+        fn read_file(&self, _path: &str) -> Result<Vec<u8>, SyncError> {
+            unimplemented!()
+        }
+
+        fn write_file(&self, _path: &str, _data: &[u8]) -> Result<(), SyncError> {
+            unimplemented!()
+        }
+
+        fn delete_file(&self, _path: &str) -> Result<(), SyncError> {
+            unimplemented!()
+        }
+    }
+
+    fn reset() -> SyncError {
+        SyncError::Connection(ErrorContext::new("connection reset").into())
+    }
+
+    fn scripted(
+        errors: Vec<SyncError>,
+        policy: RetryPolicy,
+    ) -> (RetryingLocation, Arc<Mutex<u32>>) {
+        let calls = Arc::new(Mutex::new(0));
+        let inner = Scripted {
+            errors: Mutex::new(errors),
+            calls: calls.clone(),
+        };
+        (
+            RetryingLocation::new(Box::new(inner), "test", policy),
+            calls,
+        )
+    }
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            cooldown: Duration::from_secs(3600),
+            max_cooldown: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn transient_error_is_retried_until_it_succeeds() {
+        let (location, calls) = scripted(vec![reset(), reset()], fast_policy());
+        assert!(location.list_files().is_ok());
+        assert_eq!(*calls.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn permanent_error_is_returned_at_once() {
+        let (location, calls) = scripted(vec![SyncError::not_found("gone")], fast_policy());
+        assert!(matches!(location.list_files(), Err(SyncError::NotFound(_))));
+        assert_eq!(*calls.lock().unwrap(), 1);
+        // si nu scoate locatia din uz
+        assert!(location.list_files().is_ok());
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn location_is_unavailable_after_max_attempts() {
+        let (location, calls) = scripted(vec![reset(), reset(), reset()], fast_policy());
+        assert!(matches!(
+            location.list_files(),
+            Err(SyncError::Connection(_))
+        ));
+        assert_eq!(*calls.lock().unwrap(), 3);
+
+        // in pauza apelurile nu mai ajung la locatie
+        assert!(matches!(
+            location.list_files(),
+            Err(SyncError::Unavailable(_))
+        ));
+        assert_eq!(*calls.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn location_is_tried_again_after_the_cooldown() {
+        let policy = RetryPolicy {
+            cooldown: Duration::from_millis(20),
+            ..fast_policy()
+        };
+        let (location, calls) = scripted(vec![reset(), reset(), reset()], policy);
+        assert!(location.list_files().is_err());
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(location.list_files().is_ok());
+        assert_eq!(*calls.lock().unwrap(), 4);
+        assert_eq!(location.availability.lock().unwrap().outages, 0);
+    }
+
+    #[test]
+    fn delay_and_cooldown_stay_within_limits() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            ..RetryPolicy::default()
+        };
+        let ms = Duration::from_millis;
+        // jumatate fixa, jumatate aleatoare din backoff-ul exponential
+        for (attempt, full) in [(1, ms(100)), (2, ms(200)), (4, ms(800)), (5, ms(1000))] {
+            for _ in 0..20 {
+                let delay = policy.delay(attempt);
+                assert!(
+                    delay >= full / 2 && delay <= full,
+                    "{}: {:?}",
+                    attempt,
+                    delay
+                );
+            }
+        }
+        // fara overflow la un numar mare de incercari
+        assert!(policy.delay(u32::MAX) <= ms(1000));
+
+        let secs = Duration::from_secs;
+        assert_eq!(policy.cooldown(1), secs(30));
+        assert_eq!(policy.cooldown(2), secs(60));
+        assert_eq!(policy.cooldown(5), secs(480));
+        assert_eq!(policy.cooldown(6), secs(600));
+        assert_eq!(policy.cooldown(u32::MAX), secs(600));
+    }
+}
@@ -0,0 +1,692 @@
+use super::{
+    is_transfer_temp, Capabilities, DirMetadata, FileMetadata, HashAlgorithm, Location, WriteStream,
+};
+use crate::errors::{ErrorContext, SyncError};
+use sha2::{Digest, Sha256};
+use ssh2::{CheckResult, ErrorCode, File, FileStat, KnownHostFileKind, RenameFlags, Session, Sftp};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// LIBSSH2_FX_*
+const SFTP_NO_SUCH_FILE: i32 = 2;
+const SFTP_PERMISSION_DENIED: i32 = 3;
+const SFTP_NO_CONNECTION: i32 = 6;
+const SFTP_CONNECTION_LOST: i32 = 7;
+const SFTP_NO_SUCH_PATH: i32 = 10;
+const SFTP_FILE_ALREADY_EXISTS: i32 = 11;
+const SFTP_WRITE_PROTECT: i32 = 12;
+
+// LIBSSH2_ERROR_*
+const SESSION_SOCKET_SEND: i32 = -7;
+const SESSION_TIMEOUT: i32 = -9;
+const SESSION_SOCKET_DISCONNECT: i32 = -13;
+const SESSION_AUTHENTICATION_FAILED: i32 = -18;
+const SESSION_PUBLICKEY_UNVERIFIED: i32 = -19;
+const SESSION_SOCKET_TIMEOUT: i32 = -30;
+const SESSION_SOCKET_RECV: i32 = -43;
+
+pub struct SftpLocation {
+    pub user: String,
+    // fara parola incercam ssh-agent si cheile din ~/.ssh
+    pub pass: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub path: String, // folder remote de unde facem sync
+    pub key: Option<PathBuf>,
+    pub hash: HashAlgorithm,
+    // `?accept-new`: o cheie necunoscuta e acceptata si scrisa in known_hosts, ca la
+    // `StrictHostKeyChecking=accept-new`; o cheie diferita e refuzata oricum
+    pub accept_new: bool,
+    // o singura sesiune SSH pe locatie, deschisa la prima operatie si refolosita
+    session: Mutex<Option<Arc<Sftp>>>,
+    // hash-urile deja calculate, ca sa nu citim fisierele nemodificate la fiecare poll
+    known: Mutex<HashMap<String, KnownFile>>,
+}
+
+// Un fisier modificat chiar acum poate fi scris din nou in aceeasi secunda, fara sa i
+// se schimbe mtime-ul; hash-ul lui nu il tinem minte
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+struct KnownFile {
+    size: u64,
+    mtime: u64,
+    hash: String,
+}
+
+impl SftpLocation {
+    pub fn new(user: &str, pass: Option<&str>, host: &str, port: u16, path: &str) -> Self {
+        SftpLocation {
+            user: user.to_string(),
+            pass: pass.map(|p| p.to_string()),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+            key: None,
+            hash: HashAlgorithm::default(),
+            accept_new: false,
+            session: Mutex::new(None),
+            known: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Sesiunea deschisa, sau una noua daca nu avem inca
+    fn sftp(&self) -> Result<Arc<Sftp>, SyncError> {
+        let mut session = self.session.lock().unwrap();
+        if let Some(sftp) = session.as_ref() {
+            return Ok(sftp.clone());
+        }
+        let sftp = Arc::new(self.connect()?);
+        *session = Some(sftp.clone());
+        Ok(sftp)
+    }
+
+    // Dupa o eroare de conexiune sesiunea nu mai e buna; urmatoarea operatie deschide alta
+    fn forget_session(&self, e: &SyncError) {
+        if matches!(e, SyncError::Connection(_) | SyncError::Timeout(_)) {
+            println!(
+                "SFTP connection to {} lost ({}), reconnecting",
+                self.host, e
+            );
+            *self.session.lock().unwrap() = None;
+        }
+    }
+
+    // Ruleaza `op` pe sesiunea deschisa; daca s-a pierdut conexiunea (ex. serverul a
+    // inchis-o dupa o pauza) reincearca o data pe o sesiune noua
+    fn with_sftp<T>(
+        &self,
+        mut op: impl FnMut(&Sftp) -> Result<T, SyncError>,
+    ) -> Result<T, SyncError> {
+        match op(&*self.sftp()?) {
+            Err(e @ (SyncError::Connection(_) | SyncError::Timeout(_))) => {
+                self.forget_session(&e);
+                op(&*self.sftp()?)
+            }
+            result => result,
+        }
+    }
+
+    // Hash-ul unui fisier; cat timp size+mtime raman la fel il refolosim pe cel calculat deja
+    fn file_hash(
+        &self,
+        sftp: &Sftp,
+        path: &str,
+        full_path: &Path,
+        stat: &FileStat,
+    ) -> Option<String> {
+        let (Some(size), Some(mtime)) = (stat.size, stat.mtime) else {
+            return hash_remote_file(sftp, full_path, self.hash).ok();
+        };
+        let mut known = self.known.lock().unwrap();
+        if let Some(file) = known.get(path) {
+            if file.size == size && file.mtime == mtime {
+                return Some(file.hash.clone());
+            }
+        }
+        let hash = hash_remote_file(sftp, full_path, self.hash).ok();
+        let settled = SystemTime::now() - RACY_WINDOW;
+        match &hash {
+            Some(hash) if UNIX_EPOCH + Duration::from_secs(mtime) < settled => {
+                known.insert(
+                    path.to_string(),
+                    KnownFile {
+                        size,
+                        mtime,
+                        hash: hash.clone(),
+                    },
+                );
+            }
+            _ => {
+                known.remove(path);
+            }
+        }
+        hash
+    }
+
+    fn connect(&self) -> Result<Sftp, SyncError> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))?;
+        let mut session = Session::new().map_err(sftp_error)?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(30_000);
+        session.handshake().map_err(sftp_error)?;
+
+        self.check_host_key(&session)?;
+        self.authenticate(&session)?;
+
+        session.sftp().map_err(sftp_error)
+    }
+
+    // Verificam cheia serverului in ~/.ssh/known_hosts; o cheie diferita inseamna ca nu
+    // vorbim cu cine credem, asa ca refuzam conexiunea. Una necunoscuta o acceptam doar
+    // cu `?accept-new`.
+    fn check_host_key(&self, session: &Session) -> Result<(), SyncError> {
+        let (key, key_type) = session
+            .host_key()
+            .ok_or_else(|| SyncError::protocol(format!("{} sent no host key", self.host)))?;
+
+        let mut known_hosts = session.known_hosts().map_err(sftp_error)?;
+        let file = home_dir().map(|h| h.join(".ssh").join("known_hosts"));
+        if let Some(file) = file.as_ref().filter(|f| f.is_file()) {
+            known_hosts
+                .read_file(file, KnownHostFileKind::OpenSSH)
+                .map_err(sftp_error)?;
+        }
+
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => {
+                let mut hasher = Sha256::new();
+                hasher.update(key);
+                let fingerprint = hex::encode(hasher.finalize());
+                if !self.accept_new {
+                    return Err(SyncError::permission_denied(format!(
+                        "Host key for {}:{} is not in known_hosts (SHA256 {}); \
+                         add it with ssh-keyscan or use ?accept-new",
+                        self.host, self.port, fingerprint
+                    )));
+                }
+                let file = file.ok_or_else(|| {
+                    SyncError::permission_denied(format!(
+                        "No home directory to save the host key for {}",
+                        self.host
+                    ))
+                })?;
+                // acelasi format ca OpenSSH: portul apare doar daca nu e 22
+                let entry = if self.port == 22 {
+                    self.host.clone()
+                } else {
+                    format!("[{}]:{}", self.host, self.port)
+                };
+                known_hosts
+                    .add(&entry, key, "", key_type.into())
+                    .map_err(sftp_error)?;
+                if let Some(dir) = file.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                known_hosts
+                    .write_file(&file, KnownHostFileKind::OpenSSH)
+                    .map_err(|e| {
+                        sftp_error(e)
+                            .during("save host key")
+                            .at(&file.to_string_lossy())
+                    })?;
+                println!(
+                    "Added host key for {}:{} (SHA256 {}) to {}",
+                    self.host,
+                    self.port,
+                    fingerprint,
+                    file.display()
+                );
+                Ok(())
+            }
+            CheckResult::Mismatch => Err(SyncError::permission_denied(format!(
+                "Host key for {}:{} does not match known_hosts",
+                self.host, self.port
+            ))),
+            CheckResult::Failure => Err(SyncError::protocol(format!(
+                "Could not check host key for {}:{}",
+                self.host, self.port
+            ))),
+        }
+    }
+
+    fn authenticate(&self, session: &Session) -> Result<(), SyncError> {
+        if let Some(pass) = &self.pass {
+            session
+                .userauth_password(&self.user, pass)
+                .map_err(sftp_error)?;
+        } else if let Some(key) = &self.key {
+            session
+                .userauth_pubkey_file(&self.user, None, key, None)
+                .map_err(sftp_error)?;
+        } else {
+            // ssh-agent, apoi cheile implicite
+            if session.userauth_agent(&self.user).is_err() {
+                let ssh_dir = home_dir().map(|h| h.join(".ssh")).unwrap_or_default();
+                for name in ["id_ed25519", "id_ecdsa", "id_rsa"] {
+                    let key = ssh_dir.join(name);
+                    if key.is_file()
+                        && session
+                            .userauth_pubkey_file(&self.user, None, &key, None)
+                            .is_ok()
+                    {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if !session.authenticated() {
+            return Err(SyncError::permission_denied(format!(
+                "Authentication failed for {}@{}",
+                self.user, self.host
+            )));
+        }
+        Ok(())
+    }
+
+    fn full_path(&self, path: &str) -> PathBuf {
+        if self.path.is_empty() || self.path == "." {
+            PathBuf::from(path)
+        } else {
+            Path::new(&self.path).join(path)
+        }
+    }
+
+    fn root(&self) -> PathBuf {
+        self.full_path("")
+    }
+
+    fn list_recursive_sftp(
+        &self,
+        sftp: &Sftp,
+        relative_path: &str,
+        files: &mut Vec<FileMetadata>,
+        dirs: &mut Vec<DirMetadata>,
+        with_hashes: bool,
+    ) -> Result<(), SyncError> {
+        let dir = self.full_path(relative_path);
+        let dir = if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir
+        };
+        let entries = sftp
+            .readdir(&dir)
+            .map_err(|e| sftp_error(e).during("readdir").at(&dir.to_string_lossy()))?;
+
+        for (entry_path, stat) in entries {
+            let name = match entry_path.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => continue,
+            };
+            if name == "." || name == ".." || is_transfer_temp(&entry_path) {
+                continue;
+            }
+            let child = if relative_path.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative_path, name)
+            };
+            let modified = UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0));
+
+            if stat.is_dir() {
+                dirs.push(DirMetadata {
+                    path: child.clone(),
+                    modified,
+                });
+                self.list_recursive_sftp(sftp, &child, files, dirs, with_hashes)?;
+            } else if stat.is_file() {
+                let hash = if with_hashes {
+                    self.file_hash(sftp, &child, &entry_path, &stat)
+                } else {
+                    None
+                };
+                files.push(FileMetadata {
+                    path: child,
+                    modified,
+                    size: stat.size.unwrap_or(0),
+                    hash,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Creeaza tot lantul de directoare pana la `dir`
+    fn make_dirs(&self, sftp: &Sftp, dir: &Path) -> Result<(), SyncError> {
+        let mut current = PathBuf::new();
+        for component in dir.components() {
+            current.push(component);
+            if sftp.stat(&current).is_ok_and(|s| s.is_dir()) {
+                continue;
+            }
+            match sftp.mkdir(&current, 0o755) {
+                Ok(()) => println!("Created subdir: {}", current.display()),
+                // poate l-a creat altcineva intre timp
+                Err(_) if sftp.stat(&current).is_ok_and(|s| s.is_dir()) => {}
+                Err(e) => {
+                    return Err(sftp_error(e).during("mkdir").at(&current.to_string_lossy()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_dir_sftp(&self, sftp: &Sftp, dir: &Path) -> Result<(), SyncError> {
+        let entries = sftp
+            .readdir(dir)
+            .map_err(|e| sftp_error(e).during("readdir").at(&dir.to_string_lossy()))?;
+        for (entry_path, stat) in entries {
+            if stat.is_dir() {
+                self.remove_dir_sftp(sftp, &entry_path)?;
+            } else {
+                sftp.unlink(&entry_path).map_err(|e| {
+                    sftp_error(e)
+                        .during("unlink")
+                        .at(&entry_path.to_string_lossy())
+                })?;
+            }
+        }
+        sftp.rmdir(dir)
+            .map_err(|e| sftp_error(e).during("rmdir").at(&dir.to_string_lossy()))
+    }
+}
+
+// Scriem intr-un fisier temporar pe server si il mutam peste cel final la sfarsit
+struct SftpWriteStream {
+    file: File,
+    sftp: Arc<Sftp>,
+    tmp_path: PathBuf,
+    final_path: PathBuf,
+    // mutat peste cel final; altfel `Drop` sterge fisierul temporar
+    finished: bool,
+}
+
+impl Write for SftpWriteStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl WriteStream for SftpWriteStream {
+    fn finish(mut self: Box<Self>) -> Result<(), SyncError> {
+        self.file.flush()?;
+        self.file.close().map_err(sftp_error)?;
+        replace_file(&self.sftp, &self.tmp_path, &self.final_path)?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+// O copiere abandonata nu lasa `.nume.part` pe server
+impl Drop for SftpWriteStream {
+    fn drop(&mut self) {
+        if !self.finished {
+            if let Err(e) = self.sftp.unlink(&self.tmp_path) {
+                println!(
+                    "Failed to remove the partial upload {}: {}",
+                    self.tmp_path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+impl Location for SftpLocation {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            writable: true,
+            set_mtime: true,
+            hash: self.hash,
+            native_hashes: vec![],
+            watch_root: None,
+            rename: true,
+            case_sensitive: true,
+            permissions: true,
+        }
+    }
+
+    fn host(&self) -> String {
+        self.host.clone()
+    }
+
+    fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
+        self.with_sftp(|sftp| {
+            let mut files = Vec::new();
+            let root = self.root();
+            let entries = sftp
+                .readdir(if root.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    &root
+                })
+                .map_err(sftp_error)?;
+            for (entry_path, stat) in entries {
+                if !stat.is_file() || is_transfer_temp(&entry_path) {
+                    continue;
+                }
+                if let Some(name) = entry_path.file_name() {
+                    let name = name.to_string_lossy().to_string();
+                    files.push(FileMetadata {
+                        hash: self.file_hash(sftp, &name, &entry_path, &stat),
+                        path: name,
+                        modified: UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0)),
+                        size: stat.size.unwrap_or(0),
+                    });
+                }
+            }
+            Ok(files)
+        })
+    }
+
+    fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
+        let files = self.with_sftp(|sftp| {
+            let mut files = Vec::new();
+            let mut dirs = Vec::new();
+            self.list_recursive_sftp(sftp, "", &mut files, &mut dirs, true)?;
+            Ok(files)
+        })?;
+        // uitam fisierele care nu mai exista
+        let listed: HashSet<&str> = files.iter().map(|f| f.path.as_str()).collect();
+        self.known
+            .lock()
+            .unwrap()
+            .retain(|path, _| listed.contains(path.as_str()));
+        Ok(files)
+    }
+
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        let full = self.full_path(path);
+        self.with_sftp(|sftp| {
+            let stat = match sftp.stat(&full).map_err(sftp_error) {
+                Ok(stat) if stat.is_file() => stat,
+                Ok(_) | Err(SyncError::NotFound(_)) => return Ok(None),
+                Err(e) => return Err(e.during("stat").at(path)),
+            };
+            Ok(Some(FileMetadata {
+                path: path.to_string(),
+                modified: UNIX_EPOCH + Duration::from_secs(stat.mtime.unwrap_or(0)),
+                size: stat.size.unwrap_or(0),
+                hash: self.file_hash(sftp, path, &full, &stat),
+            }))
+        })
+    }
+
+    fn list_dirs_recursive(&self) -> Result<Vec<DirMetadata>, SyncError> {
+        self.with_sftp(|sftp| {
+            let mut files = Vec::new();
+            let mut dirs = Vec::new();
+            self.list_recursive_sftp(sftp, "", &mut files, &mut dirs, false)?;
+            Ok(dirs)
+        })
+    }
+
+    fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        let mut buffer = Vec::new();
+        self.open_read(path)?.read_to_end(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        let full_path = self.full_path(path);
+        let file = self.with_sftp(|sftp| {
+            sftp.open(&full_path)
+                .map_err(|e| sftp_error(e).during("open").at(path))
+        })?;
+        Ok(Box::new(BufReader::with_capacity(64 * 1024, file)))
+    }
+
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError> {
+        let mut writer = self.open_write(path)?;
+        writer.write_all(data)?;
+        writer.finish()
+    }
+
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
+        let final_path = self.full_path(path);
+        let file_name = final_path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = final_path.with_file_name(format!(".{}.part", file_name));
+
+        let file = self.with_sftp(|sftp| {
+            if let Some(parent) = final_path.parent() {
+                self.make_dirs(sftp, parent)?;
+            }
+            sftp.create(&tmp_path).map_err(|e| {
+                sftp_error(e)
+                    .during("create")
+                    .at(&tmp_path.to_string_lossy())
+            })
+        })?;
+
+        Ok(Box::new(SftpWriteStream {
+            file,
+            sftp: self.sftp()?,
+            tmp_path,
+            final_path,
+            finished: false,
+        }))
+    }
+
+    fn delete_file(&self, path: &str) -> Result<(), SyncError> {
+        let full_path = self.full_path(path);
+        self.with_sftp(|sftp| match sftp.unlink(&full_path) {
+            Ok(()) => {
+                println!("Deleted file {} on SFTP", path);
+                Ok(())
+            }
+            Err(e) if e.code() == ErrorCode::SFTP(SFTP_NO_SUCH_FILE) => {
+                println!("File {} does not exist or already deleted", path);
+                Ok(())
+            }
+            Err(e) => Err(sftp_error(e).during("delete").at(path)),
+        })
+    }
+
+    // Fara reincercare pe o sesiune noua: daca rename-ul a ajuns la server inainte sa
+    // se piarda conexiunea, a doua incercare n-ar mai gasi sursa
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        let sftp = self.sftp()?;
+        let to_path = self.full_path(to);
+        let result = match to_path.parent() {
+            Some(parent) => self.make_dirs(&sftp, parent),
+            None => Ok(()),
+        }
+        .and_then(|_| replace_file(&sftp, &self.full_path(from), &to_path));
+        if let Err(e) = &result {
+            self.forget_session(e);
+        }
+        result
+    }
+
+    // SFTP are doar secunde
+    fn set_modified(&self, path: &str, modified: SystemTime) -> Result<(), SyncError> {
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let full_path = self.full_path(path);
+        self.with_sftp(|sftp| {
+            let stat = FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: None,
+                atime: Some(secs),
+                mtime: Some(secs),
+            };
+            sftp.setstat(&full_path, stat)
+                .map_err(|e| sftp_error(e).during("setstat").at(path))
+        })
+    }
+
+    fn create_dir(&self, path: &str) -> Result<(), SyncError> {
+        let full_path = self.full_path(path);
+        self.with_sftp(|sftp| self.make_dirs(sftp, &full_path))
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
+        let full_path = self.full_path(path);
+        self.with_sftp(|sftp| {
+            if sftp.stat(&full_path).is_ok_and(|s| s.is_dir()) {
+                self.remove_dir_sftp(sftp, &full_path)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+// Serverele SFTP v3 (OpenSSH) nu suprascriu la rename, asa ca stergem intai destinatia
+fn replace_file(sftp: &Sftp, from: &Path, to: &Path) -> Result<(), SyncError> {
+    let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+    if sftp.rename(from, to, Some(flags)).is_ok() {
+        return Ok(());
+    }
+    if sftp.stat(to).is_ok() {
+        sftp.unlink(to)
+            .map_err(|e| sftp_error(e).during("replace").at(&to.to_string_lossy()))?;
+    }
+    sftp.rename(from, to, Some(flags)).map_err(|e| {
+        sftp_error(e)
+            .during("rename")
+            .at(&format!("{} -> {}", from.display(), to.display()))
+    })
+}
+
+fn hash_remote_file(
+    sftp: &Sftp,
+    path: &Path,
+    algorithm: HashAlgorithm,
+) -> Result<String, SyncError> {
+    let file = sftp.open(path).map_err(sftp_error)?;
+    Ok(algorithm.hash_reader(file)?)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(PathBuf::from)
+}
+
+// Codurile libssh2: pe cele de sesiune (socket, timeout) le putem reincerca,
+// iar cele SFTP spun ce s-a intamplat cu fisierul
+fn sftp_error(e: ssh2::Error) -> SyncError {
+    let context = ErrorContext::new(e.to_string());
+    match e.code() {
+        ErrorCode::SFTP(SFTP_NO_SUCH_FILE | SFTP_NO_SUCH_PATH) => {
+            SyncError::NotFound(context.with_source(e).into())
+        }
+        ErrorCode::SFTP(SFTP_PERMISSION_DENIED | SFTP_WRITE_PROTECT) => {
+            SyncError::PermissionDenied(context.with_source(e).into())
+        }
+        ErrorCode::SFTP(SFTP_FILE_ALREADY_EXISTS) => {
+            SyncError::Conflict(context.with_source(e).into())
+        }
+        ErrorCode::SFTP(SFTP_NO_CONNECTION | SFTP_CONNECTION_LOST) => {
+            SyncError::Connection(context.with_source(e).into())
+        }
+        ErrorCode::Session(SESSION_TIMEOUT | SESSION_SOCKET_TIMEOUT) => {
+            SyncError::Timeout(context.with_source(e).into())
+        }
+        ErrorCode::Session(
+            SESSION_SOCKET_SEND | SESSION_SOCKET_DISCONNECT | SESSION_SOCKET_RECV,
+        ) => SyncError::Connection(context.with_source(e).into()),
+        ErrorCode::Session(SESSION_AUTHENTICATION_FAILED | SESSION_PUBLICKEY_UNVERIFIED) => {
+            SyncError::PermissionDenied(context.with_source(e).into())
+        }
+        _ => SyncError::ProtocolError {
+            context: context.with_source(e).into(),
+            transient: false,
+        },
+    }
+}
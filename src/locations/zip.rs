@@ -1,23 +1,333 @@
-use super::{DirMetadata, FileMetadata, Location};
-use crate::errors::SyncError;
-use sha2::{Digest, Sha256};
-use std::any::Any;
-use std::collections::HashSet;
+use super::{Capabilities, DirMetadata, FileMetadata, HashAlgorithm, Location, WriteStream};
+use crate::errors::{ErrorContext, SyncError};
+use chrono::{Datelike, FixedOffset, Local, NaiveDate, TimeZone, Timelike, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
-use zip::ZipArchive;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zip::read::ZipFile;
+use zip::result::ZipError;
+use zip::write::FullFileOptions;
+use zip::{CompressionMethod, ExtraField, ZipArchive, ZipWriter};
+
+// extra field "UT": timpul modificarii in secunde UTC
+const EXTENDED_TIMESTAMP_ID: u16 = 0x5455;
+// secunde intre 1601-01-01 (epoca NTFS) si 1970-01-01
+const NTFS_EPOCH_OFFSET: u64 = 11_644_473_600;
 
 pub struct ZipLocation {
     pub path: PathBuf,
+    // implicit arhiva e doar sursa; `zip:arhiva.zip?writable` o face si destinatie
+    pub writable: bool,
+    // fusul orar al timpilor DOS (fara zona); None = ora locala a masinii
+    pub dos_timezone: Option<FixedOffset>,
+    pub hash: HashAlgorithm,
+    // modificari adunate pana la urmatorul `flush`, cand rescriem arhiva o singura data
+    pending: Mutex<PendingEntries>,
+    // hash-urile intrarilor deja decomprimate; o intrare cu acelasi nume, CRC32 si
+    // dimensiune are acelasi continut, asa ca nu o mai citim la urmatorul listing
+    hashes: Mutex<HashMap<EntryKey, String>>,
+}
+
+// nume, CRC32, dimensiune, algoritm
+type EntryKey = (String, u32, u64, HashAlgorithm);
+
+// Ce se intampla cu o intrare la rescrierea arhivei
+enum PendingEntry {
+    // continut nou, pastrat pana atunci intr-un fisier temporar
+    Write(PathBuf),
+    // intrarea veche cu alt nume (redenumire), copiata fara recompresie
+    CopyOf(String),
+    Dir,
+    Delete,
+}
+
+#[derive(Default)]
+struct PendingEntries {
+    entries: BTreeMap<String, PendingEntry>,
+    next_staged: usize,
+    // ultima rescriere a esuat: arhiva de pe disc nu mai arata ce am scris in ea
+    failed: bool,
+}
+
+struct ZipWriteStream<'a> {
+    writer: BufWriter<File>,
+    staged: PathBuf,
+    name: String,
+    location: &'a ZipLocation,
+}
+
+impl Write for ZipWriteStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl WriteStream for ZipWriteStream<'_> {
+    fn finish(mut self: Box<Self>) -> Result<(), SyncError> {
+        self.writer.flush()?;
+        let mut pending = self.location.pending.lock().unwrap();
+        let old = pending
+            .entries
+            .insert(self.name.clone(), PendingEntry::Write(self.staged.clone()));
+        if let Some(PendingEntry::Write(old)) = old {
+            let _ = std::fs::remove_file(old);
+        }
+        Ok(())
+    }
 }
 
 impl ZipLocation {
     pub fn new(path: &str) -> Self {
         ZipLocation {
             path: PathBuf::from(path),
+            writable: false,
+            dos_timezone: None,
+            hash: HashAlgorithm::default(),
+            pending: Mutex::new(PendingEntries::default()),
+            hashes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // CRC32 e deja in arhiva, il luam fara sa decomprimam; altfel citim intrarea o
+    // singura data si tinem minte hash-ul cat timp CRC-ul si dimensiunea ei raman la fel
+    fn entry_hash(
+        &self,
+        entry: &mut ZipFile,
+        algorithm: HashAlgorithm,
+    ) -> Result<String, SyncError> {
+        if algorithm == HashAlgorithm::Crc32 {
+            return Ok(algorithm.tag(&format!("{:08x}", entry.crc32())));
+        }
+        let key = (
+            entry.name().to_string(),
+            entry.crc32(),
+            entry.size(),
+            algorithm,
+        );
+        if let Some(hash) = self.hashes.lock().unwrap().get(&key) {
+            return Ok(hash.clone());
+        }
+        let hash = algorithm.hash_reader(entry)?;
+        self.hashes.lock().unwrap().insert(key, hash.clone());
+        Ok(hash)
+    }
+
+    // Fisierele noi stau intr-un director langa arhiva pana la rescriere
+    fn staging_dir(&self) -> PathBuf {
+        let name = self
+            .path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        self.path.with_file_name(format!(".{}.staging", name))
+    }
+
+    // Timpul modificarii unei intrari: NTFS (100ns, UTC), apoi extended timestamp
+    // (secunde, UTC), iar la final timpul DOS (ora locala, rezolutie 2s)
+    fn entry_mtime(&self, entry: &ZipFile) -> SystemTime {
+        let mut extended = None;
+        for field in entry.extra_data_fields() {
+            match field {
+                ExtraField::Ntfs(ntfs) => {
+                    if let Some(time) = ntfs_time(ntfs.mtime()) {
+                        return time;
+                    }
+                }
+                ExtraField::ExtendedTimestamp(ts) => {
+                    extended = ts
+                        .mod_time()
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs as u64));
+                }
+            }
+        }
+        extended
+            .or_else(|| entry.last_modified().and_then(|dt| self.dos_to_system(dt)))
+            .unwrap_or(UNIX_EPOCH)
+    }
+
+    fn dos_to_system(&self, dt: zip::DateTime) -> Option<SystemTime> {
+        let naive = NaiveDate::from_ymd_opt(dt.year() as i32, dt.month() as u32, dt.day() as u32)?
+            .and_hms_opt(dt.hour() as u32, dt.minute() as u32, dt.second() as u32)?;
+        let utc = match self.dos_timezone {
+            Some(offset) => offset
+                .from_local_datetime(&naive)
+                .single()?
+                .with_timezone(&Utc),
+            None => Local
+                .from_local_datetime(&naive)
+                .earliest()?
+                .with_timezone(&Utc),
+        };
+        Some(utc.into())
+    }
+
+    fn system_to_dos(&self, time: SystemTime) -> zip::DateTime {
+        let utc = chrono::DateTime::<Utc>::from(time);
+        let local = match self.dos_timezone {
+            Some(offset) => utc.with_timezone(&offset).naive_local(),
+            None => utc.with_timezone(&Local).naive_local(),
+        };
+        zip::DateTime::from_date_and_time(
+            local.year().clamp(1980, 2107) as u16,
+            local.month() as u8,
+            local.day() as u8,
+            local.hour() as u8,
+            local.minute() as u8,
+            local.second() as u8,
+        )
+        .unwrap_or_default()
+    }
+
+    // Intrarile noi primesc si timpul DOS si extended timestamp (UTC exact)
+    fn entry_options(&self, mtime: SystemTime) -> FullFileOptions<'static> {
+        let mut options = FullFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(self.system_to_dos(mtime));
+        let secs = mtime
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs().min(u32::MAX as u64) as u32)
+            .unwrap_or(0);
+        let mut data = vec![1u8];
+        data.extend_from_slice(&secs.to_le_bytes());
+        if let Err(e) =
+            options.add_extra_data(EXTENDED_TIMESTAMP_ID, data.into_boxed_slice(), false)
+        {
+            eprintln!("Failed to add ZIP timestamp: {}", e);
+        }
+        options
+    }
+
+    fn read_entry(&self, path: &str) -> Result<Vec<u8>, SyncError> {
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+
+        let mut file_ = archive.by_name(path).map_err(|e| zip_error(e).at(path))?;
+
+        if file_.is_dir() {
+            return Err(SyncError::conflict(format!("'{}' is a directory", path)));
+        }
+
+        let mut buf = Vec::new();
+        file_.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn archive_names(&self) -> Result<Vec<String>, SyncError> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let archive = ZipArchive::new(File::open(&self.path)?).map_err(zip_error)?;
+        Ok(archive.file_names().map(|n| n.to_string()).collect())
+    }
+
+    // Rescrie arhiva: intrarile neatinse se copiaza asa cum sunt (fara recompresie),
+    // apoi adaugam ce e nou. Scriem intr-un fisier temporar si il mutam peste arhiva.
+    fn rebuild(&self, pending: &mut PendingEntries) -> Result<(), SyncError> {
+        let file_name = self
+            .path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let tmp_path = self.path.with_file_name(format!(".{}.tmp", file_name));
+        // o rescriere esuata lasa arhiva veche neatinsa, fara fisierul temporar langa ea
+        if let Err(e) = self.write_archive(&tmp_path, pending) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        for entry in pending.entries.values() {
+            if let PendingEntry::Write(staged) = entry {
+                let _ = std::fs::remove_file(staged);
+            }
+        }
+        let _ = std::fs::remove_dir(self.staging_dir());
+        pending.entries.clear();
+        Ok(())
+    }
+
+    fn write_archive(&self, tmp_path: &Path, pending: &PendingEntries) -> Result<(), SyncError> {
+        let mut writer = ZipWriter::new(BufWriter::new(File::create(tmp_path)?));
+
+        let mut archive = if self.path.exists() {
+            Some(ZipArchive::new(File::open(&self.path)?).map_err(zip_error)?)
+        } else {
+            None
+        };
+
+        if let Some(archive) = archive.as_mut() {
+            for i in 0..archive.len() {
+                let entry = archive.by_index_raw(i).map_err(zip_error)?;
+                if !pending.entries.contains_key(entry.name()) {
+                    // copia bruta pierde campurile extra (NTFS, extended timestamp); timpul
+                    // lor ramane macar in campul DOS, in fusul orar al locatiei
+                    let modified = self.system_to_dos(self.entry_mtime(&entry));
+                    writer
+                        .raw_copy_file_touch(entry, modified, None)
+                        .map_err(zip_error)?;
+                }
+            }
         }
+
+        for (name, entry) in &pending.entries {
+            match entry {
+                PendingEntry::Write(staged) => {
+                    let mtime = std::fs::metadata(staged)?
+                        .modified()
+                        .unwrap_or_else(|_| SystemTime::now());
+                    writer
+                        .start_file(name, self.entry_options(mtime))
+                        .map_err(zip_error)?;
+                    std::io::copy(&mut File::open(staged)?, &mut writer)?;
+                }
+                PendingEntry::CopyOf(original) => {
+                    let index = archive
+                        .as_ref()
+                        .and_then(|a| a.index_for_name(original))
+                        .ok_or_else(|| {
+                            SyncError::not_found(format!("'{}' not found in ZIP", original))
+                        })?;
+                    let entry = archive
+                        .as_mut()
+                        .unwrap()
+                        .by_index_raw(index)
+                        .map_err(zip_error)?;
+                    writer
+                        .raw_copy_file_rename(entry, name)
+                        .map_err(zip_error)?;
+                }
+                PendingEntry::Dir => {
+                    writer
+                        .add_directory(name, self.entry_options(SystemTime::now()))
+                        .map_err(zip_error)?;
+                }
+                PendingEntry::Delete => {}
+            }
+        }
+
+        let file = writer
+            .finish()
+            .map_err(zip_error)?
+            .into_inner()
+            .map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    // O arhiva a carei rescriere a esuat ar fi listata fara ce am scris deja in ea, iar
+    // fisierele lipsa ar parea sterse; reincercam rescrierea, iar pana reuseste nu o listam
+    fn retry_failed_rebuild(&self) -> Result<(), SyncError> {
+        if self.pending.lock().unwrap().failed {
+            self.flush()?;
+        }
+        Ok(())
     }
 
     fn extract_directories<P: AsRef<Path>>(path: P) -> Vec<String> {
@@ -35,32 +345,48 @@ impl ZipLocation {
     }
 }
 
-fn calculate_hash_zip(data: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    hex::encode(hasher.finalize())
+// O arhiva stricata sau neacceptata nu se repara la reincercare
+fn zip_error(e: ZipError) -> SyncError {
+    match e {
+        ZipError::Io(err) => SyncError::from(err),
+        ZipError::FileNotFound => {
+            SyncError::NotFound(ErrorContext::new("not found in ZIP").with_source(e).into())
+        }
+        _ => SyncError::ProtocolError {
+            context: ErrorContext::new(e.to_string()).with_source(e).into(),
+            transient: false,
+        },
+    }
 }
 
 impl Location for ZipLocation {
-    fn as_any(&self) -> &dyn Any {
-        self
+    // read-only daca arhiva nu a fost deschisa cu `?writable`
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            writable: self.writable,
+            set_mtime: self.writable,
+            hash: self.hash,
+            native_hashes: vec![HashAlgorithm::Crc32],
+            watch_root: None,
+            rename: false,
+            case_sensitive: true,
+            permissions: false,
+        }
     }
 
     //nnu mai folosim
     fn list_files(&self) -> Result<Vec<FileMetadata>, SyncError> {
         let file = File::open(&self.path)?;
-        let mut archive = ZipArchive::new(file).map_err(|e| SyncError::Parse(e.to_string()))?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
 
         let mut results = Vec::new();
         for i in 0..archive.len() {
             if let Ok(mut file_) = archive.by_index(i) {
-                let mut buffer = Vec::new();
-                file_.read_to_end(&mut buffer)?;
                 results.push(FileMetadata {
                     path: file_.name().to_string(),
-                    modified: SystemTime::UNIX_EPOCH,
-                    //size: file_.size(),
-                    hash: Some(calculate_hash_zip(&buffer)),
+                    modified: self.entry_mtime(&file_),
+                    size: file_.size(),
+                    hash: Some(self.entry_hash(&mut file_, self.hash)?),
                 });
             }
         }
@@ -68,46 +394,194 @@ impl Location for ZipLocation {
     }
 
     fn read_file(&self, path: &str) -> Result<Vec<u8>, SyncError> {
-        let file = File::open(&self.path).map_err(SyncError::Io)?;
-        let mut archive = ZipArchive::new(file).map_err(|e| SyncError::Parse(e.to_string()))?;
+        if self.pending.lock().unwrap().entries.contains_key(path) {
+            let mut buf = Vec::new();
+            self.open_read(path)?.read_to_end(&mut buf)?;
+            return Ok(buf);
+        }
+        self.read_entry(path)
+    }
 
-        let mut file_ = archive.by_name(path).map_err(|_| {
-            SyncError::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("File '{}' not found in ZIP", path),
-            ))
-        })?;
+    // Citim intrarea direct din arhiva: sarim la datele ei si decomprimam din mers
+    fn open_read(&self, path: &str) -> Result<Box<dyn Read + '_>, SyncError> {
+        // intrarile inca nescrise in arhiva
+        let path = {
+            let pending = self.pending.lock().unwrap();
+            match pending.entries.get(path) {
+                Some(PendingEntry::Write(staged)) => {
+                    return Ok(Box::new(BufReader::new(File::open(staged)?)));
+                }
+                Some(PendingEntry::CopyOf(original)) => original.clone(),
+                Some(PendingEntry::Delete) => {
+                    return Err(SyncError::not_found(format!("'{}' not found in ZIP", path)));
+                }
+                _ => path.to_string(),
+            }
+        };
+        let path = path.as_str();
 
-        if file_.is_dir() {
-            return Err(SyncError::Parse(format!("'{}' is a directory", path)));
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+
+        let (compression, data_start, compressed_size) = {
+            let entry = archive.by_name(path).map_err(|e| zip_error(e).at(path))?;
+            if entry.is_dir() {
+                return Err(SyncError::conflict(format!("'{}' is a directory", path)));
+            }
+            if entry.encrypted() {
+                return Err(SyncError::protocol(format!("'{}' is encrypted", path)));
+            }
+            (
+                entry.compression(),
+                entry.data_start(),
+                entry.compressed_size(),
+            )
+        };
+
+        let mut file = archive.into_inner();
+        file.seek(SeekFrom::Start(data_start))?;
+        let raw = BufReader::new(file).take(compressed_size);
+
+        match compression {
+            CompressionMethod::Stored => Ok(Box::new(raw)),
+            CompressionMethod::Deflated => Ok(Box::new(flate2::read::DeflateDecoder::new(raw))),
+            // alte metode de compresie: le lasam pe seama bibliotecii zip
+            _ => Ok(Box::new(Cursor::new(self.read_entry(path)?))),
         }
+    }
 
-        let mut buf = Vec::new();
-        file_.read_to_end(&mut buf)?;
-        Ok(buf)
+    fn write_file(&self, path: &str, data: &[u8]) -> Result<(), SyncError> {
+        let mut writer = self.open_write(path)?;
+        writer.write_all(data)?;
+        writer.finish()
     }
 
-    fn write_file(&self, path: &str, _data: &[u8]) -> Result<(), SyncError> {
-        println!(
-            "Attempted to write '{}' to ZIP (read-only). Ignoring.",
-            path
-        );
+    fn open_write(&self, path: &str) -> Result<Box<dyn WriteStream + '_>, SyncError> {
+        if !self.writable {
+            return Err(SyncError::read_only("ZIP is opened read-only").at(path));
+        }
+
+        let staging_dir = self.staging_dir();
+        std::fs::create_dir_all(&staging_dir)?;
+        let staged = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.next_staged += 1;
+            staging_dir.join(pending.next_staged.to_string())
+        };
+        let file = File::create(&staged)?;
 
+        Ok(Box::new(ZipWriteStream {
+            writer: BufWriter::new(file),
+            staged,
+            name: path.to_string(),
+            location: self,
+        }))
+    }
+
+    // Intrarea noua ia la rescriere timpul fisierului ei temporar; cele copiate
+    // neschimbate il au deja pe al lor
+    fn set_modified(&self, path: &str, modified: SystemTime) -> Result<(), SyncError> {
+        if let Some(PendingEntry::Write(staged)) = self.pending.lock().unwrap().entries.get(path) {
+            File::options()
+                .write(true)
+                .open(staged)?
+                .set_modified(modified)?;
+        }
         Ok(())
     }
 
     fn delete_file(&self, path: &str) -> Result<(), SyncError> {
-        println!(
-            "Attempted to delete '{}' from ZIP (read-only). Ignoring.",
-            path
-        );
+        if !self.writable {
+            return Err(SyncError::read_only("ZIP is opened read-only").at(path));
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(PendingEntry::Write(staged)) = pending
+            .entries
+            .insert(path.to_string(), PendingEntry::Delete)
+        {
+            let _ = std::fs::remove_file(staged);
+        }
+        Ok(())
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), SyncError> {
+        if !self.writable {
+            return Err(SyncError::read_only("ZIP is opened read-only").at(from));
+        }
+
+        let mut pending = self.pending.lock().unwrap();
+        // o intrare inca nescrisa se muta direct; altfel copiem intrarea veche sub noul nume
+        let moved = match pending.entries.remove(from) {
+            Some(PendingEntry::Delete) | Some(PendingEntry::Dir) | None => {
+                PendingEntry::CopyOf(from.to_string())
+            }
+            Some(entry) => entry,
+        };
+        pending.entries.insert(to.to_string(), moved);
+        pending
+            .entries
+            .insert(from.to_string(), PendingEntry::Delete);
+        Ok(())
+    }
 
+    fn create_dir(&self, path: &str) -> Result<(), SyncError> {
+        if self.writable {
+            let name = format!("{}/", path.trim_end_matches('/'));
+            self.pending
+                .lock()
+                .unwrap()
+                .entries
+                .entry(name)
+                .or_insert(PendingEntry::Dir);
+        }
         Ok(())
     }
 
+    fn remove_dir(&self, path: &str) -> Result<(), SyncError> {
+        if !self.writable {
+            return Ok(());
+        }
+
+        let prefix = format!("{}/", path.trim_end_matches('/'));
+        let names = self.archive_names()?;
+        let mut pending = self.pending.lock().unwrap();
+        let pending_names: Vec<String> = pending.entries.keys().cloned().collect();
+        for name in names.into_iter().chain(pending_names) {
+            if name.starts_with(&prefix) {
+                if let Some(PendingEntry::Write(staged)) =
+                    pending.entries.insert(name, PendingEntry::Delete)
+                {
+                    let _ = std::fs::remove_file(staged);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> Result<(), SyncError> {
+        let mut pending = self.pending.lock().unwrap();
+        if pending.entries.is_empty() {
+            return Ok(());
+        }
+        println!(
+            "Rewriting ZIP {} with {} changed entries",
+            self.path.display(),
+            pending.entries.len()
+        );
+        let result = self.rebuild(&mut pending);
+        pending.failed = result.is_err();
+        result
+    }
+
     fn list_files_recursive(&self) -> Result<Vec<FileMetadata>, SyncError> {
-        let file = File::open(&self.path).map_err(SyncError::Io)?;
-        let mut archive = ZipArchive::new(file).map_err(|e| SyncError::Parse(e.to_string()))?;
+        self.retry_failed_rebuild()?;
+        // o arhiva destinatie care inca nu exista e pur si simplu goala
+        if self.writable && !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
 
         let mut results = Vec::new();
 
@@ -118,53 +592,397 @@ impl Location for ZipLocation {
                     continue;
                 }
 
-                let mut buffer = Vec::new();
-                file_.read_to_end(&mut buffer)?;
                 results.push(FileMetadata {
                     path: file_.name().to_string(),
-                    modified: SystemTime::UNIX_EPOCH,
-                    //size: file_.size(),
-                    hash: Some(calculate_hash_zip(&buffer)),
+                    modified: self.entry_mtime(&file_),
+                    size: file_.size(),
+                    hash: Some(self.entry_hash(&mut file_, self.hash)?),
                 });
             }
         }
+        // intrarile care nu mai sunt in arhiva nu mai au nevoie de hash
+        let listed: HashSet<&str> = results.iter().map(|f| f.path.as_str()).collect();
+        self.hashes
+            .lock()
+            .unwrap()
+            .retain(|(name, ..), _| listed.contains(name.as_str()));
         Ok(results)
     }
 
+    // Doar intrarea ceruta, nu toata arhiva
+    fn stat_file(&self, path: &str) -> Result<Option<FileMetadata>, SyncError> {
+        self.retry_failed_rebuild()?;
+        if self.writable && !self.path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+        let mut file_ = match archive.by_name(path) {
+            Ok(file_) if !file_.is_dir() => file_,
+            Ok(_) | Err(ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(zip_error(e).at(path)),
+        };
+        Ok(Some(FileMetadata {
+            path: path.to_string(),
+            modified: self.entry_mtime(&file_),
+            size: file_.size(),
+            hash: Some(self.entry_hash(&mut file_, self.hash)?),
+        }))
+    }
+
+    fn content_hash(&self, path: &str, algorithm: HashAlgorithm) -> Result<String, SyncError> {
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+        let mut entry = archive.by_name(path).map_err(|e| zip_error(e).at(path))?;
+        self.entry_hash(&mut entry, algorithm)
+    }
+
     fn list_dirs_recursive(&self) -> Result<Vec<DirMetadata>, SyncError> {
-        let file = File::open(&self.path).map_err(SyncError::Io)?;
-        let mut archive = ZipArchive::new(file).map_err(|e| SyncError::Parse(e.to_string()))?;
+        self.retry_failed_rebuild()?;
+        // o arhiva destinatie care inca nu exista e pur si simplu goala
+        if self.writable && !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let file = File::open(&self.path)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
 
-        let mut dir_set: HashSet<String> = HashSet::new();
+        // `docs/` si `docs` sunt acelasi director
+        let mut dir_times: HashMap<String, Option<SystemTime>> = HashMap::new();
 
         for i in 0..archive.len() {
             if let Ok(file_) = archive.by_index(i) {
                 let file_path = Path::new(file_.name());
 
                 if file_.is_dir() {
-                    let dir_str = file_.name().to_string();
+                    let dir_str = file_.name().trim_end_matches('/').to_string();
                     if !dir_str.is_empty() {
-                        dir_set.insert(dir_str);
+                        dir_times.insert(dir_str, Some(self.entry_mtime(&file_)));
                     }
                 }
 
                 // Extrage directoarele din calea fisierului
                 for dir in Self::extract_directories(file_path) {
-                    dir_set.insert(dir);
+                    dir_times.entry(dir).or_insert(None);
                 }
             }
         }
 
-        // Convertim set in vector de DirMetadata
-        let dirs = dir_set
+        // Directoarele fara intrare proprie raman cu UNIX_EPOCH
+        let dirs = dir_times
             .into_iter()
-            .filter(|dir| !dir.is_empty())
-            .map(|dir_path| DirMetadata {
+            .filter(|(dir, _)| !dir.is_empty())
+            .map(|(dir_path, modified)| DirMetadata {
                 path: dir_path,
-                modified: UNIX_EPOCH,
+                modified: modified.unwrap_or(UNIX_EPOCH),
             })
             .collect();
 
         Ok(dirs)
     }
 }
+
+impl Drop for ZipLocation {
+    // Nu pierdem modificarile daca nimeni nu a apelat `flush`
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("Failed to rewrite ZIP {}: {:?}", self.path.display(), e);
+        }
+    }
+}
+
+fn ntfs_time(ticks: u64) -> Option<SystemTime> {
+    let secs = (ticks / 10_000_000).checked_sub(NTFS_EPOCH_OFFSET)?;
+    let nanos = (ticks % 10_000_000) * 100;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parse_offset;
+    use super::*;
+    use crate::test_util::temp_root;
+    use std::fs;
+    use zip::write::SimpleFileOptions;
+
+    fn build_archive(path: &Path, entries: &[(&str, &str)]) {
+        let mut writer = ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn writable(path: &Path) -> ZipLocation {
+        let mut location = ZipLocation::new(path.to_str().unwrap());
+        location.writable = true;
+        location
+    }
+
+    fn names(path: &Path) -> Vec<String> {
+        let archive = ZipArchive::new(File::open(path).unwrap()).unwrap();
+        let mut names: Vec<String> = archive.file_names().map(|n| n.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rebuild_copies_untouched_entries_and_keeps_their_times() {
+        let root = temp_root("zip_rebuild");
+        let path = root.join("a.zip");
+        let old = UNIX_EPOCH + Duration::from_secs(1_600_000_001);
+        let new = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        // o intrare necomprimata: daca ar fi recomprimata, ar iesi Deflated
+        let mut writer = ZipWriter::new(File::create(&path).unwrap());
+        let mut options = FullFileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut stamp = vec![1u8];
+        stamp.extend_from_slice(&1_600_000_001u32.to_le_bytes());
+        options
+            .add_extra_data(EXTENDED_TIMESTAMP_ID, stamp.into_boxed_slice(), false)
+            .unwrap();
+        writer.start_file("keep.txt", options).unwrap();
+        writer.write_all(b"old").unwrap();
+        writer.finish().unwrap();
+
+        let location = writable(&path);
+        location.write_file("new.txt", b"new").unwrap();
+        location.set_modified("new.txt", new).unwrap();
+        // pana la flush scrierea sta in directorul temporar
+        assert_eq!(names(&path), ["keep.txt"]);
+        location.flush().unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(
+            archive.by_name("keep.txt").unwrap().compression(),
+            CompressionMethod::Stored
+        );
+        assert_eq!(
+            archive.by_name("new.txt").unwrap().compression(),
+            CompressionMethod::Deflated
+        );
+        let times: HashMap<String, SystemTime> = location
+            .list_files_recursive()
+            .unwrap()
+            .into_iter()
+            .map(|f| (f.path, f.modified))
+            .collect();
+        // copia bruta pastreaza timpul doar in campul DOS, cu rezolutie de 2s
+        assert_eq!(times["keep.txt"], old - Duration::from_secs(1));
+        assert_eq!(times["new.txt"], new);
+        assert_eq!(location.read_file("keep.txt").unwrap(), b"old");
+        assert_eq!(location.read_file("new.txt").unwrap(), b"new");
+        assert!(!location.staging_dir().exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rebuild_applies_deletes_and_renames() {
+        let root = temp_root("zip_deletes");
+        let path = root.join("a.zip");
+        build_archive(
+            &path,
+            &[
+                ("a.txt", "a"),
+                ("b.txt", "b"),
+                ("docs/c.txt", "c"),
+                ("docs/d.txt", "d"),
+            ],
+        );
+
+        let location = writable(&path);
+        location.delete_file("a.txt").unwrap();
+        location.rename("b.txt", "moved/b.txt").unwrap();
+        // o scriere inca nescrisa in arhiva dispare odata cu directorul ei
+        location.write_file("docs/new.txt", b"new").unwrap();
+        location.remove_dir("docs").unwrap();
+        // iar una redenumita se muta direct
+        location.write_file("x.txt", b"x").unwrap();
+        location.rename("x.txt", "y.txt").unwrap();
+        location.flush().unwrap();
+
+        assert_eq!(names(&path), ["moved/b.txt", "y.txt"]);
+        assert_eq!(location.read_file("moved/b.txt").unwrap(), b"b");
+        assert_eq!(location.read_file("y.txt").unwrap(), b"x");
+        assert!(!location.staging_dir().exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn failed_rebuild_keeps_archive_and_leaves_no_tmp() {
+        let root = temp_root("zip_failed");
+        let path = root.join("a.zip");
+        build_archive(&path, &[("a.txt", "a")]);
+        let before = fs::read(&path).unwrap();
+
+        let location = writable(&path);
+        // redenumirea unei intrari care nu exista esueaza abia la rescriere
+        location.rename("missing.txt", "b.txt").unwrap();
+        assert!(matches!(location.flush(), Err(SyncError::NotFound(_))));
+
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert!(!root.join(".a.zip.tmp").exists());
+        // pana reuseste rescrierea, arhiva veche nu e listata
+        assert!(location.list_files_recursive().is_err());
+        assert!(location.stat_file("a.txt").is_err());
+
+        location.pending.lock().unwrap().entries.remove("b.txt");
+        assert_eq!(location.list_files_recursive().unwrap().len(), 1);
+        assert!(!location.pending.lock().unwrap().failed);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // O arhiva cu o singura intrare `a.txt`, scrisa de mana ca sa putem pune campuri extra
+    // pe care ZipWriter nu le accepta (NTFS)
+    fn hand_built(path: &Path, dos: (u16, u16), extra: &[u8]) {
+        let (name, data) = (b"a.txt", b"x");
+        let crc = crc32fast::hash(data);
+        let mut header = Vec::new();
+        header.extend_from_slice(&20u16.to_le_bytes()); // versiunea necesara
+        header.extend_from_slice(&0u16.to_le_bytes()); // flag-uri
+        header.extend_from_slice(&0u16.to_le_bytes()); // Stored
+        header.extend_from_slice(&dos.0.to_le_bytes());
+        header.extend_from_slice(&dos.1.to_le_bytes());
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        out.extend_from_slice(&header);
+        out.extend_from_slice(name);
+        out.extend_from_slice(extra);
+        out.extend_from_slice(data);
+
+        let central_start = out.len() as u32;
+        out.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        out.extend_from_slice(&20u16.to_le_bytes()); // creat de
+        out.extend_from_slice(&header);
+        out.extend_from_slice(&[0; 10]); // comentariu, disc, atribute interne si externe
+        out.extend_from_slice(&0u32.to_le_bytes()); // antetul local e la inceput
+        out.extend_from_slice(name);
+        out.extend_from_slice(extra);
+        let central_size = out.len() as u32 - central_start;
+
+        out.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&central_size.to_le_bytes());
+        out.extend_from_slice(&central_start.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        fs::write(path, out).unwrap();
+    }
+
+    // 2024-03-10 12:30:44, fara fus orar
+    const DOS: (u16, u16) = (
+        (12 << 11) | (30 << 5) | (44 / 2),
+        ((2024 - 1980) << 9) | (3 << 5) | 10,
+    );
+    const DOS_AS_UTC: u64 = 1_710_073_844;
+
+    fn ntfs_field(ticks: u64) -> Vec<u8> {
+        let mut field = Vec::new();
+        field.extend_from_slice(&0x000au16.to_le_bytes());
+        field.extend_from_slice(&32u16.to_le_bytes());
+        field.extend_from_slice(&0u32.to_le_bytes());
+        field.extend_from_slice(&1u16.to_le_bytes());
+        field.extend_from_slice(&24u16.to_le_bytes());
+        for _ in 0..3 {
+            field.extend_from_slice(&ticks.to_le_bytes());
+        }
+        field
+    }
+
+    fn extended_field(secs: u32) -> Vec<u8> {
+        let mut field = Vec::new();
+        field.extend_from_slice(&EXTENDED_TIMESTAMP_ID.to_le_bytes());
+        field.extend_from_slice(&5u16.to_le_bytes());
+        field.push(1);
+        field.extend_from_slice(&secs.to_le_bytes());
+        field
+    }
+
+    #[test]
+    fn entry_mtime_prefers_ntfs_then_extended_then_dos() {
+        let root = temp_root("zip_mtime");
+        let path = root.join("a.zip");
+        let ntfs = UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000);
+        let ntfs_ticks = (1_700_000_000 + NTFS_EPOCH_OFFSET) * 10_000_000 + 5_000_000;
+        let extended = UNIX_EPOCH + Duration::from_secs(1_650_000_000);
+        let dos = UNIX_EPOCH + Duration::from_secs(DOS_AS_UTC);
+
+        let cases = [
+            (
+                "ntfs",
+                [ntfs_field(ntfs_ticks), extended_field(1_650_000_000)].concat(),
+                ntfs,
+            ),
+            ("extended", extended_field(1_650_000_000), extended),
+            // un timp NTFS dinainte de 1970 nu e folosit
+            (
+                "ntfs before 1970",
+                [ntfs_field(0), extended_field(1_650_000_000)].concat(),
+                extended,
+            ),
+            ("dos", Vec::new(), dos),
+        ];
+        for (name, extra, expected) in cases {
+            hand_built(&path, DOS, &extra);
+            let mut location = ZipLocation::new(path.to_str().unwrap());
+            location.dos_timezone = FixedOffset::east_opt(0);
+            let file = location.stat_file("a.txt").unwrap().unwrap();
+            assert_eq!(file.modified, expected, "{}", name);
+        }
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn dos_time_is_read_in_the_given_timezone() {
+        let root = temp_root("zip_tz");
+        let path = root.join("a.zip");
+        let hour = Duration::from_secs(3600);
+
+        let cases = [
+            ("+02:00", DOS_AS_UTC - 2 * 3600),
+            ("-05:00", DOS_AS_UTC + 5 * 3600),
+            ("utc", DOS_AS_UTC),
+        ];
+        for (tz, expected) in cases {
+            hand_built(&path, DOS, &[]);
+            let mut location = ZipLocation::new(path.to_str().unwrap());
+            location.dos_timezone = Some(parse_offset("zip", tz).unwrap());
+            let file = location.stat_file("a.txt").unwrap().unwrap();
+            assert_eq!(
+                file.modified,
+                UNIX_EPOCH + Duration::from_secs(expected),
+                "{}",
+                tz
+            );
+        }
+
+        // timpii UTC din campurile extra nu depind de fusul orar
+        hand_built(&path, DOS, &extended_field(1_650_000_000));
+        let mut location = ZipLocation::new(path.to_str().unwrap());
+        location.dos_timezone = FixedOffset::east_opt(2 * 3600);
+        let file = location.stat_file("a.txt").unwrap().unwrap();
+        assert_eq!(
+            file.modified,
+            UNIX_EPOCH + Duration::from_secs(1_650_000_000)
+        );
+        // iar un timp scris de noi se citeste inapoi la fel
+        let written = UNIX_EPOCH + Duration::from_secs(1_650_000_000) + hour;
+        let dt = location.system_to_dos(written);
+        assert_eq!(location.dos_to_system(dt), Some(written));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
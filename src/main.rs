@@ -1,63 +1,107 @@
+use crate::config::parse_args;
 use crate::errors::SyncError;
-use crate::locations::{parse_location, FolderLocation, Location};
+use crate::locations::{parse_location, Location};
 use std::time::{Duration, Instant};
+mod config;
+mod delta;
 mod errors;
 mod locations;
 mod sync_logic;
+#[cfg(test)]
+mod test_util;
+use crate::sync_logic::conflicts::{print_conflicts, resolve_conflict};
+use crate::sync_logic::debounce::Debouncer;
 use crate::sync_logic::*;
 use crate::watchers::*;
 
 fn main() -> Result<(), SyncError> {
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        eprintln!("Usage: {} <location1> <location2> ...", args[0]);
+    let config = parse_args(&args[1..])?;
+    if config.locations.is_empty() {
+        eprintln!(
+            "Usage: {} [--state <file>] [--conflict [<i>,<j>=]<policy>] [--list-conflicts] [--once] [--resolve <id>[:<location>]] [--debounce <ms>] [--mtime-tolerance <s>] [--hash <algorithm>] <location1> <location2> ...",
+            args[0]
+        );
         std::process::exit(1);
     }
 
     let mut locations: Vec<Box<dyn Location>> = Vec::new();
-    for loc_str in &args[1..] {
-        let loc = parse_location(loc_str)?;
+    for (i, loc_str) in config.locations.iter().enumerate() {
+        let loc = parse_location(loc_str, config.hash)?;
+        println!("Location #{}: {}", i, loc.capabilities().summary());
         locations.push(loc);
     }
 
     //Initializam SyncState
-    let mut sync_state = SyncState::new();
-    initial_sync_with_state(&locations, &mut sync_state)?;
-
-    //watcher pentru foldere locale
-    let folder_path = locations.iter().find_map(|loc| {
-        loc.as_any()
-            .downcast_ref::<FolderLocation>()
-            .map(|f| f.path.clone())
-    });
-
-    let rx = if let Some(p) = folder_path {
-        Some(watch_folder(&p).map_err(|e| SyncError::Parse(e.to_string()))?)
-    } else {
-        None
-    };
+    let mut sync_state = SyncState::open(&config.state_path)?;
+    sync_state.conflict_policies = config.conflict_policies.clone();
+    sync_state.mtime_tolerance = config.mtime_tolerance;
+    let algorithms: Vec<_> = locations.iter().map(|l| l.capabilities().hash).collect();
+    sync_state.check_hash_algorithms(&algorithms);
+
+    if config.list_conflicts {
+        print_conflicts(&sync_state.list_conflicts()?);
+        return Ok(());
+    }
+    if let Some((id, keep)) = config.resolve {
+        return resolve_conflict(&locations, &mut sync_state, id, keep);
+    }
+
+    // o locatie cazuta nu opreste daemonul; ce n-a mers se reia la urmatorul poll
+    if let Err(e) = initial_sync_with_state(&locations, &mut sync_state) {
+        if config.once || matches!(e, SyncError::State(_)) {
+            return Err(e);
+        }
+        eprintln!("Initial sync incomplete, retrying on the next poll: {}", e);
+    }
+    if config.once {
+        // codul de iesire arata daca a ramas ceva nesincronizat
+        if !sync_state.failures.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    //cate un watcher pentru fiecare folder local
+    let watchers = watch_locations(&locations)?;
+
+    let mut debouncer = Debouncer::new(config.debounce);
 
     let mut last_ftp_poll = Instant::now();
     loop {
-        //Tratam evenimentele din foldere
-        if let Some(ref rx_channel) = rx {
-            while let Ok(event_res) = rx_channel.try_recv() {
-                match event_res {
-                    Ok(event) => {
-                        println!("Local folder event: {:?}", event);
-                        handle_local_event(&event, &mut locations, &mut sync_state)?;
-                    }
-                    Err(e) => eprintln!("Watcher error: {:?}", e),
-                }
+        //Adunam evenimentele din foldere; o cale se sincronizeaza abia cand s-a linistit
+        while let Ok(LocationEvent {
+            location,
+            root,
+            event,
+        }) = watchers.events.try_recv()
+        {
+            match event {
+                Ok(event) => debouncer.push_event(location, &root, &event, Instant::now()),
+                Err(e) => eprintln!("Watcher error in location #{}: {:?}", location, e),
+            }
+        }
+        for (location, change) in debouncer.ready(Instant::now()) {
+            println!("Change in location #{}: {:?}", location, change);
+            // erorile pe o cale sunt deja tinute minte pentru reincercare; o schimbare
+            // nesincronizata nu opreste daemon-ul, o prinde urmatorul poll
+            if let Err(e) = handle_local_change(location, change, &mut locations, &mut sync_state) {
+                eprintln!("Change not synced, left to polling: {}", e);
             }
         }
 
         if last_ftp_poll.elapsed() > Duration::from_secs(10) {
             println!("\nPolling FTP locations...");
-            poll_locations(&locations, &mut sync_state)?;
+            // doar o stare care nu se mai poate salva opreste daemon-ul; restul erorilor
+            // sunt deja tinute minte pe cale sau pe locatie si se reiau la urmatorul poll
+            match poll_locations(&locations, &mut sync_state) {
+                Err(e @ SyncError::State(_)) => return Err(e),
+                Err(e) => eprintln!("Sync pass failed: {}", e),
+                Ok(()) => {}
+            }
             last_ftp_poll = Instant::now();
         }
 
-        std::thread::sleep(Duration::from_millis(500));
+        std::thread::sleep(Duration::from_millis(100));
     }
 }
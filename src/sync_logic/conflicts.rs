@@ -0,0 +1,293 @@
+use super::reconcile::{copy_file, same_content};
+use super::{flush_locations, SyncState};
+use crate::errors::SyncError;
+use crate::locations::{FileMetadata, Location};
+use chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+// Ce facem cand acelasi fisier s-a modificat in mai multe locatii
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConflictPolicy {
+    NewestWins,
+    // pastram ambele variante: cea pierzatoare ca `nume.conflict-<host>-<timestamp>.ext`
+    KeepBoth,
+    PreferLocation(usize),
+    SkipAndReport,
+}
+
+impl ConflictPolicy {
+    pub fn parse(s: &str) -> Result<Self, SyncError> {
+        match s {
+            "newest-wins" => Ok(ConflictPolicy::NewestWins),
+            "keep-both" => Ok(ConflictPolicy::KeepBoth),
+            "skip" | "skip-and-report" => Ok(ConflictPolicy::SkipAndReport),
+            other => match other.strip_prefix("prefer-location-") {
+                Some(n) => n
+                    .parse()
+                    .map(ConflictPolicy::PreferLocation)
+                    .map_err(|_| SyncError::Parse(format!("Invalid location index: {}", n))),
+                None => Err(SyncError::Parse(format!(
+                    "Unknown conflict policy: {}",
+                    other
+                ))),
+            },
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ConflictPolicy::NewestWins => "newest-wins".to_string(),
+            ConflictPolicy::KeepBoth => "keep-both".to_string(),
+            ConflictPolicy::PreferLocation(n) => format!("prefer-location-{}", n),
+            ConflictPolicy::SkipAndReport => "skip-and-report".to_string(),
+        }
+    }
+}
+
+// Politica implicita (`--conflict <politica>`) plus cate una pentru anumite perechi de
+// locatii (`--conflict <i>,<j>=<politica>`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflictPolicies {
+    pub default: ConflictPolicy,
+    pairs: Vec<((usize, usize), ConflictPolicy)>,
+}
+
+impl Default for ConflictPolicies {
+    fn default() -> Self {
+        ConflictPolicies {
+            default: ConflictPolicy::NewestWins,
+            pairs: Vec::new(),
+        }
+    }
+}
+
+impl ConflictPolicies {
+    // `0,1=keep-both`; `prefer-location-N` trebuie sa fie una din cele doua locatii
+    pub fn add_pair(&mut self, spec: &str) -> Result<(), SyncError> {
+        let invalid = || SyncError::Parse(format!("Invalid conflict policy for a pair: {}", spec));
+        let (pair, policy) = spec.split_once('=').ok_or_else(invalid)?;
+        let (a, b) = pair.split_once(',').ok_or_else(invalid)?;
+        let a: usize = a.trim().parse().map_err(|_| invalid())?;
+        let b: usize = b.trim().parse().map_err(|_| invalid())?;
+        let policy = ConflictPolicy::parse(policy)?;
+        if a == b {
+            return Err(invalid());
+        }
+        if let ConflictPolicy::PreferLocation(n) = policy {
+            if n != a && n != b {
+                return Err(SyncError::Parse(format!(
+                    "{}: location #{} is not part of the pair",
+                    spec, n
+                )));
+            }
+        }
+        let pair = (a.min(b), a.max(b));
+        self.pairs.retain(|(p, _)| *p != pair);
+        self.pairs.push((pair, policy));
+        Ok(())
+    }
+
+    // Cea mai mare locatie numita (intr-o pereche sau de politica implicita), ca sa o
+    // putem verifica fata de cele date
+    pub fn max_location(&self) -> Option<usize> {
+        let default = match self.default {
+            ConflictPolicy::PreferLocation(n) => Some(n),
+            _ => None,
+        };
+        self.pairs.iter().map(|((_, b), _)| *b).chain(default).max()
+    }
+
+    // Politica pentru un conflict intre locatiile `changed`: a primei perechi configurate
+    // (in ordinea din linia de comanda) care e implicata, altfel cea implicita
+    pub fn for_locations(&self, changed: &[usize]) -> &ConflictPolicy {
+        self.pairs
+            .iter()
+            .find(|((a, b), _)| changed.contains(a) && changed.contains(b))
+            .map(|(_, policy)| policy)
+            .unwrap_or(&self.default)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConflictRecord {
+    pub id: Option<i64>,
+    pub path: String,
+    pub detected_at: SystemTime,
+    pub policy: String,
+    // variantele in conflict, ca `locatie:hash`
+    pub versions: Vec<(usize, Option<String>)>,
+    // None cat timp conflictul e nerezolvat
+    pub resolution: Option<String>,
+}
+
+impl ConflictRecord {
+    pub fn new(path: &str, policy: &ConflictPolicy, versions: &[(usize, &FileMetadata)]) -> Self {
+        ConflictRecord {
+            id: None,
+            path: path.to_string(),
+            detected_at: SystemTime::now(),
+            policy: policy.name(),
+            versions: versions
+                .iter()
+                .map(|(i, meta)| (*i, meta.hash.clone()))
+                .collect(),
+            resolution: None,
+        }
+    }
+
+    pub fn versions_to_string(&self) -> String {
+        self.versions
+            .iter()
+            .map(|(i, hash)| format!("{}:{}", i, hash.as_deref().unwrap_or("")))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn versions_from_string(s: &str) -> Vec<(usize, Option<String>)> {
+        s.split(',')
+            .filter_map(|v| {
+                let (i, hash) = v.split_once(':')?;
+                let hash = (!hash.is_empty()).then(|| hash.to_string());
+                Some((i.parse().ok()?, hash))
+            })
+            .collect()
+    }
+
+    // Acelasi conflict, cu aceleasi variante, nu il raportam de doua ori
+    pub fn same_versions(&self, other: &ConflictRecord) -> bool {
+        self.path == other.path && self.versions == other.versions
+    }
+}
+
+// `docs/report.txt` -> `docs/report.conflict-<host>-<timestamp>.txt`
+pub fn conflict_path(path: &str, host: &str, time: SystemTime) -> String {
+    let timestamp = DateTime::<Utc>::from(time).format("%Y%m%d-%H%M%S");
+    let (dir, file) = match path.rsplit_once('/') {
+        Some((dir, file)) => (format!("{}/", dir), file),
+        None => (String::new(), path),
+    };
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    match file.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => {
+            format!("{}{}.conflict-{}-{}.{}", dir, stem, host, timestamp, ext)
+        }
+        _ => format!("{}{}.conflict-{}-{}", dir, file, host, timestamp),
+    }
+}
+
+pub fn print_conflicts(conflicts: &[ConflictRecord]) {
+    if conflicts.is_empty() {
+        println!("No conflicts recorded.");
+        return;
+    }
+    for c in conflicts {
+        let detected = DateTime::<Utc>::from(c.detected_at).format("%Y-%m-%d %H:%M:%S");
+        println!(
+            "#{} [{}] {} ({}, detected {} UTC)",
+            c.id.unwrap_or_default(),
+            if c.resolution.is_some() {
+                "resolved"
+            } else {
+                "open"
+            },
+            c.path,
+            c.policy,
+            detected
+        );
+        for (loc, hash) in &c.versions {
+            println!("    location #{}: {}", loc, hash.as_deref().unwrap_or("?"));
+        }
+        if let Some(resolution) = &c.resolution {
+            println!("    -> {}", resolution);
+        }
+    }
+}
+
+// Rezolvare manuala: optional alegem locatia a carei varianta o pastram
+pub fn resolve_conflict(
+    locations: &[Box<dyn Location>],
+    sync_state: &mut SyncState,
+    id: i64,
+    keep: Option<usize>,
+) -> Result<(), SyncError> {
+    let conflict = sync_state
+        .get_conflict(id)?
+        .ok_or_else(|| SyncError::Parse(format!("No conflict with id {}", id)))?;
+    if let Some(resolution) = &conflict.resolution {
+        println!("Conflict #{} is already resolved: {}", id, resolution);
+        return Ok(());
+    }
+
+    let resolution = match keep {
+        Some(n) => {
+            let winner = locations
+                .get(n)
+                .ok_or_else(|| SyncError::Parse(format!("No location #{}", n)))?;
+            let metadata = winner.stat_file(&conflict.path)?.ok_or_else(|| {
+                SyncError::Parse(format!(
+                    "{} no longer exists in location #{}",
+                    conflict.path, n
+                ))
+            })?;
+
+            for (i, loc) in locations.iter().enumerate() {
+                if i != n && loc.capabilities().writable {
+                    copy_file(
+                        winner.as_ref(),
+                        loc.as_ref(),
+                        &conflict.path,
+                        &conflict.path,
+                        Some(metadata.modified),
+                    )?;
+                    println!(
+                        "File {} copied from location #{} to #{}",
+                        conflict.path, n, i
+                    );
+                }
+            }
+            flush_locations(locations)?;
+            sync_state.update_file(conflict.path.clone(), metadata);
+            format!("resolved manually: kept version from location #{}", n)
+        }
+        // fara locatie: variantele trebuie sa fi fost deja aduse la acelasi continut (de mana),
+        // altfel urmatoarea trecere ar gasi acelasi conflict
+        None => {
+            let mut current: Vec<(usize, FileMetadata)> = Vec::new();
+            for (i, loc) in locations.iter().enumerate() {
+                if let Some(metadata) = loc.stat_file(&conflict.path)? {
+                    current.push((i, metadata));
+                }
+            }
+            let differ: Vec<usize> = current
+                .iter()
+                .filter(|(_, m)| !same_content(m, &current[0].1, sync_state.mtime_tolerance))
+                .map(|(i, _)| *i)
+                .collect();
+            if !differ.is_empty() {
+                return Err(SyncError::Parse(format!(
+                    "{} still differs in locations {:?}; pick the version to keep with --resolve {}:<location>",
+                    conflict.path, differ, id
+                )));
+            }
+            if let Some((_, metadata)) = current.into_iter().next() {
+                sync_state.update_file(conflict.path.clone(), metadata);
+            }
+            "resolved manually".to_string()
+        }
+    };
+
+    sync_state.resolve_conflict(id, &resolution);
+    sync_state.commit()?;
+    println!("Conflict #{} on {} {}", id, conflict.path, resolution);
+    Ok(())
+}
@@ -0,0 +1,492 @@
+use super::events::{normalize, LocalChange};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// Cat asteptam dupa ultimul eveniment pe o cale inainte sa o sincronizam
+pub const DEFAULT_QUIET_PERIOD: Duration = Duration::from_millis(1000);
+
+// Marimea si mtime-ul unui fisier: cat timp se schimba, cineva inca scrie in el
+type Fingerprint = (u64, Option<SystemTime>);
+
+struct Pending {
+    change: LocalChange,
+    // dupa redenumire s-a scris si in noua cale
+    modified_after: bool,
+    full_path: PathBuf,
+    // ordinea primului eveniment, ca directoarele sa vina inaintea fisierelor din ele
+    seq: u64,
+    last_event: Instant,
+    fingerprint: Option<Fingerprint>,
+}
+
+// Aduna evenimentele pe cale si le da mai departe abia dupa o perioada de liniste,
+// cand fisierul nu mai creste: un `cp` sau un editor care salveaza produc zeci de
+// evenimente pentru o singura schimbare.
+pub struct Debouncer {
+    quiet: Duration,
+    pending: HashMap<(usize, String), Pending>,
+    next_seq: u64,
+    halves: Vec<RenameHalf>,
+}
+
+// Jumatatea unei mutari: inotify trimite From, To si apoi Both cu acelasi tracker. Le tinem
+// deoparte ca sa nu para o stergere si o creare; raman singure doar daca s-a mutat in sau
+// din afara folderului.
+struct RenameHalf {
+    location: usize,
+    tracker: usize,
+    root: PathBuf,
+    changes: Vec<LocalChange>,
+    at: Instant,
+}
+
+impl Debouncer {
+    pub fn new(quiet: Duration) -> Self {
+        Debouncer {
+            quiet,
+            pending: HashMap::new(),
+            next_seq: 0,
+            halves: Vec::new(),
+        }
+    }
+
+    pub fn push_event(&mut self, location: usize, root: &Path, event: &Event, now: Instant) {
+        match (&event.kind, event.tracker()) {
+            (
+                EventKind::Modify(ModifyKind::Name(RenameMode::From | RenameMode::To)),
+                Some(tracker),
+            ) => {
+                self.halves.push(RenameHalf {
+                    location,
+                    tracker,
+                    root: root.to_path_buf(),
+                    changes: normalize(root, event),
+                    at: now,
+                });
+                return;
+            }
+            (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), Some(tracker)) => {
+                self.halves
+                    .retain(|h| h.location != location || h.tracker != tracker);
+            }
+            _ => {}
+        }
+        for change in normalize(root, event) {
+            self.push(location, root, change, now);
+        }
+    }
+
+    pub fn push(&mut self, location: usize, root: &Path, change: LocalChange, now: Instant) {
+        let mut modified_after = false;
+        // o mutare a ceva care astepta isi pastreaza locul in ordine
+        let mut seq = None;
+        let change = match change {
+            LocalChange::Renamed { from, to } => {
+                self.move_children(location, root, &from, &to);
+                let moved = self.pending.remove(&(location, from.clone()));
+                seq = moved.as_ref().map(|p| p.seq);
+                match moved {
+                    // fisier temporar scris si mutat peste cel final: o singura modificare
+                    Some(Pending {
+                        change: LocalChange::Created { is_dir: false, .. },
+                        ..
+                    }) => LocalChange::Modified { path: to },
+                    Some(Pending {
+                        change: LocalChange::Created { is_dir: true, .. },
+                        ..
+                    }) => LocalChange::Created {
+                        path: to,
+                        is_dir: true,
+                    },
+                    // mutat de doua ori: direct de la prima sursa la ultima destinatie
+                    Some(Pending {
+                        change: LocalChange::Renamed { from: first, .. },
+                        modified_after: modified,
+                        ..
+                    }) => {
+                        modified_after = modified;
+                        LocalChange::Renamed { from: first, to }
+                    }
+                    Some(Pending {
+                        change: LocalChange::Modified { .. },
+                        ..
+                    }) => {
+                        modified_after = true;
+                        LocalChange::Renamed { from, to }
+                    }
+                    _ => LocalChange::Renamed { from, to },
+                }
+            }
+            other => other,
+        };
+
+        let previous = self
+            .pending
+            .remove(&(location, change_path(&change).to_string()));
+        let seq = match (&previous, seq) {
+            (Some(p), _) => p.seq,
+            (None, Some(seq)) => seq,
+            (None, None) => {
+                self.next_seq += 1;
+                self.next_seq
+            }
+        };
+        let (change, modified_after) = match previous {
+            Some(p) => match coalesce(p.change, change, modified_after) {
+                Some(merged) => merged,
+                None => return,
+            },
+            None => (change, modified_after),
+        };
+
+        let full_path = root.join(change_path(&change));
+        self.pending.insert(
+            (location, change_path(&change).to_string()),
+            Pending {
+                fingerprint: fingerprint(&full_path),
+                change,
+                modified_after,
+                full_path,
+                seq,
+                last_event: now,
+            },
+        );
+    }
+
+    // Ce asteapta sub un director mutat se muta odata cu el
+    fn move_children(&mut self, location: usize, root: &Path, from: &str, to: &str) {
+        let prefix = format!("{}/", from);
+        let keys: Vec<_> = self
+            .pending
+            .keys()
+            .filter(|(loc, path)| *loc == location && path.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            let mut pending = self.pending.remove(&key).unwrap();
+            let path = format!("{}/{}", to, &key.1[prefix.len()..]);
+            match &mut pending.change {
+                LocalChange::Created { path: p, .. }
+                | LocalChange::Modified { path: p }
+                | LocalChange::Removed { path: p, .. }
+                | LocalChange::Renamed { to: p, .. } => *p = path.clone(),
+            }
+            pending.full_path = root.join(&path);
+            pending.fingerprint = fingerprint(&pending.full_path);
+            self.pending.insert((location, path), pending);
+        }
+    }
+
+    // Schimbarile linistite de cel putin `quiet`, in ordinea in care au aparut. Un fisier
+    // care si-a schimbat intre timp marimea sau mtime-ul mai asteapta o perioada.
+    pub fn ready(&mut self, now: Instant) -> Vec<(usize, LocalChange)> {
+        let (lonely, halves) = std::mem::take(&mut self.halves)
+            .into_iter()
+            .partition(|h| now.duration_since(h.at) >= self.quiet);
+        self.halves = halves;
+        for half in lonely {
+            let RenameHalf {
+                location,
+                root,
+                changes,
+                at,
+                ..
+            } = half;
+            for change in changes {
+                self.push(location, &root, change, at);
+            }
+        }
+
+        let mut ready = Vec::new();
+        let keys: Vec<_> = self.pending.keys().cloned().collect();
+        for key in keys {
+            let pending = self.pending.get_mut(&key).unwrap();
+            if now.duration_since(pending.last_event) < self.quiet {
+                continue;
+            }
+
+            let writes_content = match &pending.change {
+                LocalChange::Created { is_dir, .. } => !is_dir,
+                LocalChange::Modified { .. } => true,
+                LocalChange::Renamed { .. } => pending.modified_after,
+                LocalChange::Removed { .. } => false,
+            };
+            if writes_content {
+                let current = fingerprint(&pending.full_path);
+                if current.is_some() && current != pending.fingerprint {
+                    pending.fingerprint = current;
+                    pending.last_event = now;
+                    continue;
+                }
+            }
+
+            let pending = self.pending.remove(&key).unwrap();
+            let exists = pending.full_path.exists();
+            let mut changes = match pending.change {
+                // a disparut intre timp: vine (sau a venit deja) si stergerea
+                LocalChange::Created { .. } | LocalChange::Modified { .. } if !exists => vec![],
+                change => vec![change],
+            };
+            if pending.modified_after && exists {
+                changes.push(LocalChange::Modified {
+                    path: key.1.clone(),
+                });
+            }
+            ready.extend(changes.into_iter().map(|c| (pending.seq, key.0, c)));
+        }
+
+        ready.sort_by_key(|(seq, _, _)| *seq);
+        ready.into_iter().map(|(_, loc, c)| (loc, c)).collect()
+    }
+}
+
+fn change_path(change: &LocalChange) -> &str {
+    match change {
+        LocalChange::Created { path, .. }
+        | LocalChange::Modified { path }
+        | LocalChange::Removed { path, .. } => path,
+        LocalChange::Renamed { to, .. } => to,
+    }
+}
+
+fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let meta = fs::metadata(path).ok()?;
+    meta.is_file().then(|| (meta.len(), meta.modified().ok()))
+}
+
+// Doua schimbari succesive pe aceeasi cale; None daca se anuleaza (creat si sters la loc)
+fn coalesce(
+    previous: LocalChange,
+    next: LocalChange,
+    next_modified_after: bool,
+) -> Option<(LocalChange, bool)> {
+    use LocalChange::*;
+    Some(match (previous, next) {
+        (Created { is_dir: false, .. }, Removed { .. }) => return None,
+        (
+            created @ Created { is_dir: false, .. },
+            Modified { .. } | Created { is_dir: false, .. },
+        ) => (created, false),
+        (Created { is_dir: true, .. }, Removed { .. }) => return None,
+        (Modified { path }, Modified { .. } | Created { is_dir: false, .. }) => {
+            (Modified { path }, false)
+        }
+        // sters si scris la loc (unele editoare salveaza asa): o modificare
+        (
+            Removed { is_dir: false, .. },
+            Created {
+                path,
+                is_dir: false,
+            }
+            | Modified { path },
+        ) => (Modified { path }, false),
+        (renamed @ Renamed { .. }, Modified { .. } | Created { is_dir: false, .. }) => {
+            (renamed, true)
+        }
+        // mutat si apoi sters: pentru celelalte locatii e doar o stergere a vechii cai
+        (Renamed { from, .. }, Removed { is_dir, .. }) => (Removed { path: from, is_dir }, false),
+        (_, next) => (next, next_modified_after),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_root;
+
+    const QUIET: Duration = Duration::from_millis(200);
+
+    fn created(path: &str) -> LocalChange {
+        LocalChange::Created {
+            path: path.to_string(),
+            is_dir: false,
+        }
+    }
+
+    fn modified(path: &str) -> LocalChange {
+        LocalChange::Modified {
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn burst_is_coalesced_after_quiet_period() {
+        let root = temp_root("debounce_burst");
+        fs::write(root.join("a.txt"), "data").unwrap();
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+
+        debouncer.push(0, &root, created("a.txt"), start);
+        for _ in 0..5 {
+            debouncer.push(0, &root, modified("a.txt"), start);
+        }
+        assert!(debouncer.ready(start + QUIET / 2).is_empty());
+        assert_eq!(debouncer.ready(start + QUIET), vec![(0, created("a.txt"))]);
+        assert!(debouncer.ready(start + QUIET * 3).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn temp_then_rename_is_a_single_modify() {
+        let root = temp_root("debounce_save");
+        fs::write(root.join("report.txt"), "new version").unwrap();
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+
+        debouncer.push(0, &root, created("report.txt.tmp123"), start);
+        debouncer.push(0, &root, modified("report.txt.tmp123"), start);
+        debouncer.push(
+            0,
+            &root,
+            LocalChange::Renamed {
+                from: "report.txt.tmp123".to_string(),
+                to: "report.txt".to_string(),
+            },
+            start,
+        );
+        assert_eq!(
+            debouncer.ready(start + QUIET),
+            vec![(0, modified("report.txt"))]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // cum vine o mutare de la inotify: cele doua jumatati si apoi perechea
+    #[test]
+    fn rename_halves_are_replaced_by_the_pair() {
+        let root = temp_root("debounce_halves");
+        let (temp, target) = (root.join(".report.txt.swp"), root.join("report.txt"));
+        fs::write(&target, "new version").unwrap();
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+
+        let rename = |mode, paths: &[&PathBuf]| {
+            paths.iter().fold(
+                Event::new(EventKind::Modify(ModifyKind::Name(mode))).set_tracker(7),
+                |e, p| e.add_path(p.to_path_buf()),
+            )
+        };
+        debouncer.push(0, &root, created(".report.txt.swp"), start);
+        debouncer.push_event(0, &root, &rename(RenameMode::From, &[&temp]), start);
+        debouncer.push_event(0, &root, &rename(RenameMode::To, &[&target]), start);
+        debouncer.push_event(
+            0,
+            &root,
+            &rename(RenameMode::Both, &[&temp, &target]),
+            start,
+        );
+        assert_eq!(
+            debouncer.ready(start + QUIET),
+            vec![(0, modified("report.txt"))]
+        );
+
+        // mutat in afara folderului: ramane doar jumatatea From, adica o stergere
+        let outside = root.parent().unwrap().join("report.txt.moved");
+        fs::rename(&target, &outside).unwrap();
+        debouncer.push_event(0, &root, &rename(RenameMode::From, &[&target]), start);
+        assert_eq!(
+            debouncer.ready(start + QUIET * 2),
+            vec![(
+                0,
+                LocalChange::Removed {
+                    path: "report.txt".to_string(),
+                    is_dir: false
+                }
+            )]
+        );
+
+        fs::remove_file(outside).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn created_and_removed_cancel_out() {
+        let root = temp_root("debounce_cancel");
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+
+        debouncer.push(0, &root, created("scratch.txt"), start);
+        debouncer.push(
+            0,
+            &root,
+            LocalChange::Removed {
+                path: "scratch.txt".to_string(),
+                is_dir: false,
+            },
+            start,
+        );
+        assert!(debouncer.ready(start + QUIET).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn file_still_being_written_waits() {
+        let root = temp_root("debounce_growing");
+        let file = root.join("big.bin");
+        fs::write(&file, vec![0u8; 10]).unwrap();
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+        debouncer.push(0, &root, created("big.bin"), start);
+
+        // scrierea continua fara evenimente noi (ex. un watcher care le-a pierdut)
+        fs::write(&file, vec![0u8; 20]).unwrap();
+        assert!(debouncer.ready(start + QUIET).is_empty());
+        assert_eq!(
+            debouncer.ready(start + QUIET * 2),
+            vec![(0, created("big.bin"))]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn directory_comes_before_its_files_and_moves_with_them() {
+        let root = temp_root("debounce_dirs");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/a.txt"), "a").unwrap();
+        let mut debouncer = Debouncer::new(QUIET);
+        let start = Instant::now();
+
+        debouncer.push(
+            0,
+            &root,
+            LocalChange::Created {
+                path: "new".to_string(),
+                is_dir: true,
+            },
+            start,
+        );
+        debouncer.push(0, &root, created("new/a.txt"), start);
+        debouncer.push(
+            0,
+            &root,
+            LocalChange::Renamed {
+                from: "new".to_string(),
+                to: "docs".to_string(),
+            },
+            start,
+        );
+        assert_eq!(
+            debouncer.ready(start + QUIET),
+            vec![
+                (
+                    0,
+                    LocalChange::Created {
+                        path: "docs".to_string(),
+                        is_dir: true
+                    }
+                ),
+                (0, created("docs/a.txt")),
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use super::events::LocalChange;
+use super::reconcile::SyncAction;
+use crate::locations::{same_hash, FileMetadata, HashAlgorithm};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Cat timp dupa o scriere a noastra ne asteptam sa o vedem inapoi (watcher dupa debounce,
+// sau urmatorul poll)
+pub const ECHO_WINDOW: Duration = Duration::from_secs(30);
+
+// Ce ar trebui sa gasim la o cale dupa ce am scris-o noi
+#[derive(Debug, Clone, PartialEq)]
+enum Expected {
+    // hash-ul continutului scris, daca il stim
+    File(Option<String>),
+    Dir,
+    Absent,
+}
+
+// Scrierile facute de sincronizare intr-o locatie. Cand watcher-ul sau un poll vede din nou
+// exact ce am scris, e ecoul scrierii noastre si nu trebuie trimis inapoi in celelalte locatii.
+// O schimbare reala are alt continut (alt hash), asa ca trece mai departe.
+#[derive(Default)]
+pub struct EchoGuard {
+    writes: HashMap<(usize, String), (Expected, Instant)>,
+}
+
+impl EchoGuard {
+    pub fn new() -> Self {
+        EchoGuard::default()
+    }
+
+    pub fn expect_file(&mut self, location: usize, path: &str, hash: Option<String>) {
+        self.expect(location, path, Expected::File(hash));
+    }
+
+    pub fn expect_dir(&mut self, location: usize, path: &str) {
+        self.expect(location, path, Expected::Dir);
+    }
+
+    pub fn expect_absent(&mut self, location: usize, path: &str) {
+        self.expect(location, path, Expected::Absent);
+    }
+
+    fn expect(&mut self, location: usize, path: &str, expected: Expected) {
+        let now = Instant::now();
+        self.writes.retain(|_, (_, deadline)| *deadline > now);
+        self.writes
+            .insert((location, path.to_string()), (expected, now + ECHO_WINDOW));
+    }
+
+    fn expected(&self, location: usize, path: &str) -> Option<&Expected> {
+        self.writes
+            .get(&(location, path.to_string()))
+            .filter(|(_, deadline)| *deadline > Instant::now())
+            .map(|(expected, _)| expected)
+    }
+
+    // Ce lasa in urma, in fiecare locatie tinta, o actiune reusita
+    pub fn expect_action(&mut self, action: &SyncAction) {
+        match action {
+            SyncAction::CreateDir { path, targets, .. } => {
+                for &i in targets {
+                    self.expect_dir(i, path);
+                }
+            }
+            SyncAction::RemoveDir { path, targets }
+            | SyncAction::DeleteFile { path, targets, .. } => {
+                for &i in targets {
+                    self.expect_absent(i, path);
+                }
+            }
+            SyncAction::CopyFile {
+                path,
+                targets,
+                metadata,
+                ..
+            } => {
+                for &i in targets {
+                    self.expect_file(i, path, metadata.hash.clone());
+                }
+            }
+            SyncAction::SaveConflictCopy {
+                copy_path,
+                targets,
+                metadata,
+                ..
+            } => {
+                for &i in targets {
+                    self.expect_file(i, copy_path, metadata.hash.clone());
+                }
+            }
+            SyncAction::RenameFile {
+                from,
+                to,
+                targets,
+                metadata,
+                ..
+            } => {
+                for &i in targets {
+                    self.expect_absent(i, from);
+                    self.expect_file(i, to, metadata.hash.clone());
+                }
+            }
+            SyncAction::RecordFile { .. }
+            | SyncAction::ForgetFile { .. }
+            | SyncAction::LogConflict { .. } => {}
+        }
+    }
+
+    // Un fisier gasit la poll in `location`: e cel scris de noi? Fara hash pe una din parti
+    // nu putem sti, asa ca in fereastra de ecou il consideram al nostru; o modificare reala
+    // facuta chiar atunci se vede la primul poll de dupa fereastra.
+    pub fn is_echo_file(
+        &self,
+        location: usize,
+        path: &str,
+        current: Option<&FileMetadata>,
+    ) -> bool {
+        match (self.expected(location, path), current) {
+            (Some(Expected::File(Some(hash))), Some(cur)) => {
+                // un hash din alt algoritm nu spune nimic, ca si lipsa lui
+                cur.hash
+                    .as_deref()
+                    .is_none_or(|h| same_hash(h, hash) != Some(false))
+            }
+            (Some(Expected::File(None)), Some(_)) => true,
+            (Some(Expected::Absent), None) => true,
+            _ => false,
+        }
+    }
+
+    // O schimbare venita de la watcher-ul locatiei `location` (un folder cu radacina `root`)
+    pub fn is_echo(&self, location: usize, root: &Path, change: &LocalChange) -> bool {
+        let file_matches = |path: &str| match self.expected(location, path) {
+            Some(Expected::File(Some(hash))) => HashAlgorithm::of(hash)
+                .and_then(|algorithm| algorithm.hash_file(&root.join(path)))
+                .is_some_and(|current| current == *hash),
+            Some(Expected::File(None)) => root.join(path).is_file(),
+            _ => false,
+        };
+        match change {
+            LocalChange::Created { path, is_dir: true } => {
+                self.expected(location, path) == Some(&Expected::Dir)
+            }
+            LocalChange::Created {
+                path,
+                is_dir: false,
+            }
+            | LocalChange::Modified { path } => file_matches(path),
+            LocalChange::Removed { path, .. } => {
+                self.expected(location, path) == Some(&Expected::Absent)
+            }
+            LocalChange::Renamed { from, to } => {
+                self.expected(location, from) == Some(&Expected::Absent)
+                    && (file_matches(to) || self.expected(location, to) == Some(&Expected::Dir))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::debounce::Debouncer;
+    use super::super::watchers::{watch_locations, LocationEvent};
+    use super::super::{handle_local_change, SyncState};
+    use super::*;
+    use crate::locations::parse_location;
+    use crate::test_util::temp_root;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::SystemTime;
+
+    fn file(hash: Option<&str>) -> FileMetadata {
+        FileMetadata {
+            path: "a.txt".to_string(),
+            modified: SystemTime::now(),
+            size: 3,
+            hash: hash.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn poll_sees_only_what_we_wrote_as_echo() {
+        let mut echoes = EchoGuard::new();
+        echoes.expect_file(1, "a.txt", Some("sha256:abc".to_string()));
+        echoes.expect_absent(1, "gone.txt");
+
+        assert!(echoes.is_echo_file(1, "a.txt", Some(&file(Some("sha256:abc")))));
+        // fara hash in listing (ex. FTP fara HASH) nu putem deosebi, il consideram al nostru
+        assert!(echoes.is_echo_file(1, "a.txt", Some(&file(None))));
+        assert!(!echoes.is_echo_file(1, "a.txt", Some(&file(Some("sha256:def")))));
+        assert!(!echoes.is_echo_file(0, "a.txt", Some(&file(Some("sha256:abc")))));
+        assert!(echoes.is_echo_file(1, "gone.txt", None));
+        assert!(!echoes.is_echo_file(1, "gone.txt", Some(&file(Some("sha256:abc")))));
+    }
+
+    // Doua foldere urmarite, ca in bucla principala: o schimbare intr-unul ajunge in celalalt
+    // o singura data, iar ecoul copierii nu se mai intoarce. Dupa asta nu mai vine nimic.
+    #[test]
+    fn two_watched_folders_settle() {
+        let (a, b) = (temp_root("echoes_a"), temp_root("echoes_b"));
+        // cache de hash-uri separat, nu cel al utilizatorului
+        let cache = temp_root("echoes_cache").join("hashes.db");
+        let folder = |root: &PathBuf| {
+            let spec = format!("folder:{}?hash-cache={}", root.display(), cache.display());
+            parse_location(&spec, HashAlgorithm::Blake3).unwrap()
+        };
+        let mut locations = vec![folder(&a), folder(&b)];
+        let mut sync_state = SyncState::new();
+        let watchers = watch_locations(&locations).unwrap();
+        let mut debouncer = Debouncer::new(Duration::from_millis(200));
+
+        // cate schimbari ajung la motor din fiecare locatie, pana se linisteste totul
+        let mut run = |locations: &mut Vec<_>, sync_state: &mut SyncState| {
+            let mut handled = [0, 0];
+            let mut quiet_since = Instant::now();
+            while quiet_since.elapsed() < Duration::from_secs(2) {
+                while let Ok(LocationEvent {
+                    location,
+                    root,
+                    event,
+                }) = watchers.events.try_recv()
+                {
+                    debouncer.push_event(location, &root, &event.unwrap(), Instant::now());
+                    quiet_since = Instant::now();
+                }
+                for (location, change) in debouncer.ready(Instant::now()) {
+                    if handle_local_change(location, change, locations, sync_state).unwrap() {
+                        handled[location] += 1;
+                    }
+                    quiet_since = Instant::now();
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            handled
+        };
+
+        fs::create_dir(a.join("docs")).unwrap();
+        fs::write(a.join("docs/notes.txt"), "first").unwrap();
+        assert_eq!(run(&mut locations, &mut sync_state), [2, 0]);
+        assert_eq!(
+            fs::read_to_string(b.join("docs/notes.txt")).unwrap(),
+            "first"
+        );
+
+        // o modificare reala in locatia unde tocmai am scris nu e luata drept ecou
+        fs::write(b.join("docs/notes.txt"), "second").unwrap();
+        assert_eq!(run(&mut locations, &mut sync_state), [0, 1]);
+        assert_eq!(
+            fs::read_to_string(a.join("docs/notes.txt")).unwrap(),
+            "second"
+        );
+
+        fs::rename(a.join("docs/notes.txt"), a.join("docs/renamed.txt")).unwrap();
+        assert_eq!(run(&mut locations, &mut sync_state), [1, 0]);
+        assert!(!b.join("docs/notes.txt").exists());
+        assert_eq!(
+            fs::read_to_string(b.join("docs/renamed.txt")).unwrap(),
+            "second"
+        );
+
+        fs::remove_file(b.join("docs/renamed.txt")).unwrap();
+        assert_eq!(run(&mut locations, &mut sync_state), [0, 1]);
+        assert!(!a.join("docs/renamed.txt").exists());
+
+        // nimic nu mai circula intre ele
+        assert_eq!(run(&mut locations, &mut sync_state), [0, 0]);
+
+        drop(watchers);
+        fs::remove_dir_all(&a).unwrap();
+        fs::remove_dir_all(&b).unwrap();
+        fs::remove_dir_all(cache.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+use crate::locations::is_transfer_temp;
+use notify::event::{AccessKind, AccessMode, CreateKind, ModifyKind, RemoveKind, RenameMode};
+use notify::{Event, EventKind};
+use std::path::Path;
+use walkdir::WalkDir;
+
+// Ce s-a schimbat intr-o locatie urmarita, cu cai relative la radacina ei (separate prin `/`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum LocalChange {
+    Created { path: String, is_dir: bool },
+    Modified { path: String },
+    // `is_dir` e false si cand backend-ul nu spune ce a fost sters
+    Removed { path: String, is_dir: bool },
+    Renamed { from: String, to: String },
+}
+
+// Calea relativa fata de `root`; None pentru radacina insasi sau o cale din afara ei
+pub fn relative_path(root: &Path, path: &Path) -> Option<String> {
+    let rel = path.strip_prefix(root).ok()?;
+    let parts: Vec<_> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+// Traduce un eveniment notify (oricare ar fi backend-ul) in schimbari relative la `root`.
+// Unde evenimentul nu spune destul (`Any`), ne uitam pe disc; ce nu mai exista cand ajungem
+// la el a fost sters intre timp si va veni ca Remove.
+pub fn normalize(root: &Path, event: &Event) -> Vec<LocalChange> {
+    let mut changes = Vec::new();
+    let relative = |path: &Path| relative_path(root, path).filter(|_| !is_transfer_temp(path));
+
+    match &event.kind {
+        EventKind::Create(kind) => {
+            for path in &event.paths {
+                let Some(rel) = relative(path) else { continue };
+                let is_dir = match kind {
+                    CreateKind::Folder => true,
+                    CreateKind::File => false,
+                    _ if !path.exists() => continue,
+                    _ => path.is_dir(),
+                };
+                changes.push(LocalChange::Created { path: rel, is_dir });
+                if is_dir {
+                    created_contents(path, &relative, &mut changes);
+                }
+            }
+        }
+
+        // mutare cu ambele capete cunoscute; un capat din afara radacinii e o creare/stergere
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+            let (from, to) = (&event.paths[0], &event.paths[1]);
+            match (relative(from), relative(to)) {
+                (Some(from), Some(to)) => changes.push(LocalChange::Renamed { from, to }),
+                (Some(from), None) => changes.push(LocalChange::Removed {
+                    path: from,
+                    is_dir: false,
+                }),
+                (None, Some(to)) => {
+                    changes.push(LocalChange::Created {
+                        path: to,
+                        is_dir: event.paths[1].is_dir(),
+                    });
+                    created_contents(&event.paths[1], &relative, &mut changes);
+                }
+                (None, None) => {}
+            }
+        }
+
+        // doar jumatate de mutare (ex. mutat in sau din afara folderului), sau backend-uri
+        // care nu spun directia: decidem dupa ce e pe disc acum
+        EventKind::Modify(ModifyKind::Name(_)) => {
+            for path in &event.paths {
+                let Some(rel) = relative(path) else { continue };
+                if path.exists() {
+                    changes.push(LocalChange::Created {
+                        path: rel,
+                        is_dir: path.is_dir(),
+                    });
+                    created_contents(path, &relative, &mut changes);
+                } else {
+                    changes.push(LocalChange::Removed {
+                        path: rel,
+                        is_dir: false,
+                    });
+                }
+            }
+        }
+
+        // continut, metadate (mtime, drepturi) sau un fisier inchis dupa scriere;
+        // la directoare se schimba doar mtime-ul cand li se schimba continutul
+        EventKind::Modify(_) | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            for path in &event.paths {
+                let Some(rel) = relative(path) else { continue };
+                if path.is_file() {
+                    changes.push(LocalChange::Modified { path: rel });
+                }
+            }
+        }
+
+        EventKind::Remove(kind) => {
+            for path in &event.paths {
+                let Some(rel) = relative(path) else { continue };
+                changes.push(LocalChange::Removed {
+                    path: rel,
+                    is_dir: *kind == RemoveKind::Folder,
+                });
+            }
+        }
+
+        // deschideri, citiri si ce nu stim interpreta
+        EventKind::Access(_) | EventKind::Any | EventKind::Other => {}
+    }
+
+    changes.dedup();
+    changes
+}
+
+// Watcher-ul incepe sa urmareasca un director nou abia dupa ce afla de el; ce s-a scris
+// in el pana atunci nu mai produce evenimente, asa ca il luam direct de pe disc
+fn created_contents(
+    dir: &Path,
+    relative: &impl Fn(&Path) -> Option<String>,
+    changes: &mut Vec<LocalChange>,
+) {
+    if !dir.is_dir() {
+        return;
+    }
+    for entry in WalkDir::new(dir).min_depth(1).into_iter().flatten() {
+        if let Some(path) = relative(entry.path()) {
+            changes.push(LocalChange::Created {
+                path,
+                is_dir: entry.file_type().is_dir(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_root;
+    use notify::event::{DataChange, MetadataKind};
+    use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+    use std::fs;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    fn event(kind: EventKind, paths: &[&Path]) -> Event {
+        paths
+            .iter()
+            .fold(Event::new(kind), |e, p| e.add_path(p.to_path_buf()))
+    }
+
+    #[test]
+    fn modify_keeps_nested_path() {
+        let root = temp_root("events_nested");
+        let file = root.join("docs/a/readme.txt");
+        fs::create_dir_all(file.parent().unwrap()).unwrap();
+        fs::write(&file, "hello").unwrap();
+
+        let expected = vec![LocalChange::Modified {
+            path: "docs/a/readme.txt".to_string(),
+        }];
+        for kind in [
+            EventKind::Modify(ModifyKind::Data(DataChange::Content)),
+            EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)),
+            EventKind::Modify(ModifyKind::Any),
+            EventKind::Access(AccessKind::Close(AccessMode::Write)),
+        ] {
+            assert_eq!(normalize(&root, &event(kind, &[&file])), expected);
+        }
+        // directoarele si citirile nu inseamna nimic de sincronizat
+        let dir = root.join("docs");
+        assert!(normalize(&root, &event(EventKind::Modify(ModifyKind::Any), &[&dir])).is_empty());
+        let read = EventKind::Access(AccessKind::Close(AccessMode::Read));
+        assert!(normalize(&root, &event(read, &[&file])).is_empty());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn ignores_paths_outside_root_and_transfer_temps() {
+        let root = temp_root("events_outside");
+        let outside = root.parent().unwrap().join("elsewhere.txt");
+        let temp = root.join("docs/.b.txt.part");
+        let kind = EventKind::Create(CreateKind::File);
+        assert!(normalize(&root, &event(kind, &[&outside, &temp, &root])).is_empty());
+
+        // mutat din afara folderului inauntru: e o creare
+        let inside = root.join("in.txt");
+        fs::write(&inside, "x").unwrap();
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        assert_eq!(
+            normalize(&root, &event(rename, &[&outside, &inside])),
+            vec![LocalChange::Created {
+                path: "in.txt".to_string(),
+                is_dir: false
+            }]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn new_dir_brings_what_was_already_written_in_it() {
+        let root = temp_root("events_new_dir");
+        let dir = root.join("docs");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("sub/notes.txt"), "x").unwrap();
+        fs::write(dir.join(".notes.txt.part"), "x").unwrap();
+
+        let changes = normalize(
+            &root,
+            &event(EventKind::Create(CreateKind::Folder), &[&dir]),
+        );
+        let created = |path: &str, is_dir| LocalChange::Created {
+            path: path.to_string(),
+            is_dir,
+        };
+        assert_eq!(
+            changes,
+            vec![
+                created("docs", true),
+                created("docs/sub", true),
+                created("docs/sub/notes.txt", false),
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    // Evenimentele reale de la watcher pentru operatiile obisnuite, pe un director temporar
+    #[test]
+    fn real_directory_events() {
+        let root = temp_root("events_real");
+        let (tx, rx) = channel();
+        let mut watcher = RecommendedWatcher::new(
+            move |e: notify::Result<Event>| {
+                let _ = tx.send(e);
+            },
+            notify::Config::default(),
+        )
+        .unwrap();
+        watcher.watch(&root, RecursiveMode::Recursive).unwrap();
+
+        // dupa fiecare pas adunam evenimentele pana se linisteste watcher-ul
+        let collect = || {
+            let mut changes = Vec::new();
+            while let Ok(e) = rx.recv_timeout(Duration::from_millis(500)) {
+                changes.extend(normalize(&root, &e.unwrap()));
+            }
+            changes
+        };
+        let created = |path: &str, is_dir| LocalChange::Created {
+            path: path.to_string(),
+            is_dir,
+        };
+        let modified = |path: &str| LocalChange::Modified {
+            path: path.to_string(),
+        };
+
+        fs::create_dir_all(root.join("docs/a")).unwrap();
+        let changes = collect();
+        assert!(changes.contains(&created("docs", true)), "{:?}", changes);
+
+        fs::write(root.join("docs/a/readme.txt"), "hello").unwrap();
+        let changes = collect();
+        assert!(
+            changes.contains(&created("docs/a/readme.txt", false)),
+            "{:?}",
+            changes
+        );
+
+        fs::write(root.join("docs/a/readme.txt"), "hello again").unwrap();
+        let changes = collect();
+        assert!(
+            changes.contains(&modified("docs/a/readme.txt")),
+            "{:?}",
+            changes
+        );
+        assert!(!changes.contains(&modified("readme.txt")), "{:?}", changes);
+
+        fs::rename(root.join("docs/a/readme.txt"), root.join("docs/notes.txt")).unwrap();
+        let changes = collect();
+        assert!(
+            changes.contains(&LocalChange::Renamed {
+                from: "docs/a/readme.txt".to_string(),
+                to: "docs/notes.txt".to_string(),
+            }) || changes.contains(&created("docs/notes.txt", false)),
+            "{:?}",
+            changes
+        );
+
+        fs::remove_file(root.join("docs/notes.txt")).unwrap();
+        let changes = collect();
+        assert!(
+            changes.iter().any(
+                |c| matches!(c, LocalChange::Removed { path, .. } if path == "docs/notes.txt")
+            ),
+            "{:?}",
+            changes
+        );
+
+        drop(watcher);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,127 @@
+use crate::errors::SyncError;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Dupa prima eroare reincercam la urmatorul poll, apoi tot mai rar
+const FIRST_RETRY: Duration = Duration::from_secs(10);
+const MAX_RETRY: Duration = Duration::from_secs(3600);
+
+// O cale (sau o locatie intreaga, cu `path` gol) care nu s-a putut sincroniza
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub path: String,
+    pub location: Option<usize>,
+    pub operation: String,
+    pub error: String,
+    // de cate ori la rand a esuat
+    pub count: u32,
+    pub retry_after: Instant,
+}
+
+// Erorile din trecerile de sincronizare, pastrate intre cicluri ca sa stim ce
+// reincercam si cand
+#[derive(Default)]
+pub struct FailureTracker {
+    failures: BTreeMap<String, Failure>,
+    // ce a esuat sau a fost amanat in trecerea curenta
+    this_pass: Vec<String>,
+}
+
+impl FailureTracker {
+    pub fn new() -> Self {
+        FailureTracker::default()
+    }
+
+    pub fn start_pass(&mut self) {
+        self.this_pass.clear();
+    }
+
+    // Cheia e calea; pentru o locatie care nu s-a putut lista folosim `#<index>`
+    fn key(path: &str, location: Option<usize>) -> String {
+        match (path.is_empty(), location) {
+            (true, Some(i)) => format!("#{}", i),
+            _ => path.to_string(),
+        }
+    }
+
+    pub fn record(
+        &mut self,
+        path: &str,
+        location: Option<usize>,
+        operation: &str,
+        error: &SyncError,
+    ) {
+        let key = Self::key(path, location);
+        let count = self.failures.get(&key).map_or(0, |f| f.count) + 1;
+        let backoff = FIRST_RETRY
+            .saturating_mul(1 << (count - 1).min(16))
+            .min(MAX_RETRY);
+        self.failures.insert(
+            key.clone(),
+            Failure {
+                path: path.to_string(),
+                location,
+                operation: operation.to_string(),
+                error: error.to_string(),
+                count,
+                retry_after: Instant::now() + backoff,
+            },
+        );
+        self.this_pass.push(key);
+    }
+
+    pub fn succeeded(&mut self, path: &str, location: Option<usize>) {
+        let key = Self::key(path, location);
+        if self.failures.remove(&key).is_some() {
+            println!("{} is syncing again after earlier failures", key);
+        }
+    }
+
+    // O cale care a tot esuat o lasam in pace pana ii vine randul
+    pub fn should_skip(&mut self, path: &str) -> bool {
+        match self.failures.get(path) {
+            Some(f) if Instant::now() < f.retry_after => {
+                self.this_pass.push(path.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    // Raportul de la sfarsitul unui ciclu: ce n-a mers, de ce si de cate ori
+    pub fn print_report(&self) {
+        if self.this_pass.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        let mut keys = self.this_pass.clone();
+        keys.sort();
+        keys.dedup();
+
+        println!("Sync pass finished with {} failed path(s):", keys.len());
+        for failure in keys.iter().filter_map(|k| self.failures.get(k)) {
+            let location = failure
+                .location
+                .map(|i| format!("location #{}", i))
+                .unwrap_or_else(|| "all locations".to_string());
+            let path = if failure.path.is_empty() {
+                "<listing>"
+            } else {
+                &failure.path
+            };
+            println!(
+                "    {} [{}, {}]: {} (failed {} time(s), next retry in {}s)",
+                path,
+                location,
+                failure.operation,
+                failure.error,
+                failure.count,
+                failure.retry_after.saturating_duration_since(now).as_secs()
+            );
+        }
+    }
+}
@@ -1,17 +1,46 @@
+pub mod conflicts;
+pub mod debounce;
+pub mod echoes;
+pub mod events;
+pub mod failures;
+pub mod reconcile;
+mod state_db;
 pub mod watchers;
 use crate::errors::SyncError;
-use crate::locations::{DirMetadata, FileMetadata, FolderLocation, Location, ZipLocation};
-use notify::{
-    event::{ModifyKind, RemoveKind},
-    Event, EventKind,
-};
-use std::collections::{HashMap, HashSet};
+use crate::locations::{DirMetadata, FileMetadata, HashAlgorithm, Location};
+use conflicts::{ConflictPolicies, ConflictRecord};
+use echoes::EchoGuard;
+use events::LocalChange;
+use failures::FailureTracker;
+use reconcile::{execute_plan, move_file, run_actions, Snapshot, SyncAction, SyncPlan};
+use state_db::{DirChange, FileChange, PendingChanges, StateDb};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::time::SystemTime;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+pub const DEFAULT_MTIME_TOLERANCE: Duration = Duration::from_secs(2);
 
 pub struct SyncState {
     pub file_metadata: HashMap<String, FileMetadata>,
     pub dir_metadata: HashMap<String, DirMetadata>,
+    // fisiere sterse care inca exista intr-o locatie read-only
+    pub tombstones: HashMap<String, FileMetadata>,
+    pub conflict_policies: ConflictPolicies,
+    // cat pot diferi doi timpi de modificare ai aceluiasi continut
+    pub mtime_tolerance: Duration,
+    // ce n-a mers in trecerile anterioare (doar in memorie)
+    pub failures: FailureTracker,
+    // scrierile noastre recente, ca sa nu le luam drept schimbari (doar in memorie)
+    pub echoes: EchoGuard,
+    open_conflicts: Vec<ConflictRecord>,
+    db: Option<StateDb>,
+    // caile modificate de la ultimul commit
+    dirty_files: HashSet<String>,
+    dirty_dirs: HashSet<String>,
+    dirty_tombstones: HashSet<String>,
+    new_conflicts: Vec<ConflictRecord>,
+    new_resolutions: Vec<(i64, String)>,
 }
 
 impl SyncState {
@@ -19,483 +48,469 @@ impl SyncState {
         SyncState {
             file_metadata: HashMap::new(),
             dir_metadata: HashMap::new(),
+            tombstones: HashMap::new(),
+            conflict_policies: ConflictPolicies::default(),
+            mtime_tolerance: DEFAULT_MTIME_TOLERANCE,
+            failures: FailureTracker::new(),
+            echoes: EchoGuard::new(),
+            open_conflicts: Vec::new(),
+            db: None,
+            dirty_files: HashSet::new(),
+            dirty_dirs: HashSet::new(),
+            dirty_tombstones: HashSet::new(),
+            new_conflicts: Vec::new(),
+            new_resolutions: Vec::new(),
         }
     }
 
+    // Incarcam starea salvata la rularea anterioara (daca exista)
+    pub fn open(path: &Path) -> Result<Self, SyncError> {
+        let db = StateDb::open(path)?;
+        let mut state = SyncState::new();
+        state.file_metadata = db.load_files()?;
+        state.dir_metadata = db.load_dirs()?;
+        state.tombstones = db.load_tombstones()?;
+        state.open_conflicts = db.load_conflicts(true)?;
+        state.db = Some(db);
+        println!(
+            "Loaded sync state from {:?}: {} files, {} dirs, {} open conflicts",
+            path,
+            state.file_metadata.len(),
+            state.dir_metadata.len(),
+            state.open_conflicts.len()
+        );
+        Ok(state)
+    }
+
     pub fn update_file(&mut self, path: String, metadata: FileMetadata) {
+        if self.tombstones.remove(&path).is_some() {
+            self.dirty_tombstones.insert(path.clone());
+        }
+        // Fisierul s-a sincronizat intre timp, conflictele deschise pe el nu mai au sens
+        let (closed, open): (Vec<_>, Vec<_>) = std::mem::take(&mut self.open_conflicts)
+            .into_iter()
+            .partition(|c| c.path == path);
+        self.open_conflicts = open;
+        for conflict in closed {
+            if let Some(id) = conflict.id {
+                self.new_resolutions
+                    .push((id, "resolved by a later sync".to_string()));
+            }
+        }
+        self.dirty_files.insert(path.clone());
         self.file_metadata.insert(path, metadata);
     }
 
     pub fn remove_file(&mut self, path: &str) {
+        self.dirty_files.insert(path.to_string());
         self.file_metadata.remove(path);
     }
 
-    pub fn update_dir(&mut self, path: String, metadata: DirMetadata) {
-        self.dir_metadata.insert(path, metadata);
-    }
-    pub fn remove_dir(&mut self, path: &str) {
-        self.dir_metadata.remove(path);
+    pub fn add_tombstone(&mut self, metadata: FileMetadata) {
+        self.dirty_tombstones.insert(metadata.path.clone());
+        self.tombstones.insert(metadata.path.clone(), metadata);
     }
-}
-
-pub fn handle_local_event(
-    event: &Event,
-    locations: &mut Vec<Box<dyn Location>>,
-    sync_state: &mut SyncState,
-) -> Result<(), SyncError> {
-    match &event.kind {
-        EventKind::Create(_) => {
-            for path in &event.paths {
-                let meta = match fs::metadata(path) {
-                    Ok(m) => m,
-                    Err(e) => {
-                        eprintln!("Failed to read metadata for {:?}: {:?}", path, e);
-                        continue; // Trecem peste
-                    }
-                };
-
-                let folder_loc = locations
-                    .iter()
-                    .find_map(|loc| loc.as_any().downcast_ref::<FolderLocation>());
-
-                let relative_path = if let Some(folder) = folder_loc {
-                    match path.strip_prefix(&folder.path) {
-                        Ok(rel) => rel.to_string_lossy().to_string(),
-                        Err(_) => path.to_string_lossy().to_string(),
-                    }
-                } else {
-                    path.to_string_lossy().to_string()
-                };
-
-                if meta.is_dir() {
-                    println!("Handling create for directory: {}", relative_path);
-
-                    for loc in locations.iter_mut() {
-                        if !loc.as_any().is::<ZipLocation>() {
-                            loc.create_dir(&relative_path)?;
-                            println!("Directory '{}' created in location", relative_path);
-                        }
-                    }
-
-                    sync_state.update_dir(
-                        relative_path.clone(),
-                        DirMetadata {
-                            path: relative_path.clone(),
-                            modified: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
-                        },
-                    );
-                } else {
-                    println!("Handling create for file: {}", relative_path);
-                    sync_file(locations, &relative_path, sync_state)?;
-                }
-            }
-        }
-
-        EventKind::Modify(ModifyKind::Data(_)) => {
-            for path in &event.paths {
-                let relative_path = path
-                    .file_name()
-                    .map(|f| f.to_string_lossy().to_string())
-                    .unwrap_or_else(|| path.to_string_lossy().to_string());
 
-                println!("Handling modify for file: {}", relative_path);
-                sync_file(locations, &relative_path, sync_state)?;
-            }
+    pub fn record_conflict(&mut self, conflict: ConflictRecord) {
+        if self
+            .open_conflicts
+            .iter()
+            .chain(&self.new_conflicts)
+            .any(|c| c.resolution.is_none() && c.same_versions(&conflict))
+        {
+            return;
         }
+        self.new_conflicts.push(conflict);
+    }
 
-        EventKind::Remove(kind) => {
-            for path in &event.paths {
-                let is_dir = match kind {
-                    RemoveKind::Folder => true,
-                    RemoveKind::Any => false,
-                    RemoveKind::File => false,
-                    RemoveKind::Other => false,
-                };
-
-                let folder_loc = locations
-                    .iter()
-                    .find_map(|loc| loc.as_any().downcast_ref::<FolderLocation>());
-                let relative_path = if let Some(folder) = folder_loc {
-                    match path.strip_prefix(&folder.path) {
-                        Ok(rel) => rel.to_string_lossy().to_string(),
-                        Err(_) => path.to_string_lossy().to_string(),
-                    }
-                } else {
-                    path.to_string_lossy().to_string()
-                };
-
-                if is_dir {
-                    println!("Handling remove for directory: {}", relative_path);
-                    for loc in locations.iter_mut() {
-                        if !loc.as_any().is::<ZipLocation>() {
-                            loc.remove_dir(&relative_path)?;
-                            println!("Directory {} removed from location", relative_path);
-                        }
-                    }
-                    sync_state.remove_dir(&relative_path);
-                } else {
-                    println!("Handling remove for file: {}", relative_path);
-                    for loc in locations.iter_mut() {
-                        if !loc.as_any().is::<ZipLocation>() {
-                            loc.delete_file(&relative_path)?;
-                            println!("File {} deleted from location", relative_path);
-                        }
-                    }
-                    sync_state.remove_file(&relative_path);
-                }
-            }
+    pub fn list_conflicts(&self) -> Result<Vec<ConflictRecord>, SyncError> {
+        match &self.db {
+            Some(db) => db.load_conflicts(false),
+            None => Ok(self.new_conflicts.clone()),
         }
+    }
 
-        _ => {
-            println!("Unhandled event kind: {:?}", event.kind);
+    pub fn get_conflict(&self, id: i64) -> Result<Option<ConflictRecord>, SyncError> {
+        match &self.db {
+            Some(db) => db.get_conflict(id),
+            None => Ok(None),
         }
     }
 
-    Ok(())
-}
-
-pub fn initial_sync_with_state(
-    locations: &[Box<dyn Location>],
-    sync_state: &mut SyncState,
-) -> Result<(), SyncError> {
-    ///////////////////////Directoare////////////////////
-    let mut all_dirs = Vec::new();
-    for loc in locations.iter() {
-        let dirs = loc.list_dirs_recursive()?;
-        all_dirs.push(dirs);
+    pub fn resolve_conflict(&mut self, id: i64, resolution: &str) {
+        self.open_conflicts.retain(|c| c.id != Some(id));
+        self.new_resolutions.push((id, resolution.to_string()));
     }
 
-    // Facem un set cu toate directoarele
-    use std::collections::HashSet;
-    let mut all_dirs_set: HashSet<String> = HashSet::new();
-    for dir_list in &all_dirs {
-        for d in dir_list {
-            all_dirs_set.insert(d.path.clone());
+    // O stare salvata cu alt `?hash=` nu se poate compara prin hash: pana la urmatoarea
+    // trecere, care ii reface hash-urile, acele fisiere se compara dupa dimensiune si timp
+    pub fn check_hash_algorithms(&self, algorithms: &[HashAlgorithm]) {
+        let other = self
+            .file_metadata
+            .values()
+            .filter_map(|f| f.hash.as_deref().and_then(HashAlgorithm::of))
+            .filter(|a| !algorithms.contains(a))
+            .count();
+        if other > 0 {
+            let names: Vec<&str> = algorithms.iter().map(|a| a.name()).collect();
+            println!(
+                "{} files in the sync state were hashed with an algorithm none of the locations use ({}), comparing them by size and time",
+                other,
+                names.join(", ")
+            );
         }
     }
 
-    for dirname in &all_dirs_set {
-        let mut newest_time = SystemTime::UNIX_EPOCH;
-        let mut newest_loc_idx: Option<usize> = None;
-
-        for (i, dir_list) in all_dirs.iter().enumerate() {
-            if let Some(dmeta) = dir_list.iter().find(|x| x.path == *dirname) {
-                if dmeta.modified > newest_time {
-                    newest_time = dmeta.modified;
-                    newest_loc_idx = Some(i);
-                }
+    // Mutam in stare fisierul sau tot continutul directorului
+    pub fn rename_path(&mut self, from: &str, to: &str, keep_tombstones: bool) {
+        let prefix = format!("{}/", from);
+        let moved = |p: &str| -> Option<String> {
+            if p == from {
+                Some(to.to_string())
+            } else {
+                p.strip_prefix(&prefix)
+                    .map(|rest| format!("{}/{}", to, rest))
             }
-        }
+        };
 
-        if let Some(idx) = newest_loc_idx {
-            for (i, loc) in locations.iter().enumerate() {
-                if i != idx {
-                    loc.create_dir(dirname)?;
-                }
+        let files: Vec<String> = self
+            .file_metadata
+            .keys()
+            .filter(|p| moved(p).is_some())
+            .cloned()
+            .collect();
+        for old in files {
+            let new_path = moved(&old).unwrap();
+            let mut meta = self.file_metadata[&old].clone();
+            if keep_tombstones {
+                self.add_tombstone(meta.clone());
             }
-            sync_state.update_dir(
-                dirname.clone(),
-                DirMetadata {
-                    path: dirname.clone(),
-                    modified: newest_time,
-                },
-            );
+            self.remove_file(&old);
+            meta.path = new_path.clone();
+            self.update_file(new_path, meta);
         }
-    }
-
-    //////////////////////////Fisiere//////////////////////////////
-
-    let mut all_metadata: Vec<HashMap<String, FileMetadata>> = Vec::new();
 
-    for loc in locations.iter() {
-        let files = loc.list_files_recursive()?;
-        let mut map = HashMap::new();
-        for f in files {
-            map.insert(f.path.clone(), f);
+        let dirs: Vec<String> = self
+            .dir_metadata
+            .keys()
+            .filter(|p| moved(p).is_some())
+            .cloned()
+            .collect();
+        for old in dirs {
+            let new_path = moved(&old).unwrap();
+            let mut meta = self.dir_metadata[&old].clone();
+            self.remove_dir(&old);
+            meta.path = new_path.clone();
+            self.update_dir(new_path, meta);
         }
-        all_metadata.push(map);
     }
 
-    let mut all_files_set: HashSet<String> = HashSet::new();
-    for map in &all_metadata {
-        all_files_set.extend(map.keys().cloned());
+    pub fn update_dir(&mut self, path: String, metadata: DirMetadata) {
+        self.dirty_dirs.insert(path.clone());
+        self.dir_metadata.insert(path, metadata);
+    }
+    pub fn remove_dir(&mut self, path: &str) {
+        self.dirty_dirs.insert(path.to_string());
+        self.dir_metadata.remove(path);
     }
 
-    for filename in &all_files_set {
-        let mut newest_loc_idx: Option<usize> = None;
-        let mut newest_time = SystemTime::UNIX_EPOCH;
-
-        for (i, map) in all_metadata.iter().enumerate() {
-            if let Some(meta) = map.get(filename) {
-                if meta.modified > newest_time {
-                    newest_time = meta.modified;
-                    newest_loc_idx = Some(i);
-                }
-            }
-        }
-
-        let newest_loc_idx = match newest_loc_idx {
-            Some(idx) => idx,
-            None => continue,
+    // Scriem pe disc, intr-o singura tranzactie, tot ce s-a schimbat de la ultimul commit
+    pub fn commit(&mut self) -> Result<(), SyncError> {
+        let db = match self.db.as_mut() {
+            Some(db) => db,
+            None => return Ok(()),
         };
-
-        let newest_data = locations[newest_loc_idx].read_file(filename)?;
-
-        for (i, loc) in locations.iter().enumerate() {
-            if i == newest_loc_idx {
-                continue;
-            }
-
-            let maybe_meta = all_metadata[i].get(filename);
-            match maybe_meta {
-                Some(meta) if meta.modified < newest_time => {
-                    loc.write_file(filename, &newest_data)?;
-                }
-                None => {
-                    loc.write_file(filename, &newest_data)?;
-                }
-                _ => {}
-            }
+        if self.dirty_files.is_empty()
+            && self.dirty_dirs.is_empty()
+            && self.dirty_tombstones.is_empty()
+            && self.new_conflicts.is_empty()
+            && self.new_resolutions.is_empty()
+        {
+            return Ok(());
         }
 
-        let newest_metadata = all_metadata[newest_loc_idx].get(filename).cloned().unwrap();
-        sync_state.update_file(filename.clone(), newest_metadata);
-
-        println!("Updated SyncState for file: {}", filename); // Logare
+        let files: Vec<FileChange> = self
+            .dirty_files
+            .iter()
+            .map(|path| match self.file_metadata.get(path) {
+                Some(meta) => FileChange::Upsert(meta.clone()),
+                None => FileChange::Remove(path.clone()),
+            })
+            .collect();
+        let dirs: Vec<DirChange> = self
+            .dirty_dirs
+            .iter()
+            .map(|path| match self.dir_metadata.get(path) {
+                Some(meta) => DirChange::Upsert(meta.clone()),
+                None => DirChange::Remove(path.clone()),
+            })
+            .collect();
+        let tombstones: Vec<FileChange> = self
+            .dirty_tombstones
+            .iter()
+            .map(|path| match self.tombstones.get(path) {
+                Some(meta) => FileChange::Upsert(meta.clone()),
+                None => FileChange::Remove(path.clone()),
+            })
+            .collect();
+
+        db.apply(&PendingChanges {
+            files,
+            dirs,
+            tombstones,
+            conflicts: std::mem::take(&mut self.new_conflicts),
+            resolutions: std::mem::take(&mut self.new_resolutions),
+        })?;
+        self.dirty_files.clear();
+        self.dirty_dirs.clear();
+        self.dirty_tombstones.clear();
+
+        // Reincarcam conflictele deschise ca sa avem id-urile celor noi
+        self.open_conflicts = db.load_conflicts(true)?;
+        Ok(())
     }
+}
 
-    Ok(())
+// O schimbare (deja linistita de debouncer) dintr-o locatie urmarita; `source` e locatia
+// in care s-a facut, asa ca pe ea nu o mai atingem. False daca a fost ecoul unei scrieri
+// facute chiar de noi si n-am mai propagat-o.
+pub fn handle_local_change(
+    source: usize,
+    change: LocalChange,
+    locations: &mut [Box<dyn Location>],
+    sync_state: &mut SyncState,
+) -> Result<bool, SyncError> {
+    let Some(root) = locations[source].capabilities().watch_root else {
+        return Ok(false);
+    };
+    if sync_state.echoes.is_echo(source, &root, &change) {
+        println!(
+            "Ignoring our own change in location #{}: {:?}",
+            source, change
+        );
+        return Ok(false);
+    }
+    apply_local_change(source, &root, change, locations, sync_state)?;
+    flush_locations(locations)?;
+    sync_state.commit()?;
+    Ok(true)
 }
 
-pub fn sync_file(
-    locations: &[Box<dyn Location>],
-    filename: &str,
+fn apply_local_change(
+    source: usize,
+    root: &Path,
+    change: LocalChange,
+    locations: &mut [Box<dyn Location>],
     sync_state: &mut SyncState,
 ) -> Result<(), SyncError> {
-    println!("Syncing file {}", filename);
+    match change {
+        LocalChange::Created { path, is_dir: true } => {
+            println!("Handling create for directory: {}", path);
+            let modified = fs::metadata(root.join(&path))
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            let action = SyncAction::CreateDir {
+                path: path.clone(),
+                targets: writable_others(locations, source),
+                metadata: DirMetadata {
+                    path: path.clone(),
+                    modified,
+                },
+            };
+            run_local_action(locations, &path, action, sync_state);
+        }
 
-    let mut newest_loc_idx: Option<usize> = None;
-    let mut newest_metadata: Option<FileMetadata> = None;
-    let mut newest_data: Option<Vec<u8>> = None;
+        LocalChange::Created {
+            path,
+            is_dir: false,
+        } => {
+            println!("Handling create for file: {}", path);
+            sync_file(locations, &path, sync_state)?;
+        }
 
-    // Determinam locatia cu fisierul cel mai recent
-    for (i, loc) in locations.iter().enumerate() {
-        if let Some(metadata) = loc
-            .list_files_recursive()?
-            .into_iter()
-            .find(|f| f.path == filename)
-        {
-            if newest_metadata.is_none() {
-                newest_loc_idx = Some(i);
-                newest_metadata = Some(metadata.clone());
-                newest_data = Some(loc.read_file(filename)?);
+        LocalChange::Modified { path } => {
+            println!("Handling modify for file: {}", path);
+            sync_file(locations, &path, sync_state)?;
+        }
+
+        // backend-urile care nu spun ce s-a sters: stim din stare daca era director
+        LocalChange::Removed { path, is_dir } => {
+            if is_dir || sync_state.dir_metadata.contains_key(&path) {
+                println!("Handling remove for directory: {}", path);
+                let action = SyncAction::RemoveDir {
+                    path: path.clone(),
+                    targets: writable_others(locations, source),
+                };
+                run_local_action(locations, &path, action, sync_state);
+            } else {
+                println!("Handling remove for file: {}", path);
+                let action = SyncAction::DeleteFile {
+                    path: path.clone(),
+                    targets: writable_others(locations, source),
+                    tombstone: None,
+                };
+                run_local_action(locations, &path, action, sync_state);
+            }
+        }
+
+        LocalChange::Renamed { from, to } => {
+            let known = sync_state.file_metadata.contains_key(&from)
+                || sync_state.dir_metadata.contains_key(&from);
+            if known {
+                rename_path(locations, Some(source), &from, &to, sync_state)?;
+            } else if root.join(&to).is_file() {
+                // ex. fisier temporar redenumit peste cel final
                 println!(
-                    "metadata: {:?} curent_newest: {:?}",
-                    metadata.modified,
-                    newest_metadata.as_ref().unwrap().modified
+                    "Handling rename of unknown path {} as create of {}",
+                    from, to
                 );
+                sync_file(locations, &to, sync_state)?;
             } else {
-                // pe "cel mai nou" il comparam cu cel curent
-                let current_newest = newest_metadata.as_ref().unwrap();
-
                 println!(
-                    "metadata: {:?} curent_newest: {:?}",
-                    metadata.modified, current_newest.modified
+                    "Rename of unknown directory {} -> {}, left to polling",
+                    from, to
                 );
-
-                use std::cmp::Ordering;
-
-                match metadata.modified.cmp(&current_newest.modified) {
-                    Ordering::Greater => {
-                        // Timpul este mai nou
-                        let this_hash = metadata.hash.clone().unwrap_or_default();
-                        let current_hash = current_newest.hash.clone().unwrap_or_default();
-
-                        if !this_hash.is_empty()
-                            && !current_hash.is_empty()
-                            && this_hash == current_hash
-                        {
-                            println!("Fisierul {} are un timp mai nou dar acelasi hash. Skipping update...", filename);
-                        } else {
-                            // E clar un fisier mai nou diferit
-                            newest_loc_idx = Some(i);
-                            newest_metadata = Some(metadata.clone());
-                            newest_data = Some(loc.read_file(filename)?);
-                        }
-                    }
-                    Ordering::Equal => {
-                        // Timp egal, verificam hash-ul
-                        println!("Avem un timp egal");
-                        let this_hash = metadata.hash.clone().unwrap_or_default();
-                        let current_hash = current_newest.hash.clone().unwrap_or_default();
-
-                        if !this_hash.is_empty()
-                            && !current_hash.is_empty()
-                            && this_hash != current_hash
-                        {
-                            newest_loc_idx = Some(i);
-                            newest_metadata = Some(metadata.clone());
-                            newest_data = Some(loc.read_file(filename)?);
-                        } else {
-                            println!(
-                                "Fisierul {} are timp egal si acelasi hash. Skipping update...",
-                                filename
-                            );
-                        }
-                    }
-                    Ordering::Less => {
-                        println!(
-                            "Fisierul {} are un timp mai vechi. Skipping update...",
-                            filename
-                        );
-                    }
-                }
             }
         }
     }
+    Ok(())
+}
 
-    let newest_data = match newest_data {
-        Some(data) => data,
-        None => return Ok(()), // Nimic de sincronizat
-    };
-
-    // Propagam fisierul in celelalte locatii unde apare
-    for (i, loc) in locations.iter().enumerate() {
-        if i != newest_loc_idx.unwrap() {
-            if let Some(metadata) = loc
-                .list_files_recursive()?
-                .into_iter()
-                .find(|f| f.path == filename)
-            {
-                //suprascriem
-                if metadata.modified < newest_metadata.as_ref().unwrap().modified {
-                    loc.write_file(filename, &newest_data)?;
-                    println!("File {} updated in location {}", filename, i);
-                }
-            } else {
-                loc.write_file(filename, &newest_data)?;
-                println!("File {} added to location {}", filename, i);
-            }
-        }
+// Scriem modificarile adunate in fiecare locatie inainte sa le consideram sincronizate
+pub fn flush_locations(locations: &[Box<dyn Location>]) -> Result<(), SyncError> {
+    for loc in locations {
+        loc.flush()?;
     }
+    Ok(())
+}
 
-    // Actualizam starea
-    if let Some(metadata) = newest_metadata {
-        sync_state.update_file(filename.to_string(), metadata);
-        println!("Updated SyncState for file: {}\n", filename);
-    }
+// Locatiile in care putem scrie, in afara de cea din care vine schimbarea
+fn writable_others(locations: &[Box<dyn Location>], source: usize) -> Vec<usize> {
+    (0..locations.len())
+        .filter(|&i| i != source && locations[i].capabilities().writable)
+        .collect()
+}
 
-    Ok(())
+// O schimbare venita de la watcher trece prin aceeasi izolare ca o trecere de poll:
+// daca esueaza, o tinem minte pentru calea ei si o reincercam mai tarziu
+fn run_local_action(
+    locations: &[Box<dyn Location>],
+    path: &str,
+    action: SyncAction,
+    sync_state: &mut SyncState,
+) {
+    sync_state.failures.start_pass();
+    run_actions(
+        locations,
+        path,
+        std::slice::from_ref(&action),
+        false,
+        sync_state,
+    );
+    sync_state.failures.print_report();
 }
 
-pub fn poll_locations(
+// Propagam o mutare ca mutare, nu ca stergere + copiere
+pub fn rename_path(
     locations: &[Box<dyn Location>],
+    source: Option<usize>,
+    from: &str,
+    to: &str,
     sync_state: &mut SyncState,
 ) -> Result<(), SyncError> {
-    for (loc_index, loc) in locations.iter().enumerate() {
-        //////////////////Directoare///////////////////////////
-
-        let dirs = loc.list_dirs_recursive()?;
-        let current_dirs: HashSet<String> = dirs.iter().map(|d| d.path.clone()).collect();
-        let known_dirs: HashSet<String> = sync_state.dir_metadata.keys().cloned().collect();
-
-        let removed_dirs: Vec<String> = known_dirs.difference(&current_dirs).cloned().collect();
-
-        if loc.as_any().is::<ZipLocation>() {
-            println!(
-                "Location #{} is ZIP -> skip removing files not found in ZIP.",
-                loc_index
-            );
-        } else {
-            for rd in removed_dirs {
-                println!(
-                    "Detected removed folder `{}` in location #{}",
-                    rd, loc_index
-                );
-
-                if rd.is_empty() || rd == "." {
-                    println!("Skipping remove for empty or '.' path: {}", rd);
-                    continue;
-                } else {
-                    sync_state.remove_dir(&rd);
-
-                    for (i2, other_loc) in locations.iter().enumerate() {
-                        if !other_loc.as_any().is::<ZipLocation>() {
-                            println!("Removing dir `{}` in location #{}", rd, i2);
-                            let _ = other_loc.remove_dir(&rd);
-                        }
-                    }
-                }
+    println!("Handling rename: {} -> {}", from, to);
+    sync_state.failures.start_pass();
+    let file = sync_state.file_metadata.get(from).cloned();
+    let hash = file.as_ref().map(|meta| meta.hash.clone());
+    let modified = file.map(|meta| meta.modified);
+    if sync_state.failures.should_skip(from) {
+        println!("Skipping {} until its next retry", from);
+        return Ok(());
+    }
+    for (i, loc) in locations.iter().enumerate() {
+        if Some(i) != source && loc.capabilities().writable {
+            // starea ramane cea veche; poll-ul vede apoi ce s-a mutat si ce nu
+            if let Err(e) = move_file(locations, source, i, from, to, modified) {
+                println!("Failed to rename {} in location #{}: {}", from, i, e);
+                sync_state.failures.record(from, Some(i), "rename", &e);
+                sync_state.failures.print_report();
+                return flush_locations(locations);
             }
-        }
-
-        // Directoare noi
-        for d in &dirs {
-            if !sync_state.dir_metadata.contains_key(&d.path) {
-                println!(
-                    "Detected new folder `{}` in location #{}",
-                    d.path, loc_index
-                );
-                for (i2, other_loc) in locations.iter().enumerate() {
-                    if i2 != loc_index && !other_loc.as_any().is::<ZipLocation>() {
-                        println!("Creating dir `{}` in location #{}", d.path, i2);
-                        let _ = other_loc.create_dir(&d.path);
-                    }
-                }
-                sync_state.update_dir(d.path.clone(), d.clone());
+            sync_state.echoes.expect_absent(i, from);
+            match &hash {
+                Some(hash) => sync_state.echoes.expect_file(i, to, hash.clone()),
+                None => sync_state.echoes.expect_dir(i, to),
             }
+            println!("Renamed {} -> {} in location #{}", from, to, i);
         }
+    }
 
-        /////////////////////////Fisiere//////////////////////////
-        let files = loc.list_files_recursive()?;
+    // O arhiva read-only pastreaza vechea cale, nu vrem sa o readucem
+    flush_locations(locations)?;
 
-        let current_files: HashSet<String> = files.iter().map(|f| f.path.clone()).collect();
-        let known_files: HashSet<String> = sync_state.file_metadata.keys().cloned().collect();
+    sync_state.failures.succeeded(from, None);
+    let keep_tombstones = locations.iter().any(|loc| !loc.capabilities().writable);
+    sync_state.rename_path(from, to, keep_tombstones);
+    sync_state.commit()
+}
 
-        let removed_files: Vec<String> = known_files.difference(&current_files).cloned().collect();
+pub fn initial_sync_with_state(
+    locations: &[Box<dyn Location>],
+    sync_state: &mut SyncState,
+) -> Result<(), SyncError> {
+    // Comparam fiecare locatie cu ultima stare salvata (goala la prima rulare)
+    let mut snapshot = Snapshot::take(locations)?;
+    snapshot.negotiate_hashes(locations, sync_state);
+    let plan = reconcile::plan(&snapshot, sync_state);
+    run_pass(locations, &snapshot, &plan, sync_state)
+}
 
-        if loc.as_any().is::<ZipLocation>() {
-            println!(
-                "Location #{} is ZIP -> skip removing files not found in ZIP.",
-                loc_index
-            );
-        } else {
-            for removed_file in removed_files {
-                println!(
-                    "Detected file removal: {} in location #{}",
-                    removed_file, loc_index
-                );
-                for other_loc in locations {
-                    if !other_loc.as_any().is::<ZipLocation>() {
-                        match other_loc.delete_file(&removed_file) {
-                            Ok(_) => println!("File {} deleted from location", removed_file),
-                            Err(e) => println!("Failed to delete file {}: {:?}", removed_file, e),
-                        }
-                    }
-                }
-                sync_state.remove_file(&removed_file);
-            }
-        }
+pub fn sync_file(
+    locations: &[Box<dyn Location>],
+    filename: &str,
+    sync_state: &mut SyncState,
+) -> Result<(), SyncError> {
+    println!("Syncing file {}", filename);
 
-        //fisierele noi sau modificate
-        for file in &files {
-            if let Some(state_meta) = sync_state.file_metadata.get(&file.path) {
-                if state_meta.hash != file.hash {
-                    println!(
-                        "Detected modification for file: {} in location #{}",
-                        file.path, loc_index
-                    );
-                    sync_file(locations, &file.path, sync_state)?;
-                }
-            } else {
-                println!(
-                    "Detected new file: {} in location #{}",
-                    file.path, loc_index
-                );
-                sync_file(locations, &file.path, sync_state)?;
-            }
+    let mut snapshot = Snapshot::take_file(locations, filename)?;
+    snapshot.negotiate_hashes(locations, sync_state);
+    let paths = BTreeSet::from([filename.to_string()]);
+    let plan = SyncPlan {
+        dirs: vec![],
+        files: reconcile::plan_files(&snapshot, sync_state, &paths),
+        partial: snapshot.is_partial(),
+    };
+    run_pass(locations, &snapshot, &plan, sync_state)
+}
+
+pub fn poll_locations(
+    locations: &[Box<dyn Location>],
+    sync_state: &mut SyncState,
+) -> Result<(), SyncError> {
+    let mut snapshot = Snapshot::take(locations)?;
+    snapshot.negotiate_hashes(locations, sync_state);
+    let plan = reconcile::plan(&snapshot, sync_state);
+    run_pass(locations, &snapshot, &plan, sync_state)
+}
+
+// O trecere completa: erorile se aduna pe cale si pe locatie, iar la final le raportam
+fn run_pass(
+    locations: &[Box<dyn Location>],
+    snapshot: &Snapshot,
+    plan: &SyncPlan,
+    sync_state: &mut SyncState,
+) -> Result<(), SyncError> {
+    sync_state.failures.start_pass();
+    for (i, up) in snapshot.available.iter().enumerate() {
+        if *up {
+            sync_state.failures.succeeded("", Some(i));
         }
     }
+    for (i, e) in &snapshot.listing_errors {
+        sync_state.failures.record("", Some(*i), "list", e);
+    }
 
-    Ok(())
+    let result = execute_plan(locations, plan, sync_state);
+    sync_state.failures.print_report();
+    result
 }
@@ -0,0 +1,1603 @@
+use super::conflicts::{conflict_path, ConflictPolicy, ConflictRecord};
+use super::SyncState;
+use crate::delta;
+use crate::errors::SyncError;
+use crate::locations::{same_hash, DirMetadata, FileMetadata, HashAlgorithm, Location};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::{Read, Write};
+use std::time::{Duration, SystemTime};
+
+// Cat citim odata cand copiem un fisier intre locatii
+const COPY_CHUNK: usize = 64 * 1024;
+// Peste atatia octeti noi, un delta nu mai merita tinut in memorie
+const MAX_DELTA_LITERAL: usize = 16 * 1024 * 1024;
+
+// Ce s-a intamplat cu o cale intr-o locatie, fata de ultima stare sincronizata
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathStatus {
+    Unchanged,
+    Created,
+    Modified,
+    Deleted,
+    Absent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Classification {
+    Unchanged,
+    ChangedOnOneSide,
+    ChangedOnBoth,
+    DeletedOnOneSide,
+    DeletedAndChanged,
+    // disparut de la o cale si aparut, cu acelasi continut, la alta
+    Renamed,
+}
+
+#[derive(Debug, Clone)]
+pub enum SyncAction {
+    CreateDir {
+        path: String,
+        targets: Vec<usize>,
+        metadata: DirMetadata,
+    },
+    RemoveDir {
+        path: String,
+        targets: Vec<usize>,
+    },
+    CopyFile {
+        path: String,
+        source: usize,
+        targets: Vec<usize>,
+        metadata: FileMetadata,
+    },
+    // Varianta pierzatoare dintr-un conflict, salvata sub alt nume
+    SaveConflictCopy {
+        path: String,
+        source: usize,
+        copy_path: String,
+        targets: Vec<usize>,
+        metadata: FileMetadata,
+    },
+    RenameFile {
+        from: String,
+        to: String,
+        // locatia unde a avut loc mutarea; de aici copiem unde nu exista rename nativ
+        source: usize,
+        targets: Vec<usize>,
+        metadata: FileMetadata,
+        tombstone: Option<FileMetadata>,
+    },
+    DeleteFile {
+        path: String,
+        targets: Vec<usize>,
+        // copia ramasa intr-o locatie read-only, ca sa nu o readucem inapoi
+        tombstone: Option<FileMetadata>,
+    },
+    // Continutul e deja identic peste tot, actualizam doar starea
+    RecordFile {
+        metadata: FileMetadata,
+    },
+    ForgetFile {
+        path: String,
+    },
+    LogConflict {
+        record: ConflictRecord,
+    },
+}
+
+#[derive(Debug)]
+pub struct FilePlan {
+    pub path: String,
+    pub classification: Classification,
+    pub statuses: Vec<PathStatus>,
+    pub actions: Vec<SyncAction>,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    pub dirs: Vec<SyncAction>,
+    pub files: Vec<FilePlan>,
+    // lipseste cel putin o locatie: aplicam schimbarile, dar nu le salvam in stare
+    pub partial: bool,
+}
+
+// Ce am gasit in fiecare locatie la un moment dat
+pub struct Snapshot {
+    pub files: Vec<HashMap<String, FileMetadata>>,
+    pub dirs: Vec<HashMap<String, DirMetadata>>,
+    pub writable: Vec<bool>,
+    pub case_sensitive: Vec<bool>,
+    // false pentru locatiile care n-au putut fi listate in trecerea asta
+    pub available: Vec<bool>,
+    pub listing_errors: Vec<(usize, SyncError)>,
+    pub hosts: Vec<String>,
+}
+
+impl Snapshot {
+    pub fn take(locations: &[Box<dyn Location>]) -> Result<Self, SyncError> {
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        let mut available = Vec::new();
+        let mut listing_errors = Vec::new();
+        for (i, loc) in locations.iter().enumerate() {
+            let listing = loc
+                .list_files_recursive()
+                .and_then(|f| Ok((f, loc.list_dirs_recursive()?)));
+            let (loc_files, loc_dirs) = match listing {
+                Ok(listing) => listing,
+                // fara listing nu stim ce e in locatie: o sarim in trecerea asta
+                Err(e) => {
+                    println!("Location #{} could not be listed, skipping it: {}", i, e);
+                    files.push(HashMap::new());
+                    dirs.push(HashMap::new());
+                    available.push(false);
+                    listing_errors.push((i, e));
+                    continue;
+                }
+            };
+            files.push(loc_files.into_iter().map(|f| (f.path.clone(), f)).collect());
+            dirs.push(loc_dirs.into_iter().map(|d| (d.path.clone(), d)).collect());
+            available.push(true);
+        }
+        Ok(Snapshot {
+            files,
+            dirs,
+            writable: writable_flags(locations, &available),
+            case_sensitive: locations
+                .iter()
+                .map(|loc| loc.capabilities().case_sensitive)
+                .collect(),
+            available,
+            listing_errors,
+            hosts: locations.iter().map(|loc| loc.host()).collect(),
+        })
+    }
+
+    // Doar un fisier, fara directoare; fiecare locatie e intrebata doar de calea lui
+    pub fn take_file(locations: &[Box<dyn Location>], path: &str) -> Result<Self, SyncError> {
+        let mut files = Vec::new();
+        let mut available = Vec::new();
+        let mut listing_errors = Vec::new();
+        for (i, loc) in locations.iter().enumerate() {
+            let mut map = HashMap::new();
+            match loc.stat_file(path) {
+                Ok(meta) => {
+                    if let Some(meta) = meta {
+                        map.insert(meta.path.clone(), meta);
+                    }
+                    available.push(true);
+                }
+                Err(e) => {
+                    println!("Location #{} could not be listed, skipping it: {}", i, e);
+                    available.push(false);
+                    listing_errors.push((i, e));
+                }
+            }
+            files.push(map);
+        }
+        Ok(Snapshot {
+            dirs: vec![HashMap::new(); files.len()],
+            files,
+            writable: writable_flags(locations, &available),
+            case_sensitive: locations
+                .iter()
+                .map(|loc| loc.capabilities().case_sensitive)
+                .collect(),
+            available,
+            listing_errors,
+            hosts: locations.iter().map(|loc| loc.host()).collect(),
+        })
+    }
+
+    // Cat timp o locatie lipseste nu putem actualiza starea comuna: cand revine,
+    // copiile ei vechi trebuie comparate tot cu starea de dinainte
+    pub fn is_partial(&self) -> bool {
+        self.available.contains(&false)
+    }
+
+    // Locatiile pot avea algoritmi diferiti (`?hash=`), iar hash-uri din algoritmi diferiti
+    // nu spun nimic despre continut. Pentru fiecare cale ale carei copii nu se pot compara
+    // alegem un algoritm comun si recalculam doar copiile care nu il au deja.
+    pub fn negotiate_hashes(&mut self, locations: &[Box<dyn Location>], sync_state: &SyncState) {
+        let native: Vec<Vec<HashAlgorithm>> = locations
+            .iter()
+            .map(|loc| loc.capabilities().native_hashes)
+            .collect();
+        let paths: BTreeSet<String> = self.files.iter().flat_map(|m| m.keys().cloned()).collect();
+
+        let mut rehashed = 0;
+        for path in paths {
+            let copies: Vec<(usize, HashAlgorithm)> = (0..self.files.len())
+                .filter_map(|i| {
+                    let hash = self.files[i].get(&path)?.hash.as_deref()?;
+                    Some((i, HashAlgorithm::of(hash)?))
+                })
+                .collect();
+            if copies.iter().all(|(_, a)| *a == copies[0].1) {
+                continue;
+            }
+            // dimensiuni diferite: continutul difera oricum, nu citim nimic
+            let mut sizes = copies.iter().map(|&(i, _)| self.files[i][&path].size);
+            let first = sizes.next();
+            if sizes.any(|size| Some(size) != first) {
+                continue;
+            }
+            let baseline = sync_state
+                .file_metadata
+                .get(&path)
+                .and_then(|b| b.hash.as_deref())
+                .and_then(HashAlgorithm::of);
+            let algorithm = common_algorithm(&copies, &native, baseline);
+
+            for &(i, _) in copies.iter().filter(|(_, a)| *a != algorithm) {
+                match locations[i].content_hash(&path, algorithm) {
+                    Ok(hash) => {
+                        self.files[i].get_mut(&path).unwrap().hash = Some(hash);
+                        rehashed += 1;
+                    }
+                    // ramane comparata dupa dimensiune si timp
+                    Err(e) => println!(
+                        "Could not hash {} in location #{} with {}: {}",
+                        path,
+                        i,
+                        algorithm.name(),
+                        e
+                    ),
+                }
+            }
+        }
+        if rehashed > 0 {
+            println!(
+                "Rehashed {} file(s) with a common algorithm to compare them across locations",
+                rehashed
+            );
+        }
+    }
+}
+
+// Algoritmul cel mai ieftin pentru copiile unei cai: cel pe care il au deja, apoi unul
+// dat de locatie fara sa citeasca fisierul (ex. CRC32 intr-un zip si XCRC pe FTP). La
+// egalitate il pastram pe cel din stare, ca sa nu schimbam hash-ul salvat la fiecare trecere.
+fn common_algorithm(
+    copies: &[(usize, HashAlgorithm)],
+    native: &[Vec<HashAlgorithm>],
+    baseline: Option<HashAlgorithm>,
+) -> HashAlgorithm {
+    let cost = |algorithm: HashAlgorithm| -> usize {
+        let copies_cost: usize = copies
+            .iter()
+            .map(|&(i, current)| {
+                if current == algorithm {
+                    0
+                } else if native[i].contains(&algorithm) {
+                    1
+                } else {
+                    // citim tot fisierul (de pe un server: il descarcam)
+                    4
+                }
+            })
+            .sum();
+        copies_cost + usize::from(baseline != Some(algorithm))
+    };
+    // la egalitate castiga un algoritm dat de locatie, pe care il avem gratis si data viitoare
+    let mut candidates: Vec<HashAlgorithm> = copies
+        .iter()
+        .flat_map(|&(i, _)| native[i].iter().copied())
+        .collect();
+    candidates.extend(copies.iter().map(|&(_, a)| a));
+    candidates
+        .into_iter()
+        .min_by_key(|&a| cost(a))
+        .unwrap_or_default()
+}
+
+// O locatie indisponibila e tratata ca read-only: nu o atingem si nu stergem nimic din cauza ei
+fn writable_flags(locations: &[Box<dyn Location>], available: &[bool]) -> Vec<bool> {
+    locations
+        .iter()
+        .zip(available)
+        .map(|(loc, &up)| up && loc.capabilities().writable)
+        .collect()
+}
+
+// Fara hash (sau cu hash-uri din algoritmi diferiti) comparam dimensiunea (0 = necunoscuta)
+// si timpii (toti in UTC); `tolerance` acopera rezolutia diferita a locatiilor (2s in
+// zip/FAT, minute in LIST) si un ceas usor decalat
+pub(super) fn same_content(a: &FileMetadata, b: &FileMetadata, tolerance: Duration) -> bool {
+    let hashes = a.hash.as_deref().zip(b.hash.as_deref());
+    match hashes.and_then(|(x, y)| same_hash(x, y)) {
+        Some(same) => same,
+        _ if a.size != 0 && b.size != 0 && a.size != b.size => false,
+        _ => {
+            let diff = a
+                .modified
+                .duration_since(b.modified)
+                .or_else(|_| b.modified.duration_since(a.modified))
+                .unwrap_or_default();
+            diff <= tolerance
+        }
+    }
+}
+
+fn file_status(
+    current: Option<&FileMetadata>,
+    baseline: Option<&FileMetadata>,
+    tombstone: Option<&FileMetadata>,
+    writable: bool,
+    tolerance: Duration,
+) -> PathStatus {
+    let same = |a, b| same_content(a, b, tolerance);
+    match (current, baseline) {
+        (Some(cur), Some(base)) if same(cur, base) => PathStatus::Unchanged,
+        (Some(_), Some(_)) => PathStatus::Modified,
+        // Ce a ramas intr-o arhiva dupa o stergere nu mai e un fisier nou
+        (Some(cur), None) if !writable && tombstone.is_some_and(|t| same(cur, t)) => {
+            PathStatus::Absent
+        }
+        (Some(_), None) => PathStatus::Created,
+        // Dintr-o locatie read-only nu se poate sterge nimic
+        (None, Some(_)) if writable => PathStatus::Deleted,
+        (None, _) => PathStatus::Absent,
+    }
+}
+
+// Starea are hash-ul altui algoritm (s-a schimbat `--hash`), iar copiile neschimbate au
+// toate acelasi hash nou: il salvam, ca data viitoare sa comparam iar dupa continut
+fn rehashed<'a>(
+    baseline: Option<&FileMetadata>,
+    current: &[Option<&'a FileMetadata>],
+) -> Option<&'a FileMetadata> {
+    let algorithm = |c: &FileMetadata| c.hash.as_deref().and_then(HashAlgorithm::of);
+    let old = algorithm(baseline?)?;
+    let fresh = current
+        .iter()
+        .flatten()
+        .find(|c| algorithm(c).is_some_and(|a| a != old))?;
+    let new_hash = fresh.hash.as_deref()?;
+    current
+        .iter()
+        .flatten()
+        .filter_map(|c| c.hash.as_deref())
+        .all(|h| same_hash(h, new_hash) != Some(false))
+        .then_some(*fresh)
+}
+
+pub fn plan_files(
+    snapshot: &Snapshot,
+    sync_state: &SyncState,
+    paths: &BTreeSet<String>,
+) -> Vec<FilePlan> {
+    let mut plans = Vec::new();
+    let tolerance = sync_state.mtime_tolerance;
+    let same = |a, b| same_content(a, b, tolerance);
+
+    for path in paths {
+        let baseline = sync_state.file_metadata.get(path);
+        let tombstone = sync_state.tombstones.get(path);
+        let current: Vec<Option<&FileMetadata>> =
+            snapshot.files.iter().map(|map| map.get(path)).collect();
+
+        // ce am scris chiar noi in ultimul timp nu e o schimbare a locatiei
+        let statuses: Vec<PathStatus> = current
+            .iter()
+            .zip(&snapshot.writable)
+            .enumerate()
+            .map(|(i, (cur, writable))| {
+                match file_status(*cur, baseline, tombstone, *writable, tolerance) {
+                    PathStatus::Created | PathStatus::Modified
+                        if sync_state.echoes.is_echo_file(i, path, *cur) =>
+                    {
+                        PathStatus::Unchanged
+                    }
+                    status => status,
+                }
+            })
+            .collect();
+
+        let changed: Vec<usize> = (0..statuses.len())
+            .filter(|&i| matches!(statuses[i], PathStatus::Created | PathStatus::Modified))
+            .collect();
+        let deleted = statuses.contains(&PathStatus::Deleted);
+
+        let mut actions = Vec::new();
+        let classification = if changed.is_empty() && !deleted {
+            if baseline.is_some() && current.iter().all(|c| c.is_none()) {
+                actions.push(SyncAction::ForgetFile { path: path.clone() });
+            } else if let Some(fresh) = rehashed(baseline, &current) {
+                actions.push(SyncAction::RecordFile {
+                    metadata: fresh.clone(),
+                });
+            }
+            Classification::Unchanged
+        } else if changed.is_empty() {
+            // Sters intr-o parte, neatins in rest -> stergem peste tot
+            let targets: Vec<usize> = (0..statuses.len())
+                .filter(|&i| snapshot.writable[i] && current[i].is_some())
+                .collect();
+            let tombstone = (0..statuses.len())
+                .find(|&i| !snapshot.writable[i] && current[i].is_some())
+                .and_then(|i| current[i].cloned());
+            actions.push(SyncAction::DeleteFile {
+                path: path.clone(),
+                targets,
+                tombstone,
+            });
+            Classification::DeletedOnOneSide
+        } else {
+            let first = current[changed[0]].unwrap();
+            let all_same = changed.iter().all(|&i| same(current[i].unwrap(), first));
+
+            // si cand o alta locatie a sters fisierul: modificarile diferite tot se bat cap in cap
+            let conflict = !all_same;
+            let policy = sync_state.conflict_policies.for_locations(&changed);
+
+            // Daca s-a schimbat in mai multe parti, castiga cel mai nou (sau locatia preferata)
+            let newest = *changed
+                .iter()
+                .max_by_key(|&&i| current[i].unwrap().modified)
+                .unwrap();
+            let source = match policy {
+                ConflictPolicy::PreferLocation(n) if conflict && changed.contains(n) => *n,
+                _ => newest,
+            };
+            let source_meta = current[source].unwrap();
+
+            if conflict {
+                let versions: Vec<(usize, &FileMetadata)> =
+                    changed.iter().map(|&i| (i, current[i].unwrap())).collect();
+                let mut record = ConflictRecord::new(path, policy, &versions);
+
+                if *policy == ConflictPolicy::SkipAndReport {
+                    println!(
+                        "Conflict on {}: modified in locations {:?}, skipping",
+                        path, changed
+                    );
+                    actions.push(SyncAction::LogConflict { record });
+                    plans.push(FilePlan {
+                        path: path.clone(),
+                        classification: Classification::ChangedOnBoth,
+                        statuses,
+                        actions,
+                    });
+                    continue;
+                }
+
+                if *policy == ConflictPolicy::KeepBoth {
+                    let mut saved: Vec<&FileMetadata> = vec![source_meta];
+                    for &i in &changed {
+                        let meta = current[i].unwrap();
+                        if saved.iter().any(|m| same(m, meta)) {
+                            continue;
+                        }
+                        saved.push(meta);
+
+                        let copy_path = conflict_path(path, &snapshot.hosts[i], meta.modified);
+                        let mut copy_meta = meta.clone();
+                        copy_meta.path = copy_path.clone();
+                        actions.push(SyncAction::SaveConflictCopy {
+                            path: path.clone(),
+                            source: i,
+                            copy_path,
+                            targets: (0..statuses.len())
+                                .filter(|&t| snapshot.writable[t])
+                                .collect(),
+                            metadata: copy_meta,
+                        });
+                    }
+                }
+
+                record.resolution = Some(format!(
+                    "{}: kept version from location #{}",
+                    policy.name(),
+                    source
+                ));
+                actions.push(SyncAction::LogConflict { record });
+            }
+
+            // O modificare castiga mereu in fata unei stergeri, ca sa nu pierdem date
+            let targets: Vec<usize> = (0..statuses.len())
+                .filter(|&i| i != source && snapshot.writable[i])
+                .filter(|&i| current[i].is_none_or(|c| !same(c, source_meta)))
+                .collect();
+
+            if targets.is_empty() {
+                actions.push(SyncAction::RecordFile {
+                    metadata: source_meta.clone(),
+                });
+            } else {
+                actions.push(SyncAction::CopyFile {
+                    path: path.clone(),
+                    source,
+                    targets,
+                    metadata: source_meta.clone(),
+                });
+            }
+
+            match (deleted, all_same) {
+                (true, _) => Classification::DeletedAndChanged,
+                (false, true) => Classification::ChangedOnOneSide,
+                (false, false) => Classification::ChangedOnBoth,
+            }
+        };
+
+        plans.push(FilePlan {
+            path: path.clone(),
+            classification,
+            statuses,
+            actions,
+        });
+    }
+
+    plans
+}
+
+pub fn plan_dirs(
+    snapshot: &Snapshot,
+    sync_state: &SyncState,
+    file_plans: &[FilePlan],
+) -> Vec<SyncAction> {
+    let mut paths: BTreeSet<String> = sync_state.dir_metadata.keys().cloned().collect();
+    for map in &snapshot.dirs {
+        paths.extend(map.keys().cloned());
+    }
+
+    let mut actions = Vec::new();
+    for path in paths {
+        if path.is_empty() || path == "." {
+            continue;
+        }
+
+        let known = sync_state.dir_metadata.contains_key(&path);
+        let present: Vec<bool> = snapshot
+            .dirs
+            .iter()
+            .map(|m| m.contains_key(&path))
+            .collect();
+        let deleted = known && (0..present.len()).any(|i| snapshot.writable[i] && !present[i]);
+
+        // Nu stergem un director in care a aparut sau s-a modificat ceva
+        let prefix = format!("{}/", path);
+        let changed_inside = file_plans.iter().any(|p| {
+            p.path.starts_with(&prefix)
+                && matches!(
+                    p.classification,
+                    Classification::ChangedOnOneSide
+                        | Classification::ChangedOnBoth
+                        | Classification::DeletedAndChanged
+                        | Classification::Renamed
+                )
+        });
+
+        if deleted && !changed_inside {
+            actions.push(SyncAction::RemoveDir {
+                targets: (0..present.len())
+                    .filter(|&i| snapshot.writable[i] && present[i])
+                    .collect(),
+                path,
+            });
+            continue;
+        }
+
+        let newest = snapshot
+            .dirs
+            .iter()
+            .filter_map(|m| m.get(&path))
+            .max_by_key(|d| d.modified)
+            .cloned();
+        let metadata = match newest {
+            Some(meta) => meta,
+            // Directorul a disparut peste tot
+            None => {
+                actions.push(SyncAction::RemoveDir {
+                    path,
+                    targets: vec![],
+                });
+                continue;
+            }
+        };
+
+        let targets: Vec<usize> = (0..present.len())
+            .filter(|&i| snapshot.writable[i] && !present[i])
+            .collect();
+        if !targets.is_empty() || !known {
+            actions.push(SyncAction::CreateDir {
+                path,
+                targets,
+                metadata,
+            });
+        }
+    }
+
+    actions
+}
+
+// O mutare intr-o locatie fara evenimente apare ca o stergere plus un fisier nou.
+// Le potrivim dupa hash si le inlocuim cu o singura redenumire.
+fn detect_renames(plans: &mut Vec<FilePlan>, snapshot: &Snapshot, sync_state: &SyncState) {
+    let indices_with = |plan: &FilePlan, status: PathStatus| -> Vec<usize> {
+        (0..plan.statuses.len())
+            .filter(|&i| plan.statuses[i] == status)
+            .collect()
+    };
+
+    let mut renames: Vec<(usize, usize)> = Vec::new();
+    let mut used: HashSet<usize> = HashSet::new();
+
+    for (di, deleted) in plans.iter().enumerate() {
+        if deleted.classification != Classification::DeletedOnOneSide {
+            continue;
+        }
+        let base = match sync_state.file_metadata.get(&deleted.path) {
+            Some(base) if base.hash.is_some() => base,
+            _ => continue,
+        };
+        let gone = indices_with(deleted, PathStatus::Deleted);
+
+        let matching = plans.iter().enumerate().find(|(ci, created)| {
+            !used.contains(ci)
+                && created.classification == Classification::ChangedOnOneSide
+                && !sync_state.file_metadata.contains_key(&created.path)
+                && indices_with(created, PathStatus::Created) == gone
+                && gone.iter().all(|&i| {
+                    snapshot.files[i]
+                        .get(&created.path)
+                        .is_some_and(|m| same_content(m, base, sync_state.mtime_tolerance))
+                })
+        });
+
+        if let Some((ci, _)) = matching {
+            used.insert(ci);
+            renames.push((di, ci));
+        }
+    }
+
+    let mut removed: HashSet<usize> = HashSet::new();
+    for (di, ci) in renames {
+        let from = plans[di].path.clone();
+        let to = plans[ci].path.clone();
+        let source = indices_with(&plans[ci], PathStatus::Created)[0];
+        let metadata = snapshot.files[source][&to].clone();
+
+        // mutam doar acolo unde vechiul fisier inca exista
+        let targets: Vec<usize> = (0..snapshot.files.len())
+            .filter(|&i| snapshot.writable[i] && snapshot.files[i].contains_key(&from))
+            .collect();
+        let tombstone = (0..snapshot.files.len())
+            .find(|&i| !snapshot.writable[i] && snapshot.files[i].contains_key(&from))
+            .map(|i| snapshot.files[i][&from].clone());
+
+        plans[ci].classification = Classification::Renamed;
+        plans[ci].actions = vec![SyncAction::RenameFile {
+            from,
+            to,
+            source,
+            targets,
+            metadata,
+            tombstone,
+        }];
+        removed.insert(di);
+    }
+
+    let mut index = 0;
+    plans.retain(|_| {
+        index += 1;
+        !removed.contains(&(index - 1))
+    });
+}
+
+// Intr-o locatie care nu tine cont de majuscule `Doc.txt` si `doc.txt` sunt acelasi fisier.
+// O cale care s-ar suprapune acolo peste alta nu o sincronizam deloc (nici in stare),
+// altfel ar parea stearsa din locatia respectiva; o raportam la fiecare trecere.
+fn skip_case_collisions(plans: &mut [FilePlan], snapshot: &Snapshot) {
+    let insensitive: Vec<usize> = (0..snapshot.files.len())
+        .filter(|&i| snapshot.writable[i] && !snapshot.case_sensitive[i])
+        .collect();
+    if insensitive.is_empty() {
+        return;
+    }
+
+    // ce exista deja in locatie are prioritate, apoi prima cale din plan
+    let mut taken: Vec<HashMap<String, String>> = vec![HashMap::new(); snapshot.files.len()];
+    for &i in &insensitive {
+        for path in snapshot.files[i].keys() {
+            taken[i].insert(path.to_lowercase(), path.clone());
+        }
+    }
+
+    for plan in plans.iter_mut() {
+        let mut collisions = Vec::new();
+        for action in &plan.actions {
+            let (path, renamed_from, targets) = match action {
+                SyncAction::CopyFile { path, targets, .. } => (path, None, targets),
+                SyncAction::SaveConflictCopy {
+                    copy_path, targets, ..
+                } => (copy_path, None, targets),
+                SyncAction::RenameFile {
+                    from, to, targets, ..
+                } => (to, Some(from), targets),
+                _ => continue,
+            };
+            for &i in targets.iter().filter(|i| insensitive.contains(i)) {
+                let key = path.to_lowercase();
+                match taken[i].get(&key) {
+                    // o redenumire care schimba doar majusculele e in regula
+                    Some(existing) if existing != path && Some(existing) != renamed_from => {
+                        collisions.push((i, existing.clone()));
+                    }
+                    _ => {
+                        taken[i].insert(key, path.clone());
+                    }
+                }
+            }
+        }
+        for (i, existing) in &collisions {
+            println!(
+                "Skipping {}: it would overwrite {} in location #{} (names differ only in case)",
+                plan.path, existing, i
+            );
+        }
+        if !collisions.is_empty() {
+            plan.actions.clear();
+        }
+    }
+}
+
+// O cale care e fisier intr-o locatie si director in alta nu poate fi copiata in niciun
+// sens fara sa stearga ceva; o lasam (cu tot ce e sub ea) neatinsa si o raportam
+fn skip_type_clashes(files: &mut [FilePlan], dirs: &mut Vec<SyncAction>, snapshot: &Snapshot) {
+    let clashes: Vec<&String> = snapshot
+        .files
+        .iter()
+        .flat_map(|map| map.keys())
+        .filter(|path| snapshot.dirs.iter().any(|d| d.contains_key(*path)))
+        .collect();
+    if clashes.is_empty() {
+        return;
+    }
+    let clashing = |path: &str| {
+        clashes.iter().any(|c| {
+            path == c.as_str()
+                || path
+                    .strip_prefix(c.as_str())
+                    .is_some_and(|r| r.starts_with('/'))
+        })
+    };
+
+    for path in &clashes {
+        let file_in = (0..snapshot.files.len()).filter(|&i| snapshot.files[i].contains_key(*path));
+        let dir_in = (0..snapshot.dirs.len()).filter(|&i| snapshot.dirs[i].contains_key(*path));
+        println!(
+            "Skipping {}: it is a file in location(s) {:?} and a directory in {:?}",
+            path,
+            file_in.collect::<Vec<_>>(),
+            dir_in.collect::<Vec<_>>()
+        );
+    }
+    for plan in files.iter_mut().filter(|p| clashing(&p.path)) {
+        plan.actions.clear();
+    }
+    dirs.retain(|action| match action {
+        SyncAction::CreateDir { path, .. } | SyncAction::RemoveDir { path, .. } => !clashing(path),
+        _ => true,
+    });
+}
+
+pub fn plan(snapshot: &Snapshot, sync_state: &SyncState) -> SyncPlan {
+    let mut paths: BTreeSet<String> = sync_state.file_metadata.keys().cloned().collect();
+    for map in &snapshot.files {
+        paths.extend(map.keys().cloned());
+    }
+
+    let mut files = plan_files(snapshot, sync_state, &paths);
+    detect_renames(&mut files, snapshot, sync_state);
+    skip_case_collisions(&mut files, snapshot);
+    let mut dirs = plan_dirs(snapshot, sync_state, &files);
+    skip_type_clashes(&mut files, &mut dirs, snapshot);
+    SyncPlan {
+        dirs,
+        files,
+        partial: snapshot.is_partial(),
+    }
+}
+
+pub fn execute_plan(
+    locations: &[Box<dyn Location>],
+    plan: &SyncPlan,
+    sync_state: &mut SyncState,
+) -> Result<(), SyncError> {
+    if plan.partial {
+        println!("Some locations are unavailable, the sync state is left unchanged for now");
+    }
+
+    // Intai cream directoarele (parintii inaintea copiilor)
+    for action in &plan.dirs {
+        if let SyncAction::CreateDir { path, .. } = action {
+            run_actions(
+                locations,
+                path,
+                std::slice::from_ref(action),
+                plan.partial,
+                sync_state,
+            );
+        }
+    }
+
+    for file_plan in &plan.files {
+        if file_plan.classification != Classification::Unchanged {
+            println!(
+                "File {}: {:?} {:?}",
+                file_plan.path, file_plan.classification, file_plan.statuses
+            );
+        }
+        run_actions(
+            locations,
+            &file_plan.path,
+            &file_plan.actions,
+            plan.partial,
+            sync_state,
+        );
+    }
+
+    // La final stergem directoarele (copiii inaintea parintilor)
+    for action in plan.dirs.iter().rev() {
+        if let SyncAction::RemoveDir { path, .. } = action {
+            run_actions(
+                locations,
+                path,
+                std::slice::from_ref(action),
+                plan.partial,
+                sync_state,
+            );
+        }
+    }
+
+    // o locatie care nu si-a putut scrie schimbarile nu opreste trecerea; o reincercam
+    // la urmatoarea, iar pana atunci nu o listam (vezi ZipLocation)
+    for (i, loc) in locations.iter().enumerate() {
+        if let Err(e) = loc.flush() {
+            eprintln!("Location #{} could not save its changes: {}", i, e);
+            sync_state.failures.record("", Some(i), "flush", &e);
+        }
+    }
+    sync_state.commit()
+}
+
+// Actiunile unei singure cai. O eroare opreste doar calea respectiva (restul trecerii
+// continua), iar starea ei ramane cea veche, ca sa fie replanificata data viitoare.
+pub fn run_actions(
+    locations: &[Box<dyn Location>],
+    path: &str,
+    actions: &[SyncAction],
+    partial: bool,
+    sync_state: &mut SyncState,
+) {
+    if actions.is_empty() {
+        sync_state.failures.succeeded(path, None);
+        return;
+    }
+    if sync_state.failures.should_skip(path) {
+        println!("Skipping {} until its next retry", path);
+        return;
+    }
+
+    for action in actions {
+        if let Err((location, operation, e)) = execute_action(locations, action) {
+            println!(
+                "Failed to {} {} in location #{}: {}",
+                operation, path, location, e
+            );
+            sync_state
+                .failures
+                .record(path, Some(location), operation, &e);
+            return;
+        }
+        sync_state.echoes.expect_action(action);
+        // conflictele le raportam oricum; restul starii asteapta toate locatiile
+        if !partial || matches!(action, SyncAction::LogConflict { .. }) {
+            record_action(action, sync_state);
+        }
+    }
+    sync_state.failures.succeeded(path, None);
+}
+
+// Locatia si operatia care au esuat
+type ActionError = (usize, &'static str, SyncError);
+
+// Operatiile din locatii; starea se actualizeaza separat, in `record_action`
+fn execute_action(locations: &[Box<dyn Location>], action: &SyncAction) -> Result<(), ActionError> {
+    match action {
+        SyncAction::CreateDir { path, targets, .. } => {
+            for &i in targets {
+                println!("Creating dir `{}` in location #{}", path, i);
+                locations[i]
+                    .create_dir(path)
+                    .map_err(|e| (i, "create dir", e))?;
+            }
+        }
+        SyncAction::RemoveDir { path, targets } => {
+            for &i in targets {
+                println!("Removing dir `{}` in location #{}", path, i);
+                locations[i]
+                    .remove_dir(path)
+                    .map_err(|e| (i, "remove dir", e))?;
+            }
+        }
+        SyncAction::CopyFile {
+            path,
+            source,
+            targets,
+            metadata,
+        } => {
+            for &i in targets {
+                copy_file(
+                    locations[*source].as_ref(),
+                    locations[i].as_ref(),
+                    path,
+                    path,
+                    Some(metadata.modified),
+                )
+                .map_err(|e| (i, "copy", e))?;
+                println!("File {} copied from location #{} to #{}", path, source, i);
+            }
+        }
+        SyncAction::SaveConflictCopy {
+            path,
+            source,
+            copy_path,
+            targets,
+            metadata,
+        } => {
+            for &i in targets {
+                copy_file(
+                    locations[*source].as_ref(),
+                    locations[i].as_ref(),
+                    path,
+                    copy_path,
+                    Some(metadata.modified),
+                )
+                .map_err(|e| (i, "save conflict copy of", e))?;
+                println!(
+                    "Conflict copy of {} from location #{} saved as {} in #{}",
+                    path, source, copy_path, i
+                );
+            }
+        }
+        SyncAction::RenameFile {
+            from,
+            to,
+            source,
+            targets,
+            metadata,
+            ..
+        } => {
+            for &i in targets {
+                move_file(
+                    locations,
+                    Some(*source),
+                    i,
+                    from,
+                    to,
+                    Some(metadata.modified),
+                )
+                .map_err(|e| (i, "rename", e))?;
+                println!("File {} renamed to {} in location #{}", from, to, i);
+            }
+        }
+        SyncAction::DeleteFile { path, targets, .. } => {
+            for &i in targets {
+                locations[i]
+                    .delete_file(path)
+                    .map_err(|e| (i, "delete", e))?;
+                println!("File {} deleted from location #{}", path, i);
+            }
+        }
+        SyncAction::RecordFile { .. }
+        | SyncAction::ForgetFile { .. }
+        | SyncAction::LogConflict { .. } => {}
+    }
+    Ok(())
+}
+
+// Ce ramane in starea sincronizata dupa o actiune reusita
+fn record_action(action: &SyncAction, sync_state: &mut SyncState) {
+    match action {
+        SyncAction::CreateDir { path, metadata, .. } => {
+            sync_state.update_dir(path.clone(), metadata.clone());
+        }
+        SyncAction::RemoveDir { path, .. } => {
+            sync_state.remove_dir(path);
+        }
+        SyncAction::CopyFile { path, metadata, .. } => {
+            sync_state.update_file(path.clone(), metadata.clone());
+        }
+        SyncAction::SaveConflictCopy {
+            copy_path,
+            metadata,
+            ..
+        } => {
+            sync_state.update_file(copy_path.clone(), metadata.clone());
+        }
+        SyncAction::RenameFile {
+            from,
+            to,
+            metadata,
+            tombstone,
+            ..
+        } => {
+            sync_state.remove_file(from);
+            sync_state.update_file(to.clone(), metadata.clone());
+            if let Some(meta) = tombstone {
+                sync_state.add_tombstone(meta.clone());
+            }
+        }
+        SyncAction::DeleteFile {
+            path, tombstone, ..
+        } => {
+            sync_state.remove_file(path);
+            if let Some(meta) = tombstone {
+                sync_state.add_tombstone(meta.clone());
+            }
+        }
+        SyncAction::RecordFile { metadata } => {
+            sync_state.update_file(metadata.path.clone(), metadata.clone());
+        }
+        SyncAction::ForgetFile { path } => {
+            sync_state.remove_file(path);
+        }
+        SyncAction::LogConflict { record } => {
+            sync_state.record_conflict(record.clone());
+        }
+    }
+}
+
+// Fara rename nativ, locatia ar trebui sa-si citeasca fisierul si sa-l scrie la loc;
+// daca stim unde exista deja noua cale, o copiem de acolo si stergem vechea cale
+pub fn move_file(
+    locations: &[Box<dyn Location>],
+    source: Option<usize>,
+    target: usize,
+    from: &str,
+    to: &str,
+    modified: Option<SystemTime>,
+) -> Result<(), SyncError> {
+    let location = locations[target].as_ref();
+    match source {
+        Some(s) if s != target && !location.capabilities().rename => {
+            copy_file(locations[s].as_ref(), location, to, to, modified)?;
+            location.delete_file(from)
+        }
+        _ => location.rename(from, to),
+    }
+}
+
+// Copia primeste timpul modificarii sursei (`modified`), unde locatia il poate seta;
+// altfel ar parea mai noua decat originalul la urmatoarea comparatie
+pub fn copy_file(
+    source: &dyn Location,
+    target: &dyn Location,
+    from: &str,
+    to: &str,
+    modified: Option<SystemTime>,
+) -> Result<(), SyncError> {
+    transfer(source, target, from, to)?;
+    if let Some(modified) = modified.filter(|_| target.capabilities().set_mtime) {
+        if let Err(e) = target.set_modified(to, modified) {
+            println!("Could not keep the modification time of {}: {}", to, e);
+        }
+    }
+    Ok(())
+}
+
+// Daca destinatia are deja o copie, trimitem doar blocurile schimbate;
+// altfel copiem in flux, cate o bucata, fara sa tinem tot fisierul in memorie
+fn transfer(
+    source: &dyn Location,
+    target: &dyn Location,
+    from: &str,
+    to: &str,
+) -> Result<(), SyncError> {
+    if from == to {
+        if let Some(signature) = target.block_signature(to)? {
+            let reader = source.open_read(from)?;
+            match delta::compute_delta(&signature, reader, MAX_DELTA_LITERAL)? {
+                Some(delta) => {
+                    if target.apply_delta(to, &delta)? {
+                        println!(
+                            "Delta transfer for {}: {} blocks reused, {} literal bytes",
+                            to,
+                            delta.copied_blocks(),
+                            delta.literal_bytes()
+                        );
+                        return Ok(());
+                    }
+                    println!(
+                        "Delta transfer for {} failed verification, sending whole file",
+                        to
+                    );
+                }
+                None => println!("Too many changes in {}, sending whole file", to),
+            }
+        }
+    }
+
+    let mut reader = source.open_read(from)?;
+    let mut writer = target.open_write(to)?;
+    let mut buf = vec![0u8; COPY_CHUNK];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buf[..n])?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_logic::conflicts::ConflictPolicies;
+    use std::time::UNIX_EPOCH;
+
+    const TOLERANCE: Duration = Duration::from_secs(2);
+
+    fn file(path: &str, hash: Option<&str>, secs: u64, size: u64) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            modified: UNIX_EPOCH + Duration::from_secs(secs),
+            size,
+            hash: hash.map(str::to_string),
+        }
+    }
+
+    // Versiunea `content` a unui fisier, cu timpul `secs`
+    fn version(path: &str, content: &str, secs: u64) -> FileMetadata {
+        file(path, Some(&format!("sha256:{}", content)), secs, 10)
+    }
+
+    // Doua sau mai multe locatii, toate disponibile si scriibile
+    fn snapshot(files: Vec<Vec<FileMetadata>>, dirs: Vec<Vec<&str>>) -> Snapshot {
+        let n = files.len();
+        Snapshot {
+            files: files
+                .into_iter()
+                .map(|list| list.into_iter().map(|f| (f.path.clone(), f)).collect())
+                .collect(),
+            dirs: dirs
+                .into_iter()
+                .map(|list| {
+                    list.into_iter()
+                        .map(|d| {
+                            let meta = DirMetadata {
+                                path: d.to_string(),
+                                modified: UNIX_EPOCH,
+                            };
+                            (d.to_string(), meta)
+                        })
+                        .collect()
+                })
+                .collect(),
+            writable: vec![true; n],
+            case_sensitive: vec![true; n],
+            available: vec![true; n],
+            listing_errors: vec![],
+            hosts: (0..n).map(|i| format!("host{}", i)).collect(),
+        }
+    }
+
+    fn state(files: Vec<FileMetadata>, dirs: Vec<&str>) -> SyncState {
+        let mut sync_state = SyncState::new();
+        for f in files {
+            sync_state.file_metadata.insert(f.path.clone(), f);
+        }
+        for d in dirs {
+            let meta = DirMetadata {
+                path: d.to_string(),
+                modified: UNIX_EPOCH,
+            };
+            sync_state.dir_metadata.insert(d.to_string(), meta);
+        }
+        sync_state
+    }
+
+    // Actiunile, pe scurt, ca sa le putem compara in tabele
+    fn describe(action: &SyncAction) -> String {
+        match action {
+            SyncAction::CreateDir { path, targets, .. } => format!("mkdir {} {:?}", path, targets),
+            SyncAction::RemoveDir { path, targets } => format!("rmdir {} {:?}", path, targets),
+            SyncAction::CopyFile {
+                path,
+                source,
+                targets,
+                ..
+            } => format!("copy {} #{} -> {:?}", path, source, targets),
+            SyncAction::SaveConflictCopy {
+                source, targets, ..
+            } => format!("keep #{} -> {:?}", source, targets),
+            SyncAction::RenameFile {
+                from,
+                to,
+                source,
+                targets,
+                ..
+            } => format!("rename {} -> {} #{} -> {:?}", from, to, source, targets),
+            SyncAction::DeleteFile { path, targets, .. } => {
+                format!("delete {} {:?}", path, targets)
+            }
+            SyncAction::RecordFile { metadata } => format!("record {}", metadata.path),
+            SyncAction::ForgetFile { path } => format!("forget {}", path),
+            SyncAction::LogConflict { record } => format!("conflict {}", record.path),
+        }
+    }
+
+    #[test]
+    fn same_content_cases() {
+        let cases = [
+            // (a, b, acelasi continut?)
+            (
+                file("f", Some("sha256:aa"), 0, 5),
+                file("f", Some("sha256:aa"), 100, 5),
+                true,
+            ),
+            (
+                file("f", Some("sha256:aa"), 0, 5),
+                file("f", Some("sha256:bb"), 0, 5),
+                false,
+            ),
+            // algoritmi diferiti: decid dimensiunea si timpul
+            (
+                file("f", Some("sha256:aa"), 10, 5),
+                file("f", Some("blake3:bb"), 11, 5),
+                true,
+            ),
+            (
+                file("f", Some("sha256:aa"), 10, 5),
+                file("f", Some("blake3:bb"), 10, 6),
+                false,
+            ),
+            (
+                file("f", Some("sha256:aa"), 10, 5),
+                file("f", Some("blake3:bb"), 20, 5),
+                false,
+            ),
+            // fara hash si fara dimensiune (0 = necunoscuta) ramane doar timpul
+            (file("f", None, 10, 0), file("f", None, 12, 7), true),
+            (file("f", None, 10, 0), file("f", None, 13, 7), false),
+            (
+                file("f", None, 10, 3),
+                file("f", Some("sha256:aa"), 10, 4),
+                false,
+            ),
+            // hash-urile salvate fara prefix sunt SHA-256
+            (
+                file("f", Some("aa"), 0, 5),
+                file("f", Some("sha256:aa"), 100, 5),
+                true,
+            ),
+            (
+                file("f", Some("aa"), 0, 5),
+                file("f", Some("sha256:bb"), 0, 5),
+                false,
+            ),
+        ];
+        for (i, (a, b, expected)) in cases.iter().enumerate() {
+            assert_eq!(same_content(a, b, TOLERANCE), *expected, "case {}", i);
+            assert_eq!(
+                same_content(b, a, TOLERANCE),
+                *expected,
+                "case {} reversed",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn plan_files_classifies_each_case() {
+        let f = |content: &str, secs| Some(version("f.txt", content, secs));
+        use Classification as C;
+        use PathStatus as S;
+        let cases = [
+            // (nume, in stare, in locatiile 0 si 1, clasificare, statusuri, actiuni)
+            (
+                "unchanged",
+                f("aa", 1),
+                [f("aa", 1), f("aa", 1)],
+                C::Unchanged,
+                [S::Unchanged, S::Unchanged],
+                vec![],
+            ),
+            (
+                "changed on one side",
+                f("aa", 1),
+                [f("bb", 5), f("aa", 1)],
+                C::ChangedOnOneSide,
+                [S::Modified, S::Unchanged],
+                vec!["copy f.txt #0 -> [1]"],
+            ),
+            (
+                "created on one side",
+                None,
+                [None, f("aa", 1)],
+                C::ChangedOnOneSide,
+                [S::Absent, S::Created],
+                vec!["copy f.txt #1 -> [0]"],
+            ),
+            (
+                "same change on both sides",
+                f("aa", 1),
+                [f("bb", 5), f("bb", 6)],
+                C::ChangedOnOneSide,
+                [S::Modified, S::Modified],
+                vec!["record f.txt"],
+            ),
+            (
+                "changed on both sides",
+                f("aa", 1),
+                [f("bb", 5), f("cc", 9)],
+                C::ChangedOnBoth,
+                [S::Modified, S::Modified],
+                vec!["conflict f.txt", "copy f.txt #1 -> [0]"],
+            ),
+            (
+                "deleted on one side",
+                f("aa", 1),
+                [None, f("aa", 1)],
+                C::DeletedOnOneSide,
+                [S::Deleted, S::Unchanged],
+                vec!["delete f.txt [1]"],
+            ),
+            (
+                "deleted on one side, changed on the other",
+                f("aa", 1),
+                [None, f("bb", 5)],
+                C::DeletedAndChanged,
+                [S::Deleted, S::Modified],
+                vec!["copy f.txt #1 -> [0]"],
+            ),
+            (
+                "deleted on both sides",
+                f("aa", 1),
+                [None, None],
+                C::DeletedOnOneSide,
+                [S::Deleted, S::Deleted],
+                vec!["delete f.txt []"],
+            ),
+        ];
+
+        for (name, baseline, current, classification, statuses, actions) in cases {
+            let snapshot = snapshot(
+                current.map(|c| c.into_iter().collect()).into(),
+                vec![vec![], vec![]],
+            );
+            let sync_state = state(baseline.into_iter().collect(), vec![]);
+            let paths = BTreeSet::from(["f.txt".to_string()]);
+            let plans = plan_files(&snapshot, &sync_state, &paths);
+
+            assert_eq!(plans.len(), 1, "{}", name);
+            assert_eq!(plans[0].classification, classification, "{}", name);
+            assert_eq!(plans[0].statuses, statuses, "{}", name);
+            let described: Vec<String> = plans[0].actions.iter().map(describe).collect();
+            assert_eq!(described, actions, "{}", name);
+        }
+    }
+
+    #[test]
+    fn conflict_policy_per_pair() {
+        let base = version("f.txt", "aa", 1);
+        let f = |content: &str, secs| Some(version("f.txt", content, secs));
+        let cases = [
+            // (nume, versiunile din cele trei locatii, clasificare, actiuni)
+            (
+                "default policy",
+                [f("bb", 5), f("cc", 9), f("aa", 1)],
+                Classification::ChangedOnBoth,
+                vec!["conflict f.txt", "copy f.txt #1 -> [0, 2]"],
+            ),
+            // 0 si 2 au politica lor: castiga 0, desi 2 e mai nou
+            (
+                "pair policy",
+                [f("bb", 5), f("aa", 1), f("dd", 9)],
+                Classification::ChangedOnBoth,
+                vec!["conflict f.txt", "copy f.txt #0 -> [1, 2]"],
+            ),
+            // o stergere intr-o a treia locatie nu ascunde conflictul dintre celelalte doua
+            (
+                "pair policy, deleted elsewhere",
+                [f("bb", 5), None, f("dd", 9)],
+                Classification::DeletedAndChanged,
+                vec!["conflict f.txt", "copy f.txt #0 -> [1, 2]"],
+            ),
+            (
+                "modified once, deleted elsewhere",
+                [f("bb", 5), None, f("aa", 1)],
+                Classification::DeletedAndChanged,
+                vec!["copy f.txt #0 -> [1, 2]"],
+            ),
+        ];
+        for (name, files, classification, actions) in cases {
+            let snapshot = snapshot(
+                files.into_iter().map(|f| f.into_iter().collect()).collect(),
+                vec![vec![], vec![], vec![]],
+            );
+            let mut sync_state = state(vec![base.clone()], vec![]);
+            sync_state
+                .conflict_policies
+                .add_pair("2,0=prefer-location-0")
+                .unwrap();
+            let plan = plan(&snapshot, &sync_state);
+            assert_eq!(plan.files[0].classification, classification, "{}", name);
+            let described: Vec<String> = plan.files[0].actions.iter().map(describe).collect();
+            assert_eq!(described, actions, "{}", name);
+        }
+
+        let mut policies = ConflictPolicies::default();
+        for spec in [
+            "0,1",
+            "0=keep-both",
+            "1,1=keep-both",
+            "0,1=prefer-location-2",
+        ] {
+            assert!(policies.add_pair(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn plan_dirs_cases() {
+        let cases = [
+            // (nume, in stare, in locatiile 0 si 1, actiuni)
+            ("unchanged", vec!["d"], [vec!["d"], vec!["d"]], vec![]),
+            (
+                "created on one side",
+                vec![],
+                [vec!["d"], vec![]],
+                vec!["mkdir d [1]"],
+            ),
+            (
+                "deleted on one side",
+                vec!["d"],
+                [vec![], vec!["d"]],
+                vec!["rmdir d [1]"],
+            ),
+            (
+                "deleted everywhere",
+                vec!["d"],
+                [vec![], vec![]],
+                vec!["rmdir d []"],
+            ),
+        ];
+        for (name, baseline, current, actions) in cases {
+            let snapshot = snapshot(vec![vec![], vec![]], current.into());
+            let sync_state = state(vec![], baseline);
+            let described: Vec<String> = plan_dirs(&snapshot, &sync_state, &[])
+                .iter()
+                .map(describe)
+                .collect();
+            assert_eq!(described, actions, "{}", name);
+        }
+    }
+
+    // Un director sters intr-o parte, in care a aparut intre timp un fisier in cealalta
+    #[test]
+    fn dir_with_new_content_is_not_removed() {
+        let snapshot = snapshot(
+            vec![vec![], vec![version("d/new.txt", "aa", 5)]],
+            vec![vec![], vec!["d"]],
+        );
+        let sync_state = state(vec![], vec!["d"]);
+        let plan = plan(&snapshot, &sync_state);
+        let dirs: Vec<String> = plan.dirs.iter().map(describe).collect();
+        assert_eq!(dirs, vec!["mkdir d [0]"]);
+        let files: Vec<String> = plan.files[0].actions.iter().map(describe).collect();
+        assert_eq!(files, vec!["copy d/new.txt #1 -> [0]"]);
+    }
+
+    #[test]
+    fn file_in_one_location_dir_in_the_other_is_skipped() {
+        let snapshot = snapshot(
+            vec![
+                vec![version("x", "aa", 5)],
+                vec![version("x/inner.txt", "bb", 5)],
+            ],
+            vec![vec![], vec!["x"]],
+        );
+        let plan = plan(&snapshot, &state(vec![], vec![]));
+        assert!(plan.dirs.is_empty());
+        assert_eq!(plan.files.len(), 2);
+        assert!(plan.files.iter().all(|p| p.actions.is_empty()));
+    }
+
+    #[test]
+    fn detect_renames_cases() {
+        let renamed = vec![vec!["rename old.txt -> new.txt #0 -> [1]"]];
+        let copied = vec![vec!["copy new.txt #0 -> [1]"], vec!["delete old.txt [1]"]];
+        let cases = [
+            // (nume, continutul din locatia 0 sub noul nume, cel din stare, actiuni)
+            (
+                "same content",
+                version("new.txt", "aa", 5),
+                version("old.txt", "aa", 1),
+                renamed.clone(),
+            ),
+            (
+                "different content",
+                version("new.txt", "bb", 5),
+                version("old.txt", "aa", 1),
+                copied.clone(),
+            ),
+            (
+                "untagged hash in the state",
+                version("new.txt", "aa", 5),
+                file("old.txt", Some("aa"), 1, 10),
+                renamed.clone(),
+            ),
+            // alt algoritm: decid dimensiunea si timpul, pe care un rename le pastreaza
+            (
+                "other algorithm",
+                file("new.txt", Some("blake3:cc"), 1, 10),
+                version("old.txt", "aa", 1),
+                renamed.clone(),
+            ),
+            (
+                "other algorithm, other size",
+                file("new.txt", Some("blake3:cc"), 1, 11),
+                version("old.txt", "aa", 1),
+                copied.clone(),
+            ),
+        ];
+        for (name, created, base, actions) in cases {
+            let old = file("old.txt", base.hash.as_deref(), 1, 10);
+            let snapshot = snapshot(vec![vec![created], vec![old]], vec![vec![], vec![]]);
+            let sync_state = state(vec![base], vec![]);
+            let plan = plan(&snapshot, &sync_state);
+            let described: Vec<Vec<String>> = plan
+                .files
+                .iter()
+                .map(|p| p.actions.iter().map(describe).collect())
+                .collect();
+            assert_eq!(described, actions, "{}", name);
+        }
+    }
+
+    #[test]
+    fn common_algorithm_cases() {
+        use HashAlgorithm::*;
+        // locatia 0: folder, locatia 1: zip (CRC32 gata calculat), locatia 2: FTP cu XSHA256
+        let native = vec![vec![], vec![Crc32], vec![Sha256]];
+        let cases = [
+            // (nume, copii, algoritmul din stare, asteptat)
+            ("folder vs zip", vec![(0, Sha256), (1, Crc32)], None, Crc32),
+            (
+                "zip reads less",
+                vec![(0, Sha256), (1, Blake3)],
+                None,
+                Sha256,
+            ),
+            (
+                "two folders",
+                vec![(0, Sha256), (0, Blake3)],
+                Some(Blake3),
+                Blake3,
+            ),
+            (
+                "ftp side is free",
+                vec![(0, Sha256), (2, Xxh3)],
+                None,
+                Sha256,
+            ),
+            (
+                "state breaks the tie",
+                vec![(0, Blake3), (0, Xxh3)],
+                Some(Xxh3),
+                Xxh3,
+            ),
+        ];
+        for (name, copies, baseline, expected) in cases {
+            assert_eq!(
+                common_algorithm(&copies, &native, baseline),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use super::conflicts::ConflictRecord;
+use crate::errors::SyncError;
+use crate::locations::{normalize_hash, DirMetadata, FileMetadata};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Baza de date locala in care pastram ultima stare sincronizata,
+// ca sa putem detecta la repornire ce s-a schimbat cat timp nu am rulat.
+pub struct StateDb {
+    conn: Connection,
+}
+
+pub enum FileChange {
+    Upsert(FileMetadata),
+    Remove(String),
+}
+
+pub enum DirChange {
+    Upsert(DirMetadata),
+    Remove(String),
+}
+
+// Tot ce trebuie scris la urmatorul commit
+#[derive(Default)]
+pub struct PendingChanges {
+    pub files: Vec<FileChange>,
+    pub dirs: Vec<DirChange>,
+    pub tombstones: Vec<FileChange>,
+    pub conflicts: Vec<ConflictRecord>,
+    pub resolutions: Vec<(i64, String)>,
+}
+
+impl StateDb {
+    pub fn open(path: &Path) -> Result<Self, SyncError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                modified INTEGER NOT NULL,
+                size INTEGER NOT NULL DEFAULT 0,
+                hash TEXT
+            );
+            CREATE TABLE IF NOT EXISTS dirs (
+                path TEXT PRIMARY KEY,
+                modified INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS tombstones (
+                path TEXT PRIMARY KEY,
+                modified INTEGER NOT NULL,
+                size INTEGER NOT NULL DEFAULT 0,
+                hash TEXT
+            );
+            CREATE TABLE IF NOT EXISTS conflicts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                path TEXT NOT NULL,
+                detected_at INTEGER NOT NULL,
+                policy TEXT NOT NULL,
+                versions TEXT NOT NULL,
+                resolution TEXT
+            );",
+        )?;
+        // bazele create inainte sa tinem dimensiunea: raman cu 0 (necunoscuta)
+        for table in ["files", "tombstones"] {
+            let has_size = conn
+                .prepare(&format!(
+                    "SELECT 1 FROM pragma_table_info('{}') WHERE name = 'size'",
+                    table
+                ))?
+                .exists([])?;
+            if !has_size {
+                conn.execute_batch(&format!(
+                    "ALTER TABLE {} ADD COLUMN size INTEGER NOT NULL DEFAULT 0",
+                    table
+                ))?;
+            }
+        }
+        Ok(StateDb { conn })
+    }
+
+    pub fn load_files(&self) -> Result<HashMap<String, FileMetadata>, SyncError> {
+        self.load_file_table("files")
+    }
+
+    // Fisiere sterse care au ramas intr-o locatie read-only
+    pub fn load_tombstones(&self) -> Result<HashMap<String, FileMetadata>, SyncError> {
+        self.load_file_table("tombstones")
+    }
+
+    fn load_file_table(&self, table: &str) -> Result<HashMap<String, FileMetadata>, SyncError> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT path, modified, size, hash FROM {}", table))?;
+        let rows = stmt.query_map([], |row| {
+            Ok(FileMetadata {
+                path: row.get(0)?,
+                modified: from_nanos(row.get(1)?),
+                size: row.get::<_, i64>(2)? as u64,
+                hash: row.get::<_, Option<String>>(3)?.map(normalize_hash),
+            })
+        })?;
+
+        let mut files = HashMap::new();
+        for row in rows {
+            let meta = row?;
+            files.insert(meta.path.clone(), meta);
+        }
+        Ok(files)
+    }
+
+    pub fn load_dirs(&self) -> Result<HashMap<String, DirMetadata>, SyncError> {
+        let mut stmt = self.conn.prepare("SELECT path, modified FROM dirs")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(DirMetadata {
+                path: row.get(0)?,
+                modified: from_nanos(row.get(1)?),
+            })
+        })?;
+
+        let mut dirs = HashMap::new();
+        for row in rows {
+            let meta = row?;
+            dirs.insert(meta.path.clone(), meta);
+        }
+        Ok(dirs)
+    }
+
+    pub fn load_conflicts(&self, open_only: bool) -> Result<Vec<ConflictRecord>, SyncError> {
+        let sql = if open_only {
+            "SELECT id, path, detected_at, policy, versions, resolution FROM conflicts
+             WHERE resolution IS NULL ORDER BY id"
+        } else {
+            "SELECT id, path, detected_at, policy, versions, resolution FROM conflicts ORDER BY id"
+        };
+        let mut stmt = self.conn.prepare(sql)?;
+        let rows = stmt.query_map([], conflict_from_row)?;
+
+        let mut conflicts = Vec::new();
+        for row in rows {
+            conflicts.push(row?);
+        }
+        Ok(conflicts)
+    }
+
+    pub fn get_conflict(&self, id: i64) -> Result<Option<ConflictRecord>, SyncError> {
+        let conflict = self
+            .conn
+            .query_row(
+                "SELECT id, path, detected_at, policy, versions, resolution FROM conflicts
+                 WHERE id = ?1",
+                params![id],
+                conflict_from_row,
+            )
+            .optional()?;
+        Ok(conflict)
+    }
+
+    // Toate modificarile se scriu intr-o singura tranzactie: ori toate, ori niciuna
+    pub fn apply(&mut self, changes: &PendingChanges) -> Result<(), SyncError> {
+        let tx = self.conn.transaction()?;
+        for conflict in &changes.conflicts {
+            tx.execute(
+                "INSERT INTO conflicts (path, detected_at, policy, versions, resolution)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    conflict.path,
+                    to_nanos(conflict.detected_at),
+                    conflict.policy,
+                    conflict.versions_to_string(),
+                    conflict.resolution
+                ],
+            )?;
+        }
+        for (id, resolution) in &changes.resolutions {
+            tx.execute(
+                "UPDATE conflicts SET resolution = ?1 WHERE id = ?2",
+                params![resolution, id],
+            )?;
+        }
+        for (table, file_changes) in [
+            ("files", &changes.files),
+            ("tombstones", &changes.tombstones),
+        ] {
+            for change in file_changes {
+                match change {
+                    FileChange::Upsert(meta) => {
+                        tx.execute(
+                            &format!(
+                                "INSERT OR REPLACE INTO {} (path, modified, size, hash)
+                                 VALUES (?1, ?2, ?3, ?4)",
+                                table
+                            ),
+                            params![
+                                meta.path,
+                                to_nanos(meta.modified),
+                                meta.size as i64,
+                                meta.hash
+                            ],
+                        )?;
+                    }
+                    FileChange::Remove(path) => {
+                        tx.execute(
+                            &format!("DELETE FROM {} WHERE path = ?1", table),
+                            params![path],
+                        )?;
+                    }
+                }
+            }
+        }
+        for change in &changes.dirs {
+            match change {
+                DirChange::Upsert(meta) => {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO dirs (path, modified) VALUES (?1, ?2)",
+                        params![meta.path, to_nanos(meta.modified)],
+                    )?;
+                }
+                DirChange::Remove(path) => {
+                    tx.execute("DELETE FROM dirs WHERE path = ?1", params![path])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+fn conflict_from_row(row: &Row) -> rusqlite::Result<ConflictRecord> {
+    let versions: String = row.get(4)?;
+    Ok(ConflictRecord {
+        id: row.get(0)?,
+        path: row.get(1)?,
+        detected_at: from_nanos(row.get(2)?),
+        policy: row.get(3)?,
+        versions: ConflictRecord::versions_from_string(&versions),
+        resolution: row.get(5)?,
+    })
+}
+
+fn to_nanos(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_nanos() as i64,
+        Err(e) => -(e.duration().as_nanos() as i64),
+    }
+}
+
+fn from_nanos(nanos: i64) -> SystemTime {
+    if nanos >= 0 {
+        UNIX_EPOCH + Duration::from_nanos(nanos as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_nanos(nanos.unsigned_abs())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_root;
+
+    fn file(path: &str, hash: &str, nanos: u64) -> FileMetadata {
+        FileMetadata {
+            path: path.to_string(),
+            modified: UNIX_EPOCH + Duration::from_nanos(nanos),
+            size: 42,
+            hash: Some(hash.to_string()),
+        }
+    }
+
+    fn dir(path: &str) -> DirMetadata {
+        DirMetadata {
+            path: path.to_string(),
+            modified: UNIX_EPOCH + Duration::from_secs(7),
+        }
+    }
+
+    #[test]
+    fn changes_survive_reopening() {
+        let path = temp_root("state_round_trip").join("state.db");
+        let conflict = ConflictRecord {
+            id: None,
+            path: "c.txt".to_string(),
+            detected_at: UNIX_EPOCH + Duration::from_secs(100),
+            policy: "newest-wins".to_string(),
+            versions: vec![(0, Some("sha256:aa".to_string())), (1, None)],
+            resolution: None,
+        };
+        {
+            let mut db = StateDb::open(&path).unwrap();
+            db.apply(&PendingChanges {
+                files: vec![
+                    FileChange::Upsert(file("a.txt", "sha256:aa", 1_500_000_123)),
+                    FileChange::Upsert(file("docs/b.txt", "blake3:bb", 2)),
+                ],
+                dirs: vec![DirChange::Upsert(dir("docs"))],
+                tombstones: vec![FileChange::Upsert(file("gone.txt", "sha256:cc", 3))],
+                conflicts: vec![conflict.clone()],
+                resolutions: vec![],
+            })
+            .unwrap();
+            db.apply(&PendingChanges {
+                files: vec![FileChange::Remove("docs/b.txt".to_string())],
+                resolutions: vec![(1, "kept #0".to_string())],
+                ..Default::default()
+            })
+            .unwrap();
+        }
+
+        let db = StateDb::open(&path).unwrap();
+        let files = db.load_files().unwrap();
+        assert_eq!(files.len(), 1);
+        let a = &files["a.txt"];
+        assert_eq!(a.modified, UNIX_EPOCH + Duration::from_nanos(1_500_000_123));
+        assert_eq!(a.size, 42);
+        assert_eq!(a.hash.as_deref(), Some("sha256:aa"));
+        assert_eq!(
+            db.load_dirs().unwrap()["docs"].modified,
+            dir("docs").modified
+        );
+        assert_eq!(
+            db.load_tombstones().unwrap()["gone.txt"].hash.as_deref(),
+            Some("sha256:cc")
+        );
+
+        assert!(db.load_conflicts(true).unwrap().is_empty());
+        let saved = db.get_conflict(1).unwrap().unwrap();
+        assert_eq!(saved.path, conflict.path);
+        assert_eq!(saved.detected_at, conflict.detected_at);
+        assert_eq!(saved.versions, conflict.versions);
+        assert_eq!(saved.resolution.as_deref(), Some("kept #0"));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    // O eroare la jumatea commit-ului nu lasa in baza doar o parte din schimbari
+    #[test]
+    fn failed_apply_writes_nothing() {
+        let path = temp_root("state_transaction").join("state.db");
+        let mut db = StateDb::open(&path).unwrap();
+        db.conn.execute_batch("DROP TABLE dirs").unwrap();
+
+        let result = db.apply(&PendingChanges {
+            files: vec![FileChange::Upsert(file("a.txt", "sha256:aa", 1))],
+            dirs: vec![DirChange::Upsert(dir("docs"))],
+            ..Default::default()
+        });
+        assert!(result.is_err());
+        assert!(db.load_files().unwrap().is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    // Bazele vechi: fara coloana `size`, cu hash-uri SHA-256 fara prefix
+    #[test]
+    fn old_databases_are_upgraded() {
+        let path = temp_root("state_upgrade").join("state.db");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE files (path TEXT PRIMARY KEY, modified INTEGER NOT NULL, hash TEXT);
+                 INSERT INTO files VALUES ('a.txt', 5, 'abcd');",
+            )
+            .unwrap();
+        }
+
+        let db = StateDb::open(&path).unwrap();
+        let files = db.load_files().unwrap();
+        assert_eq!(files["a.txt"].size, 0);
+        assert_eq!(files["a.txt"].hash.as_deref(), Some("sha256:abcd"));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
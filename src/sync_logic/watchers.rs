@@ -1,31 +1,124 @@
+use crate::errors::{ErrorContext, SyncError};
+use crate::locations::Location;
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::PathBuf;
 use std::sync::mpsc::{channel, Receiver};
-use std::thread;
-use std::time::Duration;
 
-pub fn watch_folder(
-    path: &std::path::Path,
-) -> Result<Receiver<notify::Result<Event>>, Box<dyn std::error::Error>> {
+// Un eveniment de la watcher, impreuna cu locatia din care vine
+pub struct LocationEvent {
+    pub location: usize,
+    pub root: PathBuf,
+    pub event: notify::Result<Event>,
+}
+
+// Cate un watcher pentru fiecare locatie care poate fi urmarita; toate scriu in
+// acelasi canal. Watcher-ele traiesc cat timp traieste structura.
+pub struct LocationWatchers {
+    // tinute doar ca sa nu se opreasca
+    _watchers: Vec<RecommendedWatcher>,
+    pub events: Receiver<LocationEvent>,
+}
+
+pub fn watch_locations(locations: &[Box<dyn Location>]) -> Result<LocationWatchers, SyncError> {
     let (tx, rx) = channel();
+    let mut watchers = Vec::new();
+
+    for (i, loc) in locations.iter().enumerate() {
+        let Some(root) = loc.capabilities().watch_root else {
+            continue;
+        };
+        // evenimentele vin cu cai absolute; cu o radacina relativa (`folder:a`)
+        // `strip_prefix` n-ar mai gasi nimic
+        let root = root.canonicalize().unwrap_or(root);
+        let tx = tx.clone();
+        let event_root = root.clone();
+        let mut watcher = RecommendedWatcher::new(
+            move |event: notify::Result<Event>| {
+                // daca bucla principala s-a oprit nu mai are cine sa primeasca
+                let _ = tx.send(LocationEvent {
+                    location: i,
+                    root: event_root.clone(),
+                    event,
+                });
+            },
+            notify::Config::default(),
+        )
+        .map_err(|e| watch_error(e, &root.to_string_lossy()))?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .map_err(|e| watch_error(e, &root.to_string_lossy()))?;
+        println!("Watching location #{} ({})", i, root.display());
+        watchers.push(watcher);
+    }
+
+    Ok(LocationWatchers {
+        _watchers: watchers,
+        events: rx,
+    })
+}
+
+fn watch_error(e: notify::Error, root: &str) -> SyncError {
+    let not_found = matches!(e.kind, notify::ErrorKind::PathNotFound);
+    let context = ErrorContext::new(e.to_string()).with_source(e).into();
+    let error = if not_found {
+        SyncError::NotFound(context)
+    } else {
+        SyncError::Io(context)
+    };
+    error.during("watch").at(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locations::{parse_location, HashAlgorithm};
+    use crate::sync_logic::events::{normalize, LocalChange};
+    use crate::test_util::temp_root;
+    use std::fs;
+    use std::path::{Component, Path};
+    use std::time::{Duration, Instant};
+
+    // Aceeasi cale, scrisa relativ la directorul curent
+    fn relative_to_cwd(path: &Path) -> PathBuf {
+        let cwd = std::env::current_dir().unwrap();
+        let mut relative = PathBuf::new();
+        for _ in cwd
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+        {
+            relative.push("..");
+        }
+        relative.join(path.strip_prefix("/").unwrap())
+    }
+
+    #[test]
+    fn relative_root_still_reports_changes() {
+        let root = temp_root("watchers_relative");
+        let relative = relative_to_cwd(&root);
+        assert!(relative.is_relative());
+        let locations = vec![parse_location(
+            &format!("folder:{}?no-hash-cache", relative.display()),
+            HashAlgorithm::default(),
+        )
+        .unwrap()];
+        let watchers = watch_locations(&locations).unwrap();
 
-    // cream un watcher nou
-    let mut watcher = RecommendedWatcher::new(
-        move |res: notify::Result<Event>| {
-            tx.send(res).expect("Failed to send event over channel");
-        },
-        // il facem non-blocant
-        notify::Config::default(),
-    )?;
-
-    watcher.watch(path, RecursiveMode::Recursive)?;
-
-    //il punem intr-un thread
-    thread::spawn(move || {
-        // "park" the thread: keep it alive
-        loop {
-            thread::sleep(Duration::from_secs(3600));
+        fs::write(root.join("x.txt"), "hello").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changes = Vec::new();
+        while changes.is_empty() && Instant::now() < deadline {
+            if let Ok(LocationEvent { root, event, .. }) =
+                watchers.events.recv_timeout(Duration::from_millis(100))
+            {
+                changes.extend(normalize(&root, &event.unwrap()));
+            }
         }
-    });
+        assert!(matches!(
+            changes.first(),
+            Some(LocalChange::Created { path, .. } | LocalChange::Modified { path }) if path == "x.txt"
+        ));
 
-    Ok(rx)
+        drop(watchers);
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,14 @@
+// Ajutoare comune pentru testele unitare
+
+use std::fs;
+use std::path::PathBuf;
+
+// Un director gol si propriu testului `name` (care trebuie sa fie unic intre teste),
+// sters si recreat la fiecare rulare
+pub fn temp_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("advanced_rsync_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    // pe macOS /tmp e un link, iar evenimentele vin cu calea reala
+    root.canonicalize().unwrap()
+}
@@ -0,0 +1,100 @@
+// Test de integrare contra unui sshd local (OpenSSH), ignorat implicit. Cere SFTP_TEST_USER;
+// optional SFTP_TEST_PORT (implicit 22), SFTP_TEST_PASS si SFTP_TEST_KEY. Fara parola/cheie
+// se folosesc ssh-agent si cheile din ~/.ssh. Cheia serverului trebuie sa fie deja in
+// ~/.ssh/known_hosts (ex. `ssh-keyscan -p <port> 127.0.0.1 >> ~/.ssh/known_hosts`).
+//
+//     SFTP_TEST_USER=$USER cargo test --test sftp -- --ignored
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn sftp_location(remote: &Path) -> String {
+    let user = std::env::var("SFTP_TEST_USER").expect("SFTP_TEST_USER must be set");
+    let port = std::env::var("SFTP_TEST_PORT").unwrap_or_else(|_| "22".to_string());
+    let cred = match std::env::var("SFTP_TEST_PASS") {
+        Ok(pass) => format!("{}:{}", user, pass),
+        Err(_) => user,
+    };
+    let key = match std::env::var("SFTP_TEST_KEY") {
+        Ok(key) => format!("?key={}", key),
+        Err(_) => String::new(),
+    };
+    // `//` -> cale absoluta pe server
+    format!(
+        "sftp:{}@127.0.0.1:{}/{}{}",
+        cred,
+        port,
+        remote.display(),
+        key
+    )
+}
+
+fn sync_once(base: &Path, local: &Path, remote: &str) {
+    let output = Command::new(env!("CARGO_BIN_EXE_untitled"))
+        .arg("--once")
+        .arg("--state")
+        .arg(base.join("state.db"))
+        // cache-ul de hash-uri al testului, nu cel al utilizatorului
+        .env("XDG_CACHE_HOME", base.join("cache"))
+        .arg(format!("folder:{}", local.display()))
+        .arg(remote)
+        .output()
+        .expect("failed to run sync");
+    assert!(
+        output.status.success(),
+        "sync failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+#[test]
+#[ignore = "needs a local sshd and SFTP_TEST_USER"]
+fn sftp_round_trip() {
+    let base: PathBuf =
+        std::env::temp_dir().join(format!("advanced_rsync_sftp_{}", std::process::id()));
+    let local = base.join("local");
+    let remote = base.join("remote");
+
+    let location = sftp_location(&remote);
+
+    fs::create_dir_all(local.join("docs/old")).unwrap();
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(local.join("a.txt"), "alpha").unwrap();
+    fs::write(local.join("docs/b.txt"), "beta").unwrap();
+    fs::write(local.join("docs/old/c.txt"), "gamma").unwrap();
+
+    // fisierele si directoarele locale ajung pe server
+    sync_once(&base, &local, &location);
+    assert_eq!(fs::read_to_string(remote.join("a.txt")).unwrap(), "alpha");
+    assert_eq!(
+        fs::read_to_string(remote.join("docs/b.txt")).unwrap(),
+        "beta"
+    );
+    assert_eq!(
+        fs::read_to_string(remote.join("docs/old/c.txt")).unwrap(),
+        "gamma"
+    );
+
+    // modificari facute pe server se intorc local
+    fs::remove_file(remote.join("a.txt")).unwrap();
+    fs::remove_dir_all(remote.join("docs/old")).unwrap();
+    fs::write(remote.join("docs/b.txt"), "beta, edited on the server").unwrap();
+    fs::write(remote.join("d.txt"), "delta").unwrap();
+
+    sync_once(&base, &local, &location);
+    assert!(!local.join("a.txt").exists());
+    assert!(!local.join("docs/old").exists());
+    assert_eq!(
+        fs::read_to_string(local.join("docs/b.txt")).unwrap(),
+        "beta, edited on the server"
+    );
+    assert_eq!(fs::read_to_string(local.join("d.txt")).unwrap(), "delta");
+
+    // si stergerile locale se propaga pe server
+    fs::remove_file(local.join("d.txt")).unwrap();
+    sync_once(&base, &local, &location);
+    assert!(!remote.join("d.txt").exists());
+
+    fs::remove_dir_all(&base).unwrap();
+}